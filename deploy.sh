@@ -99,7 +99,8 @@ Options:
                     Comma-separated permissions for VM service account
                     By default, VM has no service account (no GCP API access)
                     Options: admin, compute, gke, storage, network, bigquery,
-                             bq, iam, logging, pubsub, sql, secrets, dns, run, functions
+                             bq, iam, logging, pubsub, sql, secrets, dns, run,
+                             functions, vertex, or an IAM role (roles/...)
                     Example: --permissions compute,gke,storage
   --ip ADDRESS      Additional IP address to whitelist for SSH access
                     By default, only your current public IP is allowed
@@ -126,7 +127,6 @@ Environment Variables:
   GITHUB_TOKEN_FILE Path to file containing GitHub PAT
   ZONE              GCP zone (default: us-central1-a)
   MACHINE_TYPE      VM machine type (default: n2-standard-4)
-  CLUSTER_NAME      Optional GKE cluster name for kubectl config
   SKIP_DELETION     Set skip_deletion label (default: yes)
   PERMISSIONS       Comma-separated permissions for VM service account
   ADDITIONAL_IP     Additional IP to whitelist for SSH access (same as --ip)
@@ -214,6 +214,47 @@ get_ssh_username() {
     get_owner | tr '_' '-'
 }
 
+# Convert comma-separated permissions to a Terraform list of IAM roles.
+# Mirrors the shortcuts in src/permissions.rs; role IDs pass through, and
+# project custom roles must belong to project $2.
+# Scoped grants (storage:BUCKET) are only supported by ca.
+permission_roles_tf() {
+    local roles=() perm
+    for perm in $(echo "$1" | tr ',' ' '); do
+        case "$perm" in
+            admin) roles+=(roles/compute.admin roles/container.admin roles/storage.admin roles/iam.serviceAccountUser) ;;
+            compute) roles+=(roles/compute.admin) ;;
+            gke|container) roles+=(roles/container.admin) ;;
+            storage|gcs) roles+=(roles/storage.admin) ;;
+            network) roles+=(roles/compute.networkAdmin) ;;
+            bigquery|bq) roles+=(roles/bigquery.admin) ;;
+            iam) roles+=(roles/iam.serviceAccountUser) ;;
+            logging) roles+=(roles/logging.admin) ;;
+            pubsub) roles+=(roles/pubsub.admin) ;;
+            sql|cloudsql) roles+=(roles/cloudsql.admin) ;;
+            secrets|secretmanager) roles+=(roles/secretmanager.admin) ;;
+            dns) roles+=(roles/dns.admin) ;;
+            run|cloudrun) roles+=(roles/run.admin) ;;
+            functions) roles+=(roles/cloudfunctions.admin) ;;
+            vertex) roles+=(roles/aiplatform.user) ;;
+            roles/*|projects/"$2"/roles/*|organizations/*/roles/*) roles+=("$perm") ;;
+            projects/*/roles/*)
+                log "❌ ERROR: Custom role '$perm' is not in project $2" >&2
+                exit 1
+                ;;
+            *)
+                log "❌ ERROR: Unknown permission '$perm' (run: ca permissions list)" >&2
+                exit 1
+                ;;
+        esac
+    done
+    if [ ${#roles[@]} -eq 0 ]; then
+        echo "[]"
+    else
+        printf '["%s"]' "$(printf '%s", "' "${roles[@]}" | sed 's/", "$//')"
+    fi
+}
+
 # Get VM name based on $USER
 get_vm_name() {
     local vm_name
@@ -394,9 +435,7 @@ handle_vm_command() {
 
             # Configuration
             local region="${REGION:-us-central1}"
-            local cluster_zone="${CLUSTER_ZONE:-$zone}"
             local machine_type="${MACHINE_TYPE:-n2-standard-4}"
-            local cluster_name="${CLUSTER_NAME:-}"
             local skip_deletion="${SKIP_DELETION:-yes}"
             local permissions="${PERMISSIONS:-}"
             local additional_ip="${ADDITIONAL_IP:-}"
@@ -406,11 +445,7 @@ handle_vm_command() {
 
             # Convert comma-separated permissions to Terraform list format
             local permissions_tf
-            if [ -n "$permissions" ]; then
-                permissions_tf=$(echo "$permissions" | sed 's/,/", "/g' | sed 's/^/["/' | sed 's/$/"]/')
-            else
-                permissions_tf="[]"
-            fi
+            permissions_tf=$(permission_roles_tf "$permissions" "$project_id") || exit 1
 
             # Get current public IPs for firewall rules (both IPv4 and IPv6)
             log "Detecting your public IP addresses..."
@@ -467,12 +502,10 @@ project_id     = "$project_id"
 region         = "$region"
 zone           = "$zone"
 machine_type   = "$machine_type"
-cluster_name   = "$cluster_name"
-cluster_zone   = "$cluster_zone"
 vm_name        = "$vm_name"
 owner          = "$owner"
 skip_deletion  = "$skip_deletion"
-permissions    = $permissions_tf
+iam_roles      = $permissions_tf
 allowed_ips    = $allowed_ips_tf
 ssh_username   = "$ssh_username"
 ssh_public_key = "$ssh_public_key"
@@ -618,9 +651,7 @@ log "Using GCP project: $PROJECT_ID"
 # Configuration
 ZONE="${ZONE:-us-central1-a}"
 REGION="${REGION:-us-central1}"
-CLUSTER_ZONE="${CLUSTER_ZONE:-$ZONE}"
 MACHINE_TYPE="${MACHINE_TYPE:-n2-standard-4}"
-CLUSTER_NAME="${CLUSTER_NAME:-}"
 
 # Derive VM name and owner
 VM_NAME=$(get_vm_name)
@@ -675,12 +706,7 @@ if [ "$CREATE_VM" = "yes" ]; then
     log "Creating terraform.tfvars..."
 
     # Convert comma-separated permissions to Terraform list format
-    if [ -n "$PERMISSIONS" ]; then
-        # Convert "compute,gke,storage" to ["compute", "gke", "storage"]
-        PERMISSIONS_TF=$(echo "$PERMISSIONS" | sed 's/,/", "/g' | sed 's/^/["/' | sed 's/$/"]/')
-    else
-        PERMISSIONS_TF="[]"
-    fi
+    PERMISSIONS_TF=$(permission_roles_tf "$PERMISSIONS" "$PROJECT_ID") || exit 1
 
     # Get current public IPs for firewall rules (both IPv4 and IPv6)
     # GCP VMs are typically IPv4-only, so IPv4 is most important
@@ -739,12 +765,10 @@ project_id     = "$PROJECT_ID"
 region         = "$REGION"
 zone           = "$ZONE"
 machine_type   = "$MACHINE_TYPE"
-cluster_name   = "${CLUSTER_NAME:-}"
-cluster_zone   = "$CLUSTER_ZONE"
 vm_name        = "$VM_NAME"
 owner          = "$OWNER"
 skip_deletion  = "$SKIP_DELETION"
-permissions    = $PERMISSIONS_TF
+iam_roles      = $PERMISSIONS_TF
 allowed_ips    = $ALLOWED_IPS_TF
 ssh_username   = "$SSH_USERNAME"
 ssh_public_key = "$SSH_PUBLIC_KEY"
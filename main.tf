@@ -1,5 +1,5 @@
 terraform {
-  required_version = ">= 1.0"
+  required_version = ">= 1.2"
   required_providers {
     google = {
       source  = "hashicorp/google"
@@ -15,11 +15,28 @@ provider "google" {
 }
 
 # =============================================================================
-# NETWORKING - Dedicated VPC per user for isolation
+# NETWORKING - Dedicated VPC per user for isolation, or an existing VPC
 # =============================================================================
 
+locals {
+  is_iap = var.network_mode == "iap"
+
+  # A DNS record needs a stable address to point at
+  static_ip = var.static_ip || var.dns_zone != ""
+
+  # Only the ports the on-VM egress proxy forwards to
+  restricted_egress = var.egress_mode == "restricted"
+
+  # Attach to an existing network instead of creating one
+  use_existing_network = var.network != ""
+
+  # Google's IAP TCP forwarding range
+  iap_source_ranges = ["35.235.240.0/20"]
+}
+
 # VPC Network - dedicated to this user's cloud-agent
 resource "google_compute_network" "cloud_agent" {
+  count                   = local.use_existing_network ? 0 : 1
   name                    = "${var.vm_name}-network"
   auto_create_subnetworks = false
   description             = "Dedicated network for ${var.vm_name} cloud-agent VM"
@@ -27,88 +44,166 @@ resource "google_compute_network" "cloud_agent" {
 
 # Subnet for the cloud-agent VM
 resource "google_compute_subnetwork" "cloud_agent" {
+  count         = local.use_existing_network ? 0 : 1
   name          = "${var.vm_name}-subnet"
   ip_cidr_range = "10.0.0.0/24"
   region        = var.region
-  network       = google_compute_network.cloud_agent.id
+  network       = google_compute_network.cloud_agent[0].id
   description   = "Subnet for ${var.vm_name} cloud-agent VM"
 }
 
-# Firewall rule - Allow SSH from specified IPs only
+# Keep the dedicated network of VMs created before it became optional
+moved {
+  from = google_compute_network.cloud_agent
+  to   = google_compute_network.cloud_agent[0]
+}
+
+moved {
+  from = google_compute_subnetwork.cloud_agent
+  to   = google_compute_subnetwork.cloud_agent[0]
+}
+
+# Existing network and subnet (--network/--subnet)
+data "google_compute_network" "existing" {
+  count = local.use_existing_network ? 1 : 0
+  name  = var.network
+}
+
+data "google_compute_subnetwork" "existing" {
+  count  = local.use_existing_network && var.subnet != "" ? 1 : 0
+  name   = var.subnet
+  region = var.region
+}
+
+locals {
+  network_id   = local.use_existing_network ? data.google_compute_network.existing[0].id : google_compute_network.cloud_agent[0].id
+  network_name = local.use_existing_network ? data.google_compute_network.existing[0].name : google_compute_network.cloud_agent[0].name
+
+  # Auto-mode networks can be used without naming a subnet
+  subnetwork_id   = local.use_existing_network ? try(data.google_compute_subnetwork.existing[0].id, null) : google_compute_subnetwork.cloud_agent[0].id
+  subnetwork_name = local.use_existing_network ? try(data.google_compute_subnetwork.existing[0].name, null) : google_compute_subnetwork.cloud_agent[0].name
+}
+
+# Firewall rule - Allow SSH from specified IPs only (or from IAP)
 resource "google_compute_firewall" "cloud_agent_ssh" {
   name    = "${var.vm_name}-allow-ssh"
-  network = google_compute_network.cloud_agent.id
+  network = local.network_id
+
+  # Only this VM, which matters on a shared network
+  target_tags = [var.vm_name]
 
   allow {
     protocol = "tcp"
     ports    = ["22"]
   }
 
-  # Only allow connections from specified IPs
-  source_ranges = var.allowed_ips
-  description   = "Allow SSH access to ${var.vm_name} from whitelisted IPs"
+  # Only allow connections from specified IPs, or only through IAP
+  source_ranges = local.is_iap ? local.iap_source_ranges : var.allowed_ips
+  description   = local.is_iap ? "Allow SSH to ${var.vm_name} through IAP TCP forwarding" : "Allow SSH access to ${var.vm_name} from whitelisted IPs"
+
+  lifecycle {
+    precondition {
+      condition     = local.is_iap || length(var.allowed_ips) > 0
+      error_message = "allowed_ips cannot be empty. At least one IP address must be specified for SSH access (or use network_mode = \"iap\")."
+    }
+  }
 }
 
-# Firewall rule - Allow all egress (for package downloads, git, etc.)
+# Firewall rule - Allow egress (for package downloads, git, etc.). With
+# restricted egress only the ports the on-VM proxy forwards to are allowed.
 resource "google_compute_firewall" "cloud_agent_egress" {
-  name      = "${var.vm_name}-allow-egress"
-  network   = google_compute_network.cloud_agent.id
-  direction = "EGRESS"
+  name        = "${var.vm_name}-allow-egress"
+  network     = local.network_id
+  direction   = "EGRESS"
+  target_tags = [var.vm_name]
 
   allow {
-    protocol = "all"
+    protocol = local.restricted_egress ? "tcp" : "all"
+    ports    = local.restricted_egress ? ["22", "80", "443"] : null
   }
 
   destination_ranges = ["0.0.0.0/0"]
-  description        = "Allow all outbound traffic from ${var.vm_name}"
+  description        = local.restricted_egress ? "Allow proxied outbound traffic from ${var.vm_name}" : "Allow all outbound traffic from ${var.vm_name}"
 }
 
-# =============================================================================
-# IAM - Service account and permissions (optional)
-# =============================================================================
+# Firewall rule - Deny other egress with restricted egress. The metadata
+# server (DNS, credentials) is not subject to firewall rules.
+resource "google_compute_firewall" "cloud_agent_egress_deny" {
+  count       = local.restricted_egress ? 1 : 0
+  name        = "${var.vm_name}-deny-egress"
+  network     = local.network_id
+  direction   = "EGRESS"
+  priority    = 65000
+  target_tags = [var.vm_name]
+
+  deny {
+    protocol = "all"
+  }
 
-# Local variables for permission mapping
+  destination_ranges = ["0.0.0.0/0"]
+  description        = "Deny other outbound traffic from ${var.vm_name}"
+}
+
+# Cloud NAT - outbound internet for VMs without an external IP (IAP mode).
+# Existing networks are expected to provide their own egress.
 locals {
-  # Map of permission shortcuts to GCP IAM roles
-  permission_roles = {
-    compute  = "roles/compute.admin"
-    gke      = "roles/container.admin"
-    storage  = "roles/storage.admin"
-    network  = "roles/compute.networkAdmin"
-    bigquery = "roles/bigquery.admin"
-    bq       = "roles/bigquery.admin"
-    iam      = "roles/iam.serviceAccountUser"
-    logging  = "roles/logging.admin"
-    pubsub   = "roles/pubsub.admin"
-    sql      = "roles/cloudsql.admin"
-    secrets  = "roles/secretmanager.admin"
-    dns      = "roles/dns.admin"
-    run      = "roles/run.admin"
-    functions = "roles/cloudfunctions.admin"
-  }
+  create_nat = local.is_iap && !local.use_existing_network
+}
 
-  # Admin permission grants all common admin roles
-  admin_roles = [
-    "roles/compute.admin",
-    "roles/container.admin",
-    "roles/storage.admin",
-    "roles/iam.serviceAccountUser"
-  ]
+resource "google_compute_router" "cloud_agent" {
+  count   = local.create_nat ? 1 : 0
+  name    = "${var.vm_name}-router"
+  region  = var.region
+  network = local.network_id
+}
 
-  # Determine if we need a service account (permissions is not empty)
-  has_permissions = length(var.permissions) > 0
+resource "google_compute_router_nat" "cloud_agent" {
+  count                              = local.create_nat ? 1 : 0
+  name                               = "${var.vm_name}-nat"
+  router                             = google_compute_router.cloud_agent[0].name
+  region                             = var.region
+  nat_ip_allocate_option             = "AUTO_ONLY"
+  source_subnetwork_ip_ranges_to_nat = "ALL_SUBNETWORKS_ALL_IP_RANGES"
+}
 
-  # Calculate the actual roles to grant
-  # If "admin" is in the list, use admin_roles; otherwise map each permission to its role
-  is_admin = contains(var.permissions, "admin")
+# Static external IP - keeps the address (and SSH known_hosts entries)
+# stable across stop/start
+resource "google_compute_address" "cloud_agent" {
+  count  = local.static_ip ? 1 : 0
+  name   = "${var.vm_name}-ip"
+  region = var.region
+
+  lifecycle {
+    precondition {
+      condition     = !local.is_iap
+      error_message = "static_ip needs network_mode = \"public\" (IAP VMs have no external IP)."
+    }
+  }
+}
 
-  requested_roles = local.is_admin ? local.admin_roles : [
-    for perm in var.permissions : local.permission_roles[perm]
-    if contains(keys(local.permission_roles), perm)
-  ]
+# DNS record <vm_name>.<zone domain> for the static IP (optional)
+data "google_dns_managed_zone" "cloud_agent" {
+  count = var.dns_zone != "" ? 1 : 0
+  name  = var.dns_zone
+}
 
-  # Deduplicate roles (in case of aliases like bq/bigquery)
-  unique_roles = distinct(local.requested_roles)
+resource "google_dns_record_set" "cloud_agent" {
+  count        = var.dns_zone != "" ? 1 : 0
+  managed_zone = var.dns_zone
+  name         = "${var.vm_name}.${data.google_dns_managed_zone.cloud_agent[0].dns_name}"
+  type         = "A"
+  ttl          = 300
+  rrdatas      = [google_compute_address.cloud_agent[0].address]
+}
+
+# =============================================================================
+# IAM - Service account and permissions (optional)
+# =============================================================================
+
+# Roles are resolved from --permissions by ca (src/permissions.rs)
+locals {
+  # Only create a service account when something is granted
+  has_permissions = length(var.iam_roles) + length(var.bucket_iam) + length(var.secret_iam) > 0
 }
 
 # Service account for cloud-agent VM (only created when permissions are specified)
@@ -119,14 +214,31 @@ resource "google_service_account" "cloud_agent" {
   description  = "Service account for ${var.vm_name} VM to manage GCP resources"
 }
 
-# Grant IAM roles based on permissions variable
+# Grant project-wide IAM roles
 resource "google_project_iam_member" "cloud_agent_roles" {
-  for_each = local.has_permissions ? toset(local.unique_roles) : []
+  for_each = toset(var.iam_roles)
   project  = var.project_id
   role     = each.value
   member   = "serviceAccount:${google_service_account.cloud_agent[0].email}"
 }
 
+# Grant roles on individual buckets (storage:BUCKET)
+resource "google_storage_bucket_iam_member" "cloud_agent_buckets" {
+  for_each = { for grant in var.bucket_iam : "${grant.bucket}/${grant.role}" => grant }
+  bucket   = each.value.bucket
+  role     = each.value.role
+  member   = "serviceAccount:${google_service_account.cloud_agent[0].email}"
+}
+
+# Grant roles on individual secrets (secrets:SECRET)
+resource "google_secret_manager_secret_iam_member" "cloud_agent_secrets" {
+  for_each  = { for grant in var.secret_iam : "${grant.secret}/${grant.role}" => grant }
+  project   = var.project_id
+  secret_id = each.value.secret
+  role      = each.value.role
+  member    = "serviceAccount:${google_service_account.cloud_agent[0].email}"
+}
+
 # =============================================================================
 # COMPUTE - Cloud Agent VM instance
 # =============================================================================
@@ -144,12 +256,17 @@ resource "google_compute_instance" "cloud_agent" {
     }
   }
 
-  # Use the dedicated VPC network and subnet
+  # Attach to the dedicated VPC network and subnet, or the existing ones
   network_interface {
-    network    = google_compute_network.cloud_agent.id
-    subnetwork = google_compute_subnetwork.cloud_agent.id
-    access_config {
-      # Ephemeral external IP for SSH access and outbound internet
+    network    = local.network_id
+    subnetwork = local.subnetwork_id
+    # External IP for SSH access and outbound internet (none with IAP):
+    # ephemeral, or the reserved address with static_ip
+    dynamic "access_config" {
+      for_each = local.is_iap ? [] : [1]
+      content {
+        nat_ip = local.static_ip ? google_compute_address.cloud_agent[0].address : null
+      }
     }
   }
 
@@ -164,10 +281,10 @@ resource "google_compute_instance" "cloud_agent" {
 
   metadata_startup_script = templatefile("${path.module}/startup-script.sh", {
     project_id     = var.project_id
-    cluster_name   = var.cluster_name
-    cluster_zone   = var.cluster_zone
+    zone           = var.zone
     ssh_username   = var.ssh_username
     ssh_public_key = var.ssh_public_key
+    egress_script  = file("${path.module}/src/scripts/egress-proxy.sh")
   })
 
   # SSH Security: Block all project-level and OS Login SSH access
@@ -178,6 +295,10 @@ resource "google_compute_instance" "cloud_agent" {
     # Add our SSH key to instance metadata so guest agent configures it
     # Format: username:ssh-key-content
     ssh-keys               = var.ssh_username != "" && var.ssh_public_key != "" ? "${var.ssh_username}:${var.ssh_public_key}" : null
+    # Egress mode and allowlist, applied by the startup script on every boot
+    # and followed by the on-VM egress service (ca deploy updates them)
+    cloud-agent-egress           = var.egress_mode
+    cloud-agent-egress-allowlist = join(" ", var.egress_allowlist)
   }
 
   tags = ["cloud-agent", var.vm_name]
@@ -193,13 +314,19 @@ resource "google_compute_instance" "cloud_agent" {
     google_compute_network.cloud_agent,
     google_compute_subnetwork.cloud_agent,
     google_compute_firewall.cloud_agent_ssh,
-    google_compute_firewall.cloud_agent_egress
+    google_compute_firewall.cloud_agent_egress,
+    google_compute_router_nat.cloud_agent
   ]
 }
 
 output "cloud_agent_ip" {
-  value       = google_compute_instance.cloud_agent.network_interface[0].access_config[0].nat_ip
-  description = "External IP of cloud-agent VM"
+  value       = local.is_iap ? null : local.static_ip ? google_compute_address.cloud_agent[0].address : google_compute_instance.cloud_agent.network_interface[0].access_config[0].nat_ip
+  description = "External IP of cloud-agent VM (null with IAP)"
+}
+
+output "cloud_agent_dns_name" {
+  value       = var.dns_zone != "" ? trimsuffix(google_dns_record_set.cloud_agent[0].name, ".") : null
+  description = "DNS name of cloud-agent VM (null without dns_zone)"
 }
 
 output "cloud_agent_internal_ip" {
@@ -208,7 +335,7 @@ output "cloud_agent_internal_ip" {
 }
 
 output "ssh_command" {
-  value       = "gcloud compute ssh ${var.vm_name} --zone=${var.zone}"
+  value       = "gcloud compute ssh ${var.vm_name} --zone=${var.zone}${local.is_iap ? " --tunnel-through-iap" : ""}"
   description = "Command to SSH into cloud-agent VM"
 }
 
@@ -223,11 +350,11 @@ output "service_account_email" {
 }
 
 output "network_name" {
-  value       = google_compute_network.cloud_agent.name
+  value       = local.network_name
   description = "Name of the dedicated VPC network for this cloud-agent"
 }
 
 output "subnet_name" {
-  value       = google_compute_subnetwork.cloud_agent.name
+  value       = local.subnetwork_name
   description = "Name of the subnet for this cloud-agent"
 }
@@ -0,0 +1,127 @@
+//! Aider agent implementation
+//!
+//! Aider is a Python tool installed with pipx rather than npm. It has no
+//! login: provider API keys come from `~/.aider.conf.yml`, or from the one
+//! provider variable chosen with `--aider-provider`.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::agents::{self, Agent, Autonomy};
+use crate::utils;
+
+/// Model provider whose API key deploy stores on the VM for Aider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AiderProvider {
+    Anthropic,
+    Openai,
+    Gemini,
+}
+
+impl AiderProvider {
+    /// Variable holding the provider's API key
+    pub fn key_env(self) -> &'static str {
+        match self {
+            AiderProvider::Anthropic => "ANTHROPIC_API_KEY",
+            AiderProvider::Openai => "OPENAI_API_KEY",
+            AiderProvider::Gemini => "GEMINI_API_KEY",
+        }
+    }
+}
+
+pub struct Aider {
+    /// Model passed to Aider on the VM (`--aider-model`)
+    model: Option<String>,
+
+    /// Provider whose key is stored on the VM (`--aider-provider`)
+    provider: Option<AiderProvider>,
+}
+
+impl Aider {
+    pub fn new(model: Option<String>, provider: Option<AiderProvider>) -> Self {
+        Self { model, provider }
+    }
+}
+
+impl Agent for Aider {
+    fn display_name(&self) -> &str {
+        "Aider"
+    }
+
+    fn command(&self) -> &str {
+        "aider"
+    }
+
+    fn install_command(&self) -> &str {
+        "pipx install aider-chat"
+    }
+
+    fn check_local(&self) -> bool {
+        utils::command_exists("aider")
+    }
+
+    fn check_logged_in(&self) -> bool {
+        // No login: the config file holds the provider keys (a key for
+        // --aider-provider is checked as the API key)
+        dirs::home_dir()
+            .map(|h| h.join(".aider.conf.yml").exists())
+            .unwrap_or(false)
+    }
+
+    fn login_instructions(&self) -> String {
+        match self.provider {
+            Some(_) => "Add your provider API key to ~/.aider.conf.yml".to_string(),
+            None => "Add your provider API key to ~/.aider.conf.yml, or choose the provider \
+                     with --aider-provider (anthropic, openai, gemini) and set its API key"
+                .to_string(),
+        }
+    }
+
+    fn credentials_path(&self) -> Option<PathBuf> {
+        dirs::home_dir().map(|h| h.join(".aider.conf.yml"))
+    }
+
+    fn remote_credentials_path(&self) -> &str {
+        "~/.aider.conf.yml"
+    }
+
+    fn headless_command(&self, prompt: &str, autonomy: Autonomy) -> String {
+        // --yes-always accepts every confirmation; the model comes from AIDER_MODEL
+        let flags = match autonomy {
+            Autonomy::ReadOnly => " --dry-run",
+            Autonomy::Edit => " --no-suggest-shell-commands",
+            Autonomy::Full => "",
+        };
+        format!(
+            "aider --yes-always --no-check-update{} --message {}",
+            flags,
+            utils::shell_quote(prompt)
+        )
+    }
+
+    fn autonomy_script(&self, autonomy: Autonomy) -> Option<String> {
+        // Aider never runs commands itself; it only suggests them
+        let (dry_run, shell) = match autonomy {
+            Autonomy::ReadOnly => ("true", "false"),
+            Autonomy::Edit => ("false", "false"),
+            Autonomy::Full => ("false", "true"),
+        };
+        Some(agents::yaml_setting_script(
+            "~/.aider.conf.yml",
+            &[("dry-run", dry_run), ("suggest-shell-commands", shell)],
+        ))
+    }
+
+    fn api_key_env(&self) -> Option<&str> {
+        self.provider.map(AiderProvider::key_env)
+    }
+
+    fn settings_env(&self) -> Vec<(String, String)> {
+        self.model
+            .iter()
+            .map(|model| ("AIDER_MODEL".to_string(), model.clone()))
+            .collect()
+    }
+}
@@ -1,6 +1,7 @@
 //! Auggie (Augment Code) agent implementation
 
-use crate::agents::Agent;
+use crate::agents::{self, Agent, Autonomy};
+use crate::settings::{SettingsFormat, SettingsItem};
 use crate::utils;
 use std::path::PathBuf;
 
@@ -43,4 +44,55 @@ impl Agent for Auggie {
     fn remote_credentials_path(&self) -> &str {
         "~/.augment/session.json"
     }
+
+    fn headless_command(&self, prompt: &str, autonomy: Autonomy) -> String {
+        // Ask mode can't edit files or run commands; Auggie has no edit-only
+        // flag, so edit relies on the tool permissions from autonomy_script
+        let flags = match autonomy {
+            Autonomy::ReadOnly => " --ask",
+            Autonomy::Edit | Autonomy::Full => "",
+        };
+        format!("auggie --print{} {}", flags, utils::shell_quote(prompt))
+    }
+
+    fn autonomy_script(&self, autonomy: Autonomy) -> Option<String> {
+        let edit = ["str-replace-editor", "save-file", "remove-files"];
+        let process = [
+            "launch-process",
+            "kill-process",
+            "read-process",
+            "write-process",
+        ];
+
+        let denied: Vec<&str> = match autonomy {
+            Autonomy::ReadOnly => [&edit[..], &process[..]].concat(),
+            Autonomy::Edit => process.to_vec(),
+            Autonomy::Full => Vec::new(),
+        };
+        let permissions: Vec<serde_json::Value> = denied
+            .iter()
+            .map(|tool| serde_json::json!({ "toolName": tool, "permission": { "type": "deny" } }))
+            .collect();
+        Some(agents::json_setting_script(
+            "~/.augment/settings.json",
+            "toolPermissions",
+            &serde_json::Value::from(permissions),
+        ))
+    }
+
+    fn api_key_env(&self) -> Option<&str> {
+        Some("AUGMENT_SESSION_AUTH")
+    }
+
+    fn skill_path(&self) -> Option<&str> {
+        // Installed as a custom slash command
+        Some(".augment/commands/cloud-agent.md")
+    }
+
+    fn settings_bundle(&self) -> Vec<SettingsItem> {
+        vec![
+            SettingsItem::new(".augment/settings.json", SettingsFormat::Json),
+            SettingsItem::new(".augment/commands", SettingsFormat::Dir),
+        ]
+    }
 }
@@ -1,6 +1,7 @@
 //! Claude Code (Anthropic) agent implementation
 
-use crate::agents::Agent;
+use crate::agents::{self, Agent, Autonomy};
+use crate::settings::{SettingsFormat, SettingsItem};
 use crate::utils;
 use std::path::PathBuf;
 
@@ -43,4 +44,58 @@ impl Agent for Claude {
     fn remote_credentials_path(&self) -> &str {
         "~/.claude.json"
     }
+
+    fn headless_command(&self, prompt: &str, autonomy: Autonomy) -> String {
+        // Print mode denies tools that would need approval instead of waiting
+        let flags = match autonomy {
+            Autonomy::ReadOnly => "",
+            Autonomy::Edit => " --permission-mode acceptEdits",
+            Autonomy::Full => " --dangerously-skip-permissions",
+        };
+        format!("claude -p{} {}", flags, utils::shell_quote(prompt))
+    }
+
+    fn autonomy_script(&self, autonomy: Autonomy) -> Option<String> {
+        let read = ["Read", "Glob", "Grep", "LS", "WebFetch", "WebSearch"];
+        let git_read = ["Bash(git status:*)", "Bash(git diff:*)", "Bash(git log:*)"];
+        let edit = ["Edit", "MultiEdit", "Write", "NotebookEdit"];
+        let git_write = ["Bash(git add:*)", "Bash(git commit:*)"];
+
+        let permissions = match autonomy {
+            Autonomy::ReadOnly => serde_json::json!({
+                "defaultMode": "default",
+                "allow": ([&read[..], &git_read[..]].concat()),
+                "deny": edit,
+            }),
+            Autonomy::Edit => serde_json::json!({
+                "defaultMode": "acceptEdits",
+                "allow": ([&read[..], &git_read[..], &edit[..], &git_write[..]].concat()),
+            }),
+            Autonomy::Full => serde_json::json!({ "defaultMode": "bypassPermissions" }),
+        };
+        Some(agents::json_setting_script(
+            "~/.claude/settings.json",
+            "permissions",
+            &permissions,
+        ))
+    }
+
+    fn api_key_env(&self) -> Option<&str> {
+        Some("ANTHROPIC_API_KEY")
+    }
+
+    fn skill_path(&self) -> Option<&str> {
+        Some(".claude/skills/cloud-agent/SKILL.md")
+    }
+
+    fn settings_bundle(&self) -> Vec<SettingsItem> {
+        // ~/.claude.json holds user-scoped MCP servers
+        vec![
+            SettingsItem::new(".claude.json", SettingsFormat::Json),
+            SettingsItem::new(".claude/settings.json", SettingsFormat::Json),
+            SettingsItem::new(".claude/CLAUDE.md", SettingsFormat::Text),
+            SettingsItem::new(".claude/commands", SettingsFormat::Dir),
+            SettingsItem::new(".claude/agents", SettingsFormat::Dir),
+        ]
+    }
 }
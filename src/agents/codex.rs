@@ -1,6 +1,7 @@
 //! Codex (OpenAI) agent implementation
 
-use crate::agents::Agent;
+use crate::agents::{self, Agent, Autonomy};
+use crate::settings::{SettingsFormat, SettingsItem};
 use crate::utils;
 use std::path::PathBuf;
 
@@ -43,4 +44,44 @@ impl Agent for Codex {
     fn remote_credentials_path(&self) -> &str {
         "~/.codex/config.toml"
     }
+
+    fn headless_command(&self, prompt: &str, autonomy: Autonomy) -> String {
+        // exec never asks for approval; the sandbox sets the limits
+        let flags = match autonomy {
+            Autonomy::ReadOnly => "--sandbox read-only",
+            Autonomy::Edit => "--full-auto",
+            Autonomy::Full => "--dangerously-bypass-approvals-and-sandbox",
+        };
+        format!("codex exec {} {}", flags, utils::shell_quote(prompt))
+    }
+
+    fn autonomy_script(&self, autonomy: Autonomy) -> Option<String> {
+        let sandbox = match autonomy {
+            Autonomy::ReadOnly => "read-only",
+            Autonomy::Edit => "workspace-write",
+            Autonomy::Full => "danger-full-access",
+        };
+        Some(agents::toml_setting_script(
+            "~/.codex/config.toml",
+            &[("approval_policy", "never"), ("sandbox_mode", sandbox)],
+        ))
+    }
+
+    fn api_key_env(&self) -> Option<&str> {
+        Some("OPENAI_API_KEY")
+    }
+
+    fn skill_path(&self) -> Option<&str> {
+        // Installed as a custom prompt
+        Some(".codex/prompts/cloud-agent.md")
+    }
+
+    fn settings_bundle(&self) -> Vec<SettingsItem> {
+        // config.toml holds profiles and MCP servers
+        vec![
+            SettingsItem::new(".codex/config.toml", SettingsFormat::Toml),
+            SettingsItem::new(".codex/AGENTS.md", SettingsFormat::Text),
+            SettingsItem::new(".codex/prompts", SettingsFormat::Dir),
+        ]
+    }
 }
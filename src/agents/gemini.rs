@@ -0,0 +1,92 @@
+//! Gemini CLI (Google) agent implementation
+
+use crate::agents::{self, Agent, Autonomy};
+use crate::settings::{SettingsFormat, SettingsItem};
+use crate::utils;
+use std::path::PathBuf;
+
+pub struct Gemini;
+
+impl Agent for Gemini {
+    fn display_name(&self) -> &str {
+        "Gemini CLI (Google)"
+    }
+
+    fn command(&self) -> &str {
+        "gemini"
+    }
+
+    fn install_command(&self) -> &str {
+        "npm install -g @google/gemini-cli"
+    }
+
+    fn check_local(&self) -> bool {
+        utils::command_exists("gemini")
+    }
+
+    fn check_logged_in(&self) -> bool {
+        // Check if OAuth credentials exist
+        if let Some(home) = dirs::home_dir() {
+            home.join(".gemini/oauth_creds.json").exists()
+        } else {
+            false
+        }
+    }
+
+    fn login_instructions(&self) -> String {
+        "Run 'gemini' and sign in with Google".to_string()
+    }
+
+    fn credentials_path(&self) -> Option<PathBuf> {
+        dirs::home_dir().map(|h| h.join(".gemini/oauth_creds.json"))
+    }
+
+    fn remote_credentials_path(&self) -> &str {
+        "~/.gemini/oauth_creds.json"
+    }
+
+    fn headless_command(&self, prompt: &str, autonomy: Autonomy) -> String {
+        // Non-interactive mode leaves out tools that would need approval
+        let flags = match autonomy {
+            Autonomy::ReadOnly => "",
+            Autonomy::Edit => " --approval-mode auto_edit",
+            Autonomy::Full => " --yolo",
+        };
+        format!("gemini{} -p {}", flags, utils::shell_quote(prompt))
+    }
+
+    fn autonomy_script(&self, autonomy: Autonomy) -> Option<String> {
+        // Excluded tools are unavailable even with --yolo or in a session
+        let exclude: &[&str] = match autonomy {
+            Autonomy::ReadOnly => &["write_file", "replace", "run_shell_command"],
+            Autonomy::Edit => &["run_shell_command"],
+            Autonomy::Full => &[],
+        };
+        Some(agents::json_setting_script(
+            "~/.gemini/settings.json",
+            "tools.exclude",
+            &serde_json::json!(exclude),
+        ))
+    }
+
+    fn api_key_env(&self) -> Option<&str> {
+        Some("GEMINI_API_KEY")
+    }
+
+    fn service_account_env(&self, project_id: &str, region: &str) -> Option<Vec<(String, String)>> {
+        // Vertex AI picks up the VM's service account through the metadata server
+        Some(vec![
+            ("GOOGLE_GENAI_USE_VERTEXAI".to_string(), "true".to_string()),
+            ("GOOGLE_CLOUD_PROJECT".to_string(), project_id.to_string()),
+            ("GOOGLE_CLOUD_LOCATION".to_string(), region.to_string()),
+        ])
+    }
+
+    fn settings_bundle(&self) -> Vec<SettingsItem> {
+        vec![
+            SettingsItem::new(".gemini/settings.json", SettingsFormat::Json),
+            SettingsItem::new(".gemini/GEMINI.md", SettingsFormat::Text),
+            SettingsItem::new(".gemini/commands", SettingsFormat::Dir),
+        ]
+    }
+}
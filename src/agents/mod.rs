@@ -1,19 +1,58 @@
 //! Agent management for cloud-agent
 //!
-//! This module handles different AI coding agents (Auggie, Claude, Codex).
+//! This module handles different AI coding agents (Aider, Auggie, Claude,
+//! Codex, Gemini).
 //! Each agent has its own configuration and requirements.
 
+mod aider;
 mod auggie;
 mod claude;
 mod codex;
+mod gemini;
 
 use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 use crate::config::Config;
 use crate::error::CloudAgentError;
+use crate::settings::SettingsItem;
 use crate::utils;
 
+pub use aider::AiderProvider;
+
+/// How much an unattended agent may do without asking.
+///
+/// Every agent maps the levels onto its own permission settings and
+/// headless flags, as closely as it allows: Aider only has a dry run for
+/// read-only, and Auggie and Gemini enforce edit by denying their shell
+/// tools rather than with an edit-only mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Autonomy {
+    /// Read and search the code, no edits or commands
+    ReadOnly,
+
+    /// Edit files in the workspace, no arbitrary commands
+    Edit,
+
+    /// Run anything without approval
+    #[default]
+    Full,
+}
+
+impl std::fmt::Display for Autonomy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Autonomy::ReadOnly => "read-only",
+            Autonomy::Edit => "edit",
+            Autonomy::Full => "full",
+        };
+        f.write_str(s)
+    }
+}
+
 /// Trait for agent implementations
 #[allow(dead_code)]
 pub trait Agent {
@@ -40,29 +79,121 @@ pub trait Agent {
 
     /// Get the remote credentials path on the VM
     fn remote_credentials_path(&self) -> &str;
+
+    /// Get the shell command that runs `prompt` non-interactively with
+    /// the agent's flags for `autonomy`
+    fn headless_command(&self, prompt: &str, autonomy: Autonomy) -> String;
+
+    /// Shell command that writes the agent's permission config for
+    /// `autonomy` on the VM, if it has one
+    fn autonomy_script(&self, _autonomy: Autonomy) -> Option<String> {
+        None
+    }
+
+    /// Environment variable holding an API key the agent accepts instead
+    /// of a login, if it supports one
+    fn api_key_env(&self) -> Option<&str>;
+
+    /// Environment that makes the agent authenticate with the VM's service
+    /// account instead of copied credentials, if it supports that
+    fn service_account_env(
+        &self,
+        _project_id: &str,
+        _region: &str,
+    ) -> Option<Vec<(String, String)>> {
+        None
+    }
+
+    /// Environment configuring the agent on the VM (e.g. model selection)
+    fn settings_env(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Where the cloud-agent skill is installed locally, relative to the
+    /// home directory, if the agent supports skills or custom commands
+    fn skill_path(&self) -> Option<&str> {
+        None
+    }
+
+    /// Local settings, memory files, commands and MCP server definitions
+    /// copied by `--with-settings`
+    fn settings_bundle(&self) -> Vec<SettingsItem> {
+        Vec::new()
+    }
 }
 
+/// Env file on the VM holding agent API keys and auth settings
+pub const AGENT_ENV_FILE: &str = "~/.cloud-agent/agent.env";
+
 /// Agent manager that handles all agent operations
 pub struct AgentManager {
     agent: Box<dyn Agent>,
+
+    /// Local command printing the agent's API key (`--api-key-command`)
+    api_key_command: Option<String>,
+
+    /// Service account environment, when `--service-account-auth` is set
+    /// and the agent supports it
+    service_account_env: Option<Vec<(String, String)>>,
+
+    /// Permission level for headless commands
+    autonomy: Autonomy,
 }
 
 impl AgentManager {
     /// Create a new agent manager
     pub fn new(config: Config) -> Result<Self> {
-        let agent: Box<dyn Agent> = match config.agent.as_str() {
-            "auggie" => Box::new(auggie::Auggie),
-            "claude" => Box::new(claude::Claude),
-            "codex" => Box::new(codex::Codex),
-            _ => {
-                let available = "auggie, claude, codex";
-                return Err(
-                    CloudAgentError::AgentNotFound(config.agent, available.to_string()).into(),
-                );
-            }
+        Self::for_agent(&config.agent, &config)
+    }
+
+    /// Create an agent manager for the agent called `name`
+    pub fn for_agent(name: &str, config: &Config) -> Result<Self> {
+        let agent = create_agent(name, Some(config))?;
+
+        let service_account_env = if config.service_account_auth {
+            agent.service_account_env(&config.project_id, &config.region)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            agent,
+            api_key_command: config.api_key_commands.get(name).cloned(),
+            service_account_env,
+            autonomy: config.autonomy.unwrap_or_default(),
+        })
+    }
+
+    /// Get the agent's API key from its environment variable or from
+    /// `--api-key-command`, if either is set
+    pub fn api_key(&self) -> Result<Option<String>> {
+        let Some(var) = self.agent.api_key_env() else {
+            return Ok(None);
+        };
+
+        if let Some(key) = std::env::var(var).ok().filter(|k| !k.trim().is_empty()) {
+            return Ok(Some(key.trim().to_string()));
+        }
+
+        let Some(command) = &self.api_key_command else {
+            return Ok(None);
         };
 
-        Ok(Self { agent })
+        let output = std::process::Command::new("sh")
+            .args(["-c", command])
+            .stderr(std::process::Stdio::inherit())
+            .output()?;
+        let key = String::from_utf8(output.stdout)?.trim().to_string();
+        if !output.status.success() || key.is_empty() {
+            return Err(CloudAgentError::ConfigError(format!(
+                "API key command for {} failed: {}",
+                self.agent.display_name(),
+                command
+            ))
+            .into());
+        }
+
+        Ok(Some(key))
     }
 
     /// Check agent prerequisites (installed and logged in)
@@ -72,6 +203,21 @@ impl AgentManager {
             self.agent.display_name()
         ));
 
+        if self.service_account_env.is_some() {
+            utils::log_success(&format!(
+                "{} will use the VM service account",
+                self.agent.display_name()
+            ));
+            return Ok(());
+        }
+
+        if let Some(var) = self.agent.api_key_env() {
+            if self.api_key()?.is_some() {
+                utils::log_success(&format!("Using {} for {}", var, self.agent.display_name()));
+                return Ok(());
+            }
+        }
+
         if !self.agent.check_local() {
             return Err(CloudAgentError::AgentNotLoggedIn(
                 self.agent.display_name().to_string(),
@@ -81,9 +227,13 @@ impl AgentManager {
         }
 
         if !self.agent.check_logged_in() {
+            let mut instructions = self.agent.login_instructions();
+            if let Some(var) = self.agent.api_key_env() {
+                instructions.push_str(&format!(", or set {} (or --api-key-command)", var));
+            }
             return Err(CloudAgentError::AgentNotLoggedIn(
                 self.agent.display_name().to_string(),
-                self.agent.login_instructions(),
+                instructions,
             )
             .into());
         }
@@ -118,10 +268,340 @@ impl AgentManager {
     pub fn remote_credentials_path(&self) -> &str {
         self.agent.remote_credentials_path()
     }
+
+    /// Get the shell command that runs `prompt` non-interactively
+    pub fn headless_command(&self, prompt: &str) -> String {
+        self.agent.headless_command(prompt, self.autonomy)
+    }
+}
+
+/// Create the agent called `name`, with its options from `config` if given
+fn create_agent(name: &str, config: Option<&Config>) -> Result<Box<dyn Agent>> {
+    let agent: Box<dyn Agent> = match name {
+        "aider" => Box::new(aider::Aider::new(
+            config.and_then(|c| c.aider_model.clone()),
+            config.and_then(|c| c.aider_provider),
+        )),
+        "auggie" => Box::new(auggie::Auggie),
+        "claude" => Box::new(claude::Claude),
+        "codex" => Box::new(codex::Codex),
+        "gemini" => Box::new(gemini::Gemini),
+        _ => {
+            let available = list_agents().join(", ");
+            return Err(CloudAgentError::AgentNotFound(name.to_string(), available).into());
+        }
+    };
+    Ok(agent)
 }
 
 /// List all available agents
-#[allow(dead_code)]
 pub fn list_agents() -> Vec<&'static str> {
-    vec!["auggie", "claude", "codex"]
+    vec!["aider", "auggie", "claude", "codex", "gemini"]
+}
+
+/// Collect the environment for `agents` as `(variable, value)` pairs: agent
+/// settings, plus service account settings where enabled or otherwise the
+/// agent's API key. Other agents' keys stay on this machine.
+pub fn collect_agent_env(config: &Config, agents: &[String]) -> Result<Vec<(String, String)>> {
+    let mut env: Vec<(String, String)> = Vec::new();
+    for name in agents {
+        let manager = AgentManager::for_agent(name, config)?;
+        let mut vars = manager.agent.settings_env();
+        if let Some(sa_env) = manager.service_account_env.clone() {
+            vars.extend(sa_env);
+        } else if let (Some(var), Some(key)) = (manager.agent.api_key_env(), manager.api_key()?) {
+            vars.push((var.to_string(), key));
+        }
+        // Agents can share a variable (Codex and Aider with openai)
+        for (var, value) in vars {
+            if !env.iter().any(|(existing, _)| *existing == var) {
+                env.push((var, value));
+            }
+        }
+    }
+    Ok(env)
+}
+
+/// Local agents that can use the skill but don't run on VMs, with their
+/// skill paths
+const SKILL_ONLY_AGENTS: &[(&str, &str)] = &[("copilot", ".copilot/skills/cloud-agent/SKILL.md")];
+
+/// Local skill paths as `(agent, path)` pairs, for `agent` or for every
+/// agent that supports skills. Doesn't need a full configuration, so
+/// `ca skill` works without gcloud.
+pub fn skill_paths(agent: Option<&str>) -> Result<Vec<(String, PathBuf)>> {
+    let Some(home) = dirs::home_dir() else {
+        return Ok(Vec::new());
+    };
+    let names = match agent {
+        Some(name) => vec![name],
+        None => list_agents()
+            .into_iter()
+            .chain(SKILL_ONLY_AGENTS.iter().map(|(name, _)| *name))
+            .collect(),
+    };
+
+    let mut paths = Vec::new();
+    for name in names {
+        if let Some((_, path)) = SKILL_ONLY_AGENTS.iter().find(|(n, _)| *n == name) {
+            paths.push((name.to_string(), home.join(path)));
+            continue;
+        }
+        match create_agent(name, None)?.skill_path() {
+            Some(path) => paths.push((name.to_string(), home.join(path))),
+            None if agent.is_some() => {
+                return Err(CloudAgentError::ConfigError(format!(
+                    "{} doesn't support skills or custom commands",
+                    name
+                ))
+                .into());
+            }
+            None => {}
+        }
+    }
+    Ok(paths)
+}
+
+/// Collect the settings bundle items of every agent
+pub fn collect_settings_bundle(config: &Config) -> Result<Vec<SettingsItem>> {
+    let mut items = Vec::new();
+    for name in list_agents() {
+        items.extend(
+            AgentManager::for_agent(name, config)?
+                .agent
+                .settings_bundle(),
+        );
+    }
+    Ok(items)
+}
+
+/// Shell commands writing every agent's permission config for `autonomy`,
+/// as `(display name, script)` pairs
+pub fn autonomy_scripts(config: &Config, autonomy: Autonomy) -> Result<Vec<(String, String)>> {
+    let mut scripts = Vec::new();
+    for name in list_agents() {
+        let manager = AgentManager::for_agent(name, config)?;
+        if let Some(script) = manager.agent.autonomy_script(autonomy) {
+            scripts.push((manager.agent.display_name().to_string(), script));
+        }
+    }
+    Ok(scripts)
+}
+
+/// Shell command that sets `key` to `value` in the JSON file at `path`,
+/// creating the file if needed and keeping its other settings
+pub fn json_setting_script(path: &str, key: &str, value: &serde_json::Value) -> String {
+    format!(
+        "f={path} && mkdir -p \"$(dirname \"$f\")\" && {{ [ -s \"$f\" ] || echo '{{}}' > \"$f\"; }} && \
+         jq --argjson v {value} '.{key} = $v' \"$f\" > \"$f.tmp\" && mv \"$f.tmp\" \"$f\"",
+        path = path,
+        key = key,
+        value = utils::shell_quote(&value.to_string()),
+    )
+}
+
+/// Shell command that sets top-level `key: value` lines at the start of
+/// the YAML file at `path`, replacing earlier top-level values for the keys
+pub fn yaml_setting_script(path: &str, settings: &[(&str, &str)]) -> String {
+    let lines: Vec<String> = settings
+        .iter()
+        .map(|(key, value)| utils::shell_quote(&format!("{}: {}", key, value)))
+        .collect();
+    let keys: Vec<&str> = settings.iter().map(|(key, _)| *key).collect();
+    format!(
+        "f={path} && touch \"$f\" && \
+         {{ printf '%s\\n' {lines}; awk '!/^({keys})[ \\t]*:/' \"$f\"; }} > \"$f.tmp\" && \
+         mv \"$f.tmp\" \"$f\"",
+        path = path,
+        lines = lines.join(" "),
+        keys = keys.join("|"),
+    )
+}
+
+/// Shell command that sets top-level `key = value` lines at the start of
+/// the TOML file at `path`, replacing earlier top-level values for the keys
+pub fn toml_setting_script(path: &str, settings: &[(&str, &str)]) -> String {
+    let lines: Vec<String> = settings
+        .iter()
+        .map(|(key, value)| utils::shell_quote(&format!("{} = \"{}\"", key, value)))
+        .collect();
+    let keys: Vec<&str> = settings.iter().map(|(key, _)| *key).collect();
+    format!(
+        "f={path} && mkdir -p \"$(dirname \"$f\")\" && touch \"$f\" && \
+         {{ printf '%s\\n' {lines}; awk '/^\\[/ {{ t = 1 }} t || !/^({keys})[ \\t]*=/' \"$f\"; }} > \"$f.tmp\" && \
+         mv \"$f.tmp\" \"$f\"",
+        path = path,
+        lines = lines.join(" "),
+        keys = keys.join("|"),
+    )
+}
+
+/// Whether `agent` authenticates with the VM's service account, so its
+/// local credentials shouldn't be copied
+pub fn uses_service_account(agent: &str, config: &Config) -> bool {
+    AgentManager::for_agent(agent, config)
+        .map(|m| m.service_account_env.is_some())
+        .unwrap_or(false)
+}
+
+/// Shell command that writes `keys` to [`AGENT_ENV_FILE`] (mode 600), or
+/// removes the file when there are none.
+///
+/// The file uses plain `VAR='value'` lines so both systemd's
+/// `EnvironmentFile=` and the shell can read it; `~/.bashrc` sources it
+/// before its interactive check so tmux windows and SSH commands see it.
+pub fn env_file_script(keys: &[(String, String)]) -> Result<String> {
+    if keys.is_empty() {
+        return Ok(format!("rm -f {}", AGENT_ENV_FILE));
+    }
+
+    let mut content = String::new();
+    for (var, key) in keys {
+        if key.contains(['\'', '\n', '\r']) {
+            return Err(CloudAgentError::ConfigError(format!(
+                "{} contains characters that can't be stored on the VM",
+                var
+            ))
+            .into());
+        }
+        content.push_str(&format!("{}='{}'\n", var, key));
+    }
+
+    let source_line = format!(
+        "[ -f {file} ] && {{ set -a; . {file}; set +a; }}",
+        file = AGENT_ENV_FILE
+    );
+
+    Ok(format!(
+        "mkdir -p ~/.cloud-agent && (umask 077 && printf '%s' {content} > {file}) && \
+         chmod 600 {file} && \
+         (grep -qF '/.cloud-agent/agent.env' ~/.bashrc 2>/dev/null || \
+          {{ {{ printf '%s\\n' {line}; cat ~/.bashrc 2>/dev/null; }} > ~/.bashrc.tmp && \
+             mv ~/.bashrc.tmp ~/.bashrc; }})",
+        content = utils::shell_quote(&content),
+        file = AGENT_ENV_FILE,
+        line = utils::shell_quote(&source_line),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gemini_service_account_env() {
+        let gemini = gemini::Gemini;
+        let env = gemini
+            .service_account_env("my-project", "us-central1")
+            .unwrap();
+        assert!(env.contains(&("GOOGLE_GENAI_USE_VERTEXAI".to_string(), "true".to_string())));
+        assert!(env.contains(&("GOOGLE_CLOUD_PROJECT".to_string(), "my-project".to_string())));
+        assert!(codex::Codex
+            .service_account_env("my-project", "us-central1")
+            .is_none());
+        assert!(list_agents().contains(&"gemini"));
+    }
+
+    #[test]
+    fn test_unknown_agent() {
+        let err = create_agent("cursor", None).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Agent 'cursor' not found. Available agents: aider, auggie, claude, codex, gemini"
+        );
+        for name in list_agents() {
+            assert!(create_agent(name, None).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_aider_model_env() {
+        let aider = aider::Aider::new(Some("sonnet".to_string()), None);
+        assert_eq!(
+            aider.settings_env(),
+            vec![("AIDER_MODEL".to_string(), "sonnet".to_string())]
+        );
+        assert_eq!(aider.api_key_env(), None);
+        let aider_openai = aider::Aider::new(None, Some(AiderProvider::Openai));
+        assert!(aider_openai.settings_env().is_empty());
+        assert_eq!(aider_openai.api_key_env(), Some("OPENAI_API_KEY"));
+        assert_eq!(
+            aider.headless_command("fix it", Autonomy::Full),
+            "aider --yes-always --no-check-update --message 'fix it'"
+        );
+    }
+
+    #[test]
+    fn test_autonomy() {
+        assert_eq!(
+            claude::Claude.headless_command("go", Autonomy::Full),
+            "claude -p --dangerously-skip-permissions 'go'"
+        );
+        assert_eq!(
+            codex::Codex.headless_command("go", Autonomy::ReadOnly),
+            "codex exec --sandbox read-only 'go'"
+        );
+        let script = gemini::Gemini.autonomy_script(Autonomy::Edit).unwrap();
+        assert!(script.starts_with("f=~/.gemini/settings.json && "));
+        assert!(script.contains("'.tools.exclude = $v'"));
+        assert!(script.contains("'[\"run_shell_command\"]'"));
+
+        let script = aider::Aider::new(None, None)
+            .autonomy_script(Autonomy::ReadOnly)
+            .unwrap();
+        assert!(script.contains("printf '%s\\n' 'dry-run: true' 'suggest-shell-commands: false'"));
+        assert!(script.contains("awk '!/^(dry-run|suggest-shell-commands)[ \\t]*:/'"));
+
+        let script = auggie::Auggie.autonomy_script(Autonomy::Edit).unwrap();
+        assert!(script.starts_with("f=~/.augment/settings.json && "));
+        assert!(
+            script.contains("{\"permission\":{\"type\":\"deny\"},\"toolName\":\"launch-process\"}")
+        );
+        assert!(!script.contains("save-file"));
+        let script = auggie::Auggie.autonomy_script(Autonomy::Full).unwrap();
+        assert!(script.contains("'[]'"));
+
+        let script = claude::Claude.autonomy_script(Autonomy::Edit).unwrap();
+        assert!(script.starts_with("f=~/.claude/settings.json && "));
+        assert!(script.contains("'.permissions = $v'"));
+        assert!(script.contains("\"defaultMode\":\"acceptEdits\""));
+
+        let script = codex::Codex.autonomy_script(Autonomy::Full).unwrap();
+        assert!(script.contains(
+            "printf '%s\\n' 'approval_policy = \"never\"' 'sandbox_mode = \"danger-full-access\"'"
+        ));
+        assert!(script.contains("!/^(approval_policy|sandbox_mode)[ \\t]*=/"));
+    }
+
+    #[test]
+    fn test_env_file_script() {
+        let keys = vec![("ANTHROPIC_API_KEY".to_string(), "sk-ant-123".to_string())];
+        let script = env_file_script(&keys).unwrap();
+        assert!(script.contains("printf '%s' 'ANTHROPIC_API_KEY='\\''sk-ant-123'\\''"));
+        assert!(script.contains("chmod 600 ~/.cloud-agent/agent.env"));
+        assert!(script.contains("set -a; . ~/.cloud-agent/agent.env; set +a;"));
+
+        assert_eq!(
+            env_file_script(&[]).unwrap(),
+            "rm -f ~/.cloud-agent/agent.env"
+        );
+        let bad = vec![("OPENAI_API_KEY".to_string(), "a'b".to_string())];
+        assert!(env_file_script(&bad).is_err());
+    }
+
+    #[test]
+    fn test_skill_paths() {
+        let agents: Vec<String> = skill_paths(None)
+            .unwrap()
+            .into_iter()
+            .map(|(agent, _)| agent)
+            .collect();
+        assert_eq!(agents, vec!["auggie", "claude", "codex", "copilot"]);
+
+        let copilot = skill_paths(Some("copilot")).unwrap();
+        assert!(copilot[0]
+            .1
+            .ends_with(".copilot/skills/cloud-agent/SKILL.md"));
+        assert!(skill_paths(Some("aider")).is_err());
+    }
 }
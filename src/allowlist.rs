@@ -0,0 +1,119 @@
+//! SSH firewall allowlist helpers
+//!
+//! Addresses allowed to SSH to a public VM are tracked with an expiry in its
+//! [`VmState`](crate::state::VmState). The firewall rule is built from the
+//! unexpired entries and any ranges added to it outside `ca`, so moving
+//! between networks adds an address without locking out the previous one,
+//! and stale addresses drop off on the next update.
+
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::error::CloudAgentError;
+
+/// Normalise an address or CIDR range for a firewall rule (`1.2.3.4` becomes
+/// `1.2.3.4/32`, IPv6 addresses get `/128`)
+pub fn normalize_cidr(value: &str) -> Result<String> {
+    let invalid =
+        || CloudAgentError::ConfigError(format!("Invalid IP address or range: {}", value));
+    let (addr, prefix) = match value.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (value.trim(), None),
+    };
+    let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= max)
+            .ok_or_else(invalid)?,
+        None => max,
+    };
+    Ok(format!("{}/{}", addr, prefix))
+}
+
+/// Source ranges for the SSH firewall rule: the `current` ranges that `ca`
+/// doesn't track (added by hand, so kept), then the `active` tracked ones.
+/// Tracked ranges that are no longer active drop out.
+pub fn merge_ranges(current: &[String], tracked: &[String], active: &[String]) -> Vec<String> {
+    let mut ranges: Vec<String> = current
+        .iter()
+        .filter(|r| !tracked.contains(r) && !active.contains(r))
+        .cloned()
+        .collect();
+    ranges.extend(active.iter().cloned());
+    ranges
+}
+
+/// Whether `ip` falls in any of the CIDR `ranges`
+pub fn covers(ranges: &[String], ip: IpAddr) -> bool {
+    ranges.iter().any(|range| {
+        let Some((net, prefix)) = range.split_once('/') else {
+            return range.parse::<IpAddr>().ok() == Some(ip);
+        };
+        let (Ok(net), Ok(prefix)) = (net.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+            return false;
+        };
+        match (net, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) if prefix <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) if prefix <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Whether the VM's SSH port accepts connections within `timeout`
+pub fn ssh_port_open(vm_ip: &str, timeout: Duration) -> bool {
+    vm_ip
+        .parse::<IpAddr>()
+        .map(|ip| TcpStream::connect_timeout(&SocketAddr::new(ip, 22), timeout).is_ok())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_cidr() {
+        assert_eq!(normalize_cidr("1.2.3.4").unwrap(), "1.2.3.4/32");
+        assert_eq!(normalize_cidr("10.0.0.0/8").unwrap(), "10.0.0.0/8");
+        assert_eq!(normalize_cidr("2001:db8::1").unwrap(), "2001:db8::1/128");
+        assert!(normalize_cidr("1.2.3.4/33").is_err());
+        assert!(normalize_cidr("1.2.3").is_err());
+        assert!(normalize_cidr("example.com").is_err());
+    }
+
+    #[test]
+    fn test_covers() {
+        let ranges = vec!["10.0.0.0/8".to_string(), "1.2.3.4/32".to_string()];
+        assert!(covers(&ranges, "10.20.30.40".parse().unwrap()));
+        assert!(covers(&ranges, "1.2.3.4".parse().unwrap()));
+        assert!(!covers(&ranges, "1.2.3.5".parse().unwrap()));
+        assert!(covers(
+            &["0.0.0.0/0".to_string()],
+            "8.8.8.8".parse().unwrap()
+        ));
+        assert!(!covers(&ranges, "2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_merge_ranges() {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let current = strings(&["10.0.0.0/8", "1.1.1.1/32", "2.2.2.2/32"]);
+        let tracked = strings(&["1.1.1.1/32", "2.2.2.2/32"]);
+        let active = strings(&["2.2.2.2/32", "3.3.3.3/32"]);
+        assert_eq!(
+            merge_ranges(&current, &tracked, &active),
+            strings(&["10.0.0.0/8", "2.2.2.2/32", "3.3.3.3/32"])
+        );
+    }
+}
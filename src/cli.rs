@@ -5,17 +5,28 @@
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use std::net::IpAddr;
+use std::path::PathBuf;
 
-use crate::agents::AgentManager;
-use crate::config::Config;
+use crate::agents::{self, AgentManager, AiderProvider, Autonomy};
+use crate::config::{Config, NetworkMode};
+use crate::credentials::GitAuth;
+use crate::egress::EgressMode;
+use crate::error::CloudAgentError;
 use crate::gcp::VmManager;
+use crate::permissions;
+use crate::public_ip::IpFamily;
+use crate::run::RunOptions;
+use crate::skill;
+use crate::update;
+use crate::utils;
 
 /// Cloud Agent - Deploy repos to Cloud Agent VMs for AI coding agents
 #[derive(Parser, Debug)]
 #[command(name = "ca")]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Agent to use (auggie, claude, codex)
+    /// Agent to use (aider, auggie, claude, codex, gemini)
     #[arg(long, env = "AGENT", default_value = "auggie")]
     pub agent: String,
 
@@ -27,9 +38,19 @@ pub struct Args {
     #[arg(long, env = "MACHINE_TYPE", default_value = "n2-standard-4")]
     pub machine_type: String,
 
-    /// GKE cluster name (optional)
-    #[arg(long, env = "CLUSTER_NAME")]
-    pub cluster_name: Option<String>,
+    /// GKE clusters to configure kubectl for, as NAME or NAME@LOCATION (repeatable)
+    #[arg(
+        long = "cluster",
+        visible_alias = "cluster-name",
+        env = "CLUSTER_NAME",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub clusters: Vec<String>,
+
+    /// Default zone or region of --cluster entries without a location (default: --zone)
+    #[arg(long, env = "CLUSTER_LOCATION", global = true)]
+    pub cluster_location: Option<String>,
 
     /// Path to SSH private key for GitHub
     #[arg(long, env = "SSH_KEY")]
@@ -39,11 +60,75 @@ pub struct Args {
     #[arg(long, env = "GITHUB_TOKEN")]
     pub github_token: Option<String>,
 
+    /// Access token for a Git host as HOST=TOKEN (repeatable)
+    #[arg(long = "git-token", env = "GIT_TOKENS", value_delimiter = ',')]
+    pub git_tokens: Vec<String>,
+
+    /// How the VM authenticates to Git hosts over SSH (copy-key stores your
+    /// SSH key on the VM)
+    #[arg(long, env = "GIT_AUTH", value_enum, default_value = "agent-forward")]
+    pub git_auth: GitAuth,
+
+    /// Git commit author name on the VM (default: local git config)
+    #[arg(long, env = "GIT_USER_NAME")]
+    pub git_name: Option<String>,
+
+    /// Git commit author email on the VM (default: local git config)
+    #[arg(long, env = "GIT_USER_EMAIL")]
+    pub git_email: Option<String>,
+
+    /// Sign commits on the VM using the local SSH signing setup
+    #[arg(long, env = "SIGN_COMMITS")]
+    pub sign_commits: bool,
+
+    /// Dedicated SSH key for signing commits on the VM (implies --sign-commits)
+    #[arg(long, env = "SIGNING_KEY")]
+    pub signing_key: Option<PathBuf>,
+
+    /// Trailer added to every commit on the VM, e.g. "Agent: {agent}" (repeatable)
+    #[arg(
+        long = "commit-trailer",
+        env = "COMMIT_TRAILERS",
+        value_delimiter = ','
+    )]
+    pub commit_trailers: Vec<String>,
+
+    /// Don't add trailers to commits made on the VM
+    #[arg(long, conflicts_with = "commit_trailers")]
+    pub no_commit_trailers: bool,
+
+    /// Branches agents may not push to (comma-separated globs)
+    #[arg(
+        long,
+        env = "PROTECTED_BRANCHES",
+        value_delimiter = ',',
+        default_value = "main,master,release/*"
+    )]
+    pub protected_branches: Vec<String>,
+
+    /// Branches agents may push to (comma-separated globs, empty allows any)
+    #[arg(
+        long,
+        env = "ALLOWED_BRANCHES",
+        value_delimiter = ',',
+        default_value = "cloud-agent/*"
+    )]
+    pub allowed_branches: Vec<String>,
+
+    /// Don't install the pre-push guard hook on the VM
+    #[arg(long, env = "NO_PUSH_GUARD")]
+    pub no_push_guard: bool,
+
+    /// GitHub REST API URL (for GitHub Enterprise)
+    #[arg(long, env = "GITHUB_API_URL", default_value = crate::github::DEFAULT_API_URL)]
+    pub github_api_url: String,
+
     /// Skip deletion label (yes/no)
     #[arg(long, env = "SKIP_DELETION", default_value = "yes")]
     pub skip_deletion: String,
 
-    /// Comma-separated permissions for VM service account
+    /// Comma-separated permissions for VM service account: shortcuts (see
+    /// `ca permissions list`), SHORTCUT:RESOURCE, or IAM role IDs
     #[arg(long, env = "PERMISSIONS")]
     pub permissions: Option<String>,
 
@@ -59,6 +144,98 @@ pub struct Args {
     #[arg(long, env = "COMPANY")]
     pub company: Option<String>,
 
+    /// Path to config file (default: ~/.config/cloud-agent/config.toml)
+    #[arg(long, env = "CLOUD_AGENT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Shallow clone repos with this many commits
+    #[arg(long, env = "CLONE_DEPTH", value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: Option<u32>,
+
+    /// Partial clone filter (e.g. blob:none)
+    #[arg(long, env = "CLONE_FILTER")]
+    pub filter: Option<String>,
+
+    /// Comma-separated paths for sparse checkout
+    #[arg(long, env = "SPARSE_PATHS")]
+    pub sparse: Option<String>,
+
+    /// Local command printing an agent's API key, as AGENT=COMMAND (repeatable)
+    #[arg(long = "api-key-command", value_name = "AGENT=COMMAND")]
+    pub api_key_commands: Vec<String>,
+
+    /// How much agents may do unattended: read-only, edit or full. Written to
+    /// each agent's permission config on deploy; headless runs default to full
+    #[arg(long, global = true, env = "AUTONOMY", value_enum)]
+    pub autonomy: Option<Autonomy>,
+
+    /// How to reach the VM: public (external IP, SSH from your IP) or iap (no
+    /// external IP, SSH through an IAP tunnel). Defaults to the mode the VM was
+    /// created with, or public
+    #[arg(long, global = true, env = "NETWORK_MODE", value_enum)]
+    pub network_mode: Option<NetworkMode>,
+
+    /// Existing VPC network to attach the VM to instead of creating a
+    /// dedicated one (also `[network] name` in the config file)
+    #[arg(long, global = true, env = "NETWORK")]
+    pub network: Option<String>,
+
+    /// Subnet of --network in the VM's region (also `[network] subnet`)
+    #[arg(long, global = true, env = "SUBNET")]
+    pub subnet: Option<String>,
+
+    /// Reserve a static external IP, so the address survives stop/start
+    #[arg(long, global = true, env = "STATIC_IP")]
+    pub static_ip: bool,
+
+    /// Cloud DNS managed zone to add a `<vm>.<zone domain>` record to (implies
+    /// --static-ip)
+    #[arg(long, global = true, env = "DNS_ZONE")]
+    pub dns_zone: Option<String>,
+
+    /// Outbound access from the VM: open or restricted (allowlisted domains
+    /// only, through a proxy on the VM). Defaults to the VM's recorded mode, or
+    /// open; a restricted VM is only opened again with an explicit --egress open
+    #[arg(long, global = true, env = "EGRESS", value_enum)]
+    pub egress: Option<EgressMode>,
+
+    /// Extra domains for --egress restricted (comma-separated; `.example.com`
+    /// includes subdomains), remembered for the VM. Also `[egress] allow` in
+    /// the config file
+    #[arg(long, global = true, env = "EGRESS_ALLOW", value_delimiter = ',')]
+    pub egress_allow: Vec<String>,
+
+    /// Your public IP, skipping detection (offline, or when detection reports
+    /// the wrong address)
+    #[arg(long, global = true, env = "MY_IP")]
+    pub my_ip: Option<IpAddr>,
+
+    /// Address family of your public IP: auto (IPv4, falling back to IPv6), v4 or v6
+    #[arg(long, global = true, env = "IP_FAMILY", value_enum, default_value_t)]
+    pub ip_family: IpFamily,
+
+    /// Copy agent settings, memory files, custom commands and MCP servers to the VM
+    #[arg(long, global = true, env = "WITH_SETTINGS")]
+    pub with_settings: bool,
+
+    /// Model for Aider on the VM (e.g. sonnet, gpt-4o, gemini/gemini-2.5-pro)
+    #[arg(long, env = "AIDER_MODEL")]
+    pub aider_model: Option<String>,
+
+    /// Provider whose API key is stored on the VM for Aider: anthropic,
+    /// openai or gemini (other keys stay in ~/.aider.conf.yml)
+    #[arg(long, env = "AIDER_PROVIDER", value_enum)]
+    pub aider_provider: Option<AiderProvider>,
+
+    /// Authenticate agents that support it (gemini) with the VM's service
+    /// account via Vertex AI instead of copying local credentials
+    #[arg(long, env = "SERVICE_ACCOUNT_AUTH")]
+    pub service_account_auth: bool,
+
+    /// Command that runs the tests when comparing agents (overrides the config file)
+    #[arg(long, env = "TEST_COMMAND")]
+    pub test_command: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 
@@ -81,6 +258,9 @@ pub enum Command {
     /// Terminate (delete) the cloud-agent VM
     Terminate,
 
+    /// Show VM status and blocked push attempts
+    Status,
+
     /// SSH into the VM and attach to tmux session
     Ssh,
 
@@ -98,6 +278,82 @@ pub enum Command {
     /// Create VM (force creation even if it exists)
     CreateVm,
 
+    /// Open or update pull requests for agent branches pushed from the VM
+    Pr {
+        /// Only look at this repo in /workspace
+        #[arg(long)]
+        repo: Option<String>,
+        /// Open new pull requests as drafts
+        #[arg(long)]
+        draft: bool,
+        /// Labels to add to the pull requests
+        #[arg(long, value_delimiter = ',', default_value = "cloud-agent")]
+        labels: Vec<String>,
+    },
+
+    /// Queue tasks for the agent to work through on the VM
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+
+    /// Run the agent headless on one repo and collect the results locally
+    Run {
+        /// Repository (URL; a name in /workspace also works without --ephemeral)
+        #[arg(long)]
+        repo: String,
+        /// Prompt for the agent
+        prompt: String,
+        /// Create a VM just for this run and destroy it afterwards
+        #[arg(long)]
+        ephemeral: bool,
+        /// Run these agents side by side and compare them (e.g. claude,codex)
+        #[arg(long, value_delimiter = ',', conflicts_with = "ephemeral")]
+        agents: Vec<String>,
+        /// Directory for the transcript and patch (default: cloud-agent-runs/<id>)
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Push the run branch to origin
+        #[arg(long)]
+        push: bool,
+    },
+
+    /// Update ca to the latest (or a specific) GitHub release
+    SelfUpdate {
+        /// Release to install instead of the latest (e.g. 0.3.0)
+        #[arg(long)]
+        version: Option<String>,
+    },
+
+    /// Allow an IP to SSH to the VM (default: your current public IP)
+    AllowIp {
+        /// Address or CIDR range to allow
+        ip: Option<String>,
+
+        /// Drop every previously allowed address, including ranges added to
+        /// the firewall rule outside ca
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// Show the permission shortcuts accepted by --permissions
+    Permissions {
+        #[command(subcommand)]
+        command: PermissionsCommand,
+    },
+
+    /// Manage kubectl access to GKE clusters on the VM
+    Kube {
+        #[command(subcommand)]
+        command: KubeCommand,
+    },
+
+    /// Install or remove the cloud-agent skill for local agents
+    Skill {
+        #[command(subcommand)]
+        command: SkillCommand,
+    },
+
     /// Deploy repos to existing VM (skip VM creation)
     Deploy {
         /// Repository URLs to deploy
@@ -108,6 +364,57 @@ pub enum Command {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum PermissionsCommand {
+    /// List permission shortcuts and the IAM roles they grant
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KubeCommand {
+    /// Re-fetch cluster credentials on the VM (uses --cluster if given,
+    /// otherwise the clusters configured at deploy)
+    Refresh,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SkillCommand {
+    /// Install or update the skill (default: every agent set up in your home directory)
+    Install {
+        /// Agent to install the skill for (claude, auggie, codex, copilot)
+        #[arg(long = "agent", value_name = "AGENT")]
+        skill_agent: Option<String>,
+    },
+
+    /// Remove the skill (default: from every agent)
+    Uninstall {
+        /// Agent to remove the skill from (claude, auggie, codex, copilot)
+        #[arg(long = "agent", value_name = "AGENT")]
+        skill_agent: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum QueueCommand {
+    /// Add a task to the queue
+    Add {
+        /// Repository (name in /workspace or URL)
+        #[arg(long)]
+        repo: String,
+        /// Prompt for the agent
+        prompt: String,
+    },
+
+    /// List queued, running and finished tasks
+    List,
+
+    /// Cancel a queued or running task
+    Cancel {
+        /// Task ID (from 'ca queue list')
+        id: String,
+    },
+}
+
 impl Args {
     /// Parse arguments from command line
     pub fn parse() -> Self {
@@ -117,6 +424,45 @@ impl Args {
 
 /// Execute the command based on parsed arguments
 pub async fn execute(args: Args) -> Result<()> {
+    if let Some(Command::SelfUpdate { version }) = &args.command {
+        return update::self_update(version.as_deref()).await;
+    }
+    update::notify_if_outdated().await;
+
+    // Keep installed skills in step with this binary
+    refresh_skills();
+
+    // Skills are local-only and don't need gcloud or an agent login
+    if let Some(Command::Skill { command }) = &args.command {
+        return match command {
+            SkillCommand::Install { skill_agent } => install_skill(skill_agent.as_deref()),
+            SkillCommand::Uninstall { skill_agent } => uninstall_skill(skill_agent.as_deref()),
+        };
+    }
+
+    if let Some(Command::Permissions {
+        command: PermissionsCommand::List,
+    }) = &args.command
+    {
+        permissions::list();
+        return Ok(());
+    }
+
+    // Each compared agent gets its own branch, worktree and tmux window
+    if let Some(Command::Run { agents, .. }) = &args.command {
+        if let Some(agent) = agents
+            .iter()
+            .enumerate()
+            .find_map(|(i, agent)| agents[..i].contains(agent).then_some(agent))
+        {
+            return Err(CloudAgentError::ConfigError(format!(
+                "--agents lists '{}' more than once",
+                agent
+            ))
+            .into());
+        }
+    }
+
     // Load configuration
     let config = Config::from_args(&args)?;
 
@@ -132,6 +478,7 @@ pub async fn execute(args: Args) -> Result<()> {
         Some(Command::List) => vm_manager.list().await?,
         Some(Command::Start) => vm_manager.start().await?,
         Some(Command::Stop) => vm_manager.stop().await?,
+        Some(Command::Status) => vm_manager.status().await?,
         Some(Command::Terminate) => vm_manager.terminate().await?,
         Some(Command::Ssh) => vm_manager.ssh().await?,
         Some(Command::Scp { src, dst }) => vm_manager.scp(&src, &dst).await?,
@@ -139,6 +486,59 @@ pub async fn execute(args: Args) -> Result<()> {
         Some(Command::CreateVm) => {
             vm_manager.create_vm(true).await?;
         }
+        Some(Command::Pr {
+            repo,
+            draft,
+            labels,
+        }) => {
+            vm_manager.pr(repo.as_deref(), draft, &labels).await?;
+        }
+        Some(Command::Queue { command }) => match command {
+            QueueCommand::Add { repo, prompt } => {
+                let command = agent_manager.headless_command(&prompt);
+                vm_manager.queue_add(&repo, &prompt, command).await?;
+            }
+            QueueCommand::List => vm_manager.queue_list().await?,
+            QueueCommand::Cancel { id } => vm_manager.queue_cancel(&id).await?,
+        },
+        Some(Command::Run {
+            repo,
+            prompt,
+            ephemeral,
+            agents,
+            output,
+            push,
+        }) => {
+            let options = RunOptions { output, push };
+            if !agents.is_empty() {
+                let mut commands = Vec::new();
+                for agent in &agents {
+                    let manager = AgentManager::for_agent(agent, &config)?;
+                    manager.check_prerequisites().await?;
+                    commands.push((agent.clone(), manager.headless_command(&prompt)));
+                }
+                vm_manager
+                    .compare(&repo, &prompt, &commands, &options)
+                    .await?;
+            } else if ephemeral {
+                let command = agent_manager.headless_command(&prompt);
+                VmManager::run_ephemeral(&config, &repo, &prompt, &command, &options).await?;
+            } else {
+                let command = agent_manager.headless_command(&prompt);
+                vm_manager.run(&repo, &prompt, &command, &options).await?;
+            }
+        }
+        Some(Command::SelfUpdate { .. })
+        | Some(Command::Skill { .. })
+        | Some(Command::Permissions { .. }) => {
+            unreachable!("handled before loading configuration")
+        }
+        Some(Command::Kube {
+            command: KubeCommand::Refresh,
+        }) => vm_manager.kube_refresh().await?,
+        Some(Command::AllowIp { ip, replace }) => {
+            vm_manager.allow_ip(ip.as_deref(), replace).await?
+        }
         Some(Command::Deploy { repos, skip_creds }) => {
             vm_manager.deploy_repos(&repos, skip_creds).await?;
         }
@@ -157,3 +557,82 @@ pub async fn execute(args: Args) -> Result<()> {
 
     Ok(())
 }
+
+/// Update installed skills written by another version of `ca`
+fn refresh_skills() {
+    let Ok(paths) = agents::skill_paths(None) else {
+        return;
+    };
+    for (agent, path) in paths {
+        match skill::refresh(&path) {
+            Ok(Some(from)) => utils::log(&format!(
+                "Updated the {} skill for {} ({} -> {})",
+                skill::SKILL_NAME,
+                agent,
+                from,
+                env!("CARGO_PKG_VERSION")
+            )),
+            Ok(None) => {}
+            Err(e) => utils::log_warning(&format!(
+                "Could not update the {} skill for {}: {}",
+                skill::SKILL_NAME,
+                agent,
+                e
+            )),
+        }
+    }
+}
+
+/// Install the skill for `agent`, or for every agent whose config directory
+/// exists in the home directory
+fn install_skill(agent: Option<&str>) -> Result<()> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let paths: Vec<_> = agents::skill_paths(agent)?
+        .into_iter()
+        .filter(|(_, path)| {
+            agent.is_some()
+                || path
+                    .strip_prefix(&home)
+                    .ok()
+                    .and_then(|p| p.components().next())
+                    .is_some_and(|dir| home.join(dir).is_dir())
+        })
+        .collect();
+    if paths.is_empty() {
+        return Err(CloudAgentError::ConfigError(
+            "No agent configuration found in your home directory; use --agent".to_string(),
+        )
+        .into());
+    }
+
+    for (agent, path) in paths {
+        let message = match skill::install(&path)? {
+            skill::SkillStatus::Installed => "installed",
+            skill::SkillStatus::UpToDate => "already up to date",
+            skill::SkillStatus::Updated { .. } => "updated",
+        };
+        utils::log_success(&format!(
+            "{}: {} skill {} ({})",
+            agent,
+            skill::SKILL_NAME,
+            message,
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Remove the skill from `agent`, or from every agent
+fn uninstall_skill(agent: Option<&str>) -> Result<()> {
+    let mut removed = false;
+    for (agent, path) in agents::skill_paths(agent)? {
+        if skill::uninstall(&path)? {
+            utils::log_success(&format!("{}: removed {}", agent, path.display()));
+            removed = true;
+        }
+    }
+    if !removed {
+        utils::log(&format!("The {} skill is not installed", skill::SKILL_NAME));
+    }
+    Ok(())
+}
@@ -3,19 +3,206 @@
 //! This module handles loading and managing configuration from various sources:
 //! - Command-line arguments
 //! - Environment variables
+//! - Config file (`~/.config/cloud-agent/config.toml`)
 //! - Default values
 
 use anyhow::Result;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
+use crate::agents::{AiderProvider, Autonomy};
 use crate::cli::Args;
+use crate::credentials::GitAuth;
+use crate::egress::{self, EgressMode, EgressSettings};
 use crate::error::CloudAgentError;
+use crate::git::{self, CloneOptions, RepoSpec};
+use crate::git_hooks;
+use crate::kube::{self, GkeCluster};
+use crate::permissions;
+use crate::public_ip::{IpDetectionSettings, IpFamily};
+use crate::state::VmState;
+use crate::utils;
+
+/// How `ca` reaches the VM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkMode {
+    /// Ephemeral public IP, SSH allowed from whitelisted IPs
+    #[default]
+    Public,
+
+    /// No public IP; SSH through an IAP TCP tunnel, egress through Cloud NAT
+    Iap,
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            NetworkMode::Public => "public",
+            NetworkMode::Iap => "iap",
+        };
+        f.write_str(s)
+    }
+}
+
+/// `[network]` settings from the config file
+///
+/// ```toml
+/// [network]
+/// name = "shared-vpc"
+/// subnet = "agents-us-central1"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// Existing VPC network to attach the VM to
+    pub name: Option<String>,
+
+    /// Subnet of that network in the VM's region
+    pub subnet: Option<String>,
+}
+
+/// Settings read from the optional TOML config file
+///
+/// ```toml
+/// [clone]
+/// filter = "blob:none"
+///
+/// [repos.monorepo]
+/// depth = 1
+/// sparse = ["services/api", "libs/common"]
+/// test_command = "make test"
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileConfig {
+    /// Clone options applied to every repository
+    pub clone: CloneOptions,
+
+    /// Per-repository settings, keyed by repo name or URL
+    pub repos: HashMap<String, RepoConfig>,
+
+    /// Public IP detection endpoints and timeouts
+    pub ip_detection: IpDetectionSettings,
+
+    /// Existing network to use instead of a dedicated one
+    pub network: NetworkSettings,
+
+    /// Extra domains for restricted egress
+    pub egress: EgressSettings,
+}
+
+/// Settings for one repository in the config file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoConfig {
+    /// Clone options for this repository
+    #[serde(flatten)]
+    pub clone: CloneOptions,
+
+    /// Command that runs the repository's tests (used by `ca run --agents`)
+    pub test_command: Option<String>,
+}
+
+impl FileConfig {
+    /// Load the config file, if present.
+    ///
+    /// An explicitly requested path must exist; the default path is optional.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(p) => (p.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(p) => (p, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        if !path.exists() {
+            if explicit {
+                return Err(CloudAgentError::ConfigError(format!(
+                    "Config file not found: {}",
+                    path.display()
+                ))
+                .into());
+            }
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        toml::from_str(&content).map_err(|e| {
+            CloudAgentError::ConfigError(format!("Invalid config file {}: {}", path.display(), e))
+                .into()
+        })
+    }
+}
+
+/// Check `--network`/`--subnet` are valid GCP resource names and that a
+/// subnet comes with its network
+fn validate_network(network: Option<&str>, subnet: Option<&str>) -> Result<()> {
+    if subnet.is_some() && network.is_none() {
+        return Err(CloudAgentError::ConfigError(
+            "--subnet requires --network (the subnet's VPC network)".to_string(),
+        )
+        .into());
+    }
+
+    for name in network.iter().chain(subnet.iter()) {
+        if !is_valid_gcp_name(name) {
+            return Err(CloudAgentError::ConfigError(format!(
+                "Invalid network or subnet name '{}'",
+                name
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Check `--static-ip`/`--dns-zone`: an external address needs public
+/// network mode, and the DNS zone must be a valid managed zone name
+fn validate_static_ip(
+    static_ip: bool,
+    dns_zone: Option<&str>,
+    network_mode: NetworkMode,
+) -> Result<()> {
+    if static_ip && network_mode == NetworkMode::Iap {
+        return Err(CloudAgentError::ConfigError(
+            "--static-ip needs --network-mode public (IAP VMs have no external IP)".to_string(),
+        )
+        .into());
+    }
+    if let Some(zone) = dns_zone {
+        if !is_valid_gcp_name(zone) {
+            return Err(
+                CloudAgentError::ConfigError(format!("Invalid DNS zone name '{}'", zone)).into(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is a valid GCP resource name (`[a-z]([-a-z0-9]*[a-z0-9])?`)
+fn is_valid_gcp_name(name: &str) -> bool {
+    name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Default location of the config file
+fn default_config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|h| h.join(".config/cloud-agent/config.toml"))
+}
 
 /// Main configuration struct
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    /// Agent to use (auggie, claude, codex)
+    /// Agent to use (aider, auggie, claude, codex, gemini)
     pub agent: String,
 
     /// GCP project ID
@@ -42,11 +229,8 @@ pub struct Config {
     /// Skip deletion label
     pub skip_deletion: String,
 
-    /// GKE cluster name (optional)
-    pub cluster_name: Option<String>,
-
-    /// Cluster zone
-    pub cluster_zone: String,
+    /// GKE clusters to configure kubectl for
+    pub clusters: Vec<GkeCluster>,
 
     /// Path to SSH private key
     pub ssh_key: Option<PathBuf>,
@@ -54,6 +238,36 @@ pub struct Config {
     /// GitHub personal access token
     pub github_token: Option<String>,
 
+    /// Access tokens for Git hosts, keyed by host name
+    pub git_tokens: BTreeMap<String, String>,
+
+    /// How the VM authenticates to Git hosts over SSH
+    pub git_auth: GitAuth,
+
+    /// GitHub REST API URL
+    pub github_api_url: String,
+
+    /// Commit author name on the VM
+    pub git_user_name: String,
+
+    /// Commit author email on the VM
+    pub git_user_email: String,
+
+    /// SSH private key used to sign commits on the VM
+    pub signing_key: Option<PathBuf>,
+
+    /// Trailers appended to commits made on the VM
+    pub commit_trailers: Vec<String>,
+
+    /// Install the pre-push guard hook on the VM
+    pub push_guard: bool,
+
+    /// Branch globs agents may not push to
+    pub protected_branches: Vec<String>,
+
+    /// Branch globs agents may push to (empty allows any unprotected branch)
+    pub allowed_branches: Vec<String>,
+
     /// Permissions for VM service account
     pub permissions: Vec<String>,
 
@@ -62,6 +276,67 @@ pub struct Config {
 
     /// Company domain
     pub company: Option<String>,
+
+    /// Clone options applied to every repository
+    pub clone_options: CloneOptions,
+
+    /// Per-repository settings, keyed by repo name or URL
+    pub repo_options: HashMap<String, RepoConfig>,
+
+    /// Test command for agent comparisons, overriding per-repo settings
+    pub test_command: Option<String>,
+
+    /// Local commands printing agent API keys, keyed by agent name
+    pub api_key_commands: BTreeMap<String, String>,
+
+    /// Authenticate supporting agents with the VM's service account
+    pub service_account_auth: bool,
+
+    /// Model for Aider on the VM
+    pub aider_model: Option<String>,
+
+    /// Provider whose API key is stored on the VM for Aider
+    pub aider_provider: Option<AiderProvider>,
+
+    /// Copy agent settings bundles to the VM on deploy
+    pub with_settings: bool,
+
+    /// Permission level for unattended agents (`--autonomy`)
+    pub autonomy: Option<Autonomy>,
+
+    /// How the VM is reached (`--network-mode`)
+    pub network_mode: NetworkMode,
+
+    /// Existing VPC network for the VM (None creates a dedicated one)
+    pub network: Option<String>,
+
+    /// Subnet of the existing network
+    pub subnet: Option<String>,
+
+    /// Reserve a static external IP for the VM (`--static-ip`)
+    pub static_ip: bool,
+
+    /// Cloud DNS managed zone for a `<vm>.<zone domain>` record (`--dns-zone`)
+    pub dns_zone: Option<String>,
+
+    /// Outbound access from the VM (`--egress`, else as recorded for the VM).
+    /// `None` leaves a VM without a recorded mode as it is
+    pub egress: Option<EgressMode>,
+
+    /// Extra egress domains (`--egress-allow`, else as recorded for the VM)
+    pub egress_allow: Vec<String>,
+
+    /// Domains reachable with restricted egress
+    pub egress_allowlist: Vec<String>,
+
+    /// Public IP to allow instead of detecting it (`--my-ip`)
+    pub my_ip: Option<IpAddr>,
+
+    /// Address family of the detected public IP (`--ip-family`)
+    pub ip_family: IpFamily,
+
+    /// Public IP detection settings from the config file
+    pub ip_detection: IpDetectionSettings,
 }
 
 impl Config {
@@ -75,13 +350,33 @@ impl Config {
         let vm_name = derive_vm_name(&owner);
         let ssh_username = owner.replace('_', "-");
 
+        // Settings recorded when the VM was created apply unless overridden
+        let vm_state = VmState::load(&vm_name)?;
+        let network_mode = args
+            .network_mode
+            .or(vm_state.network_mode)
+            .unwrap_or_default();
+
         // Parse permissions
-        let permissions = args
+        let mut permissions: Vec<String> = args
             .permissions
             .as_ref()
-            .map(|p| p.split(',').map(|s| s.trim().to_string()).collect())
+            .map(|p| {
+                p.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
             .unwrap_or_default();
 
+        // Service account auth needs an attached service account with Vertex AI access
+        if args.service_account_auth && !permissions.iter().any(|p| p == "vertex") {
+            permissions.push("vertex".to_string());
+        }
+
+        // Reject typos before they become a VM without the access asked for
+        permissions::resolve(&permissions, &project_id)?;
+
         // Detect SSH key
         let ssh_key = args
             .ssh_key
@@ -89,6 +384,81 @@ impl Config {
             .map(PathBuf::from)
             .or_else(detect_ssh_key);
 
+        // Collect per-host Git tokens (GITHUB_TOKEN covers github.com)
+        let mut git_tokens = crate::credentials::parse_git_tokens(&args.git_tokens)?;
+        if let Some(token) = &args.github_token {
+            git_tokens
+                .entry(crate::credentials::DEFAULT_GIT_HOST.to_string())
+                .or_insert_with(|| token.clone());
+        }
+
+        // Commit identity, signing and trailers for the VM
+        let git_user_name = args
+            .git_name
+            .clone()
+            .or_else(|| git::local_config("user.name"))
+            .unwrap_or_else(|| "Cloud Agent".to_string());
+        let git_user_email = args
+            .git_email
+            .clone()
+            .or_else(|| git::local_config("user.email"))
+            .unwrap_or_else(|| "cloud-agent@localhost".to_string());
+        let signing_key = match &args.signing_key {
+            Some(key) => Some(key.clone()),
+            None if args.sign_commits => Some(detect_signing_key()?),
+            None => None,
+        };
+        let commit_trailers = if args.no_commit_trailers {
+            Vec::new()
+        } else {
+            resolve_trailers(&args.commit_trailers, &args.agent, &vm_name)?
+        };
+
+        // Push guard branch patterns
+        let protected_branches = non_empty(&args.protected_branches);
+        let allowed_branches = non_empty(&args.allowed_branches);
+        if !git_hooks::validate_branch_globs(&protected_branches)
+            || !git_hooks::validate_branch_globs(&allowed_branches)
+        {
+            return Err(CloudAgentError::ConfigError(
+                "Branch patterns may only contain letters, digits and . _ / * -".to_string(),
+            )
+            .into());
+        }
+
+        // Load config file and overlay CLI clone options
+        let file_config = FileConfig::load(args.config.as_deref())?;
+        // Existing network, command line first
+        let network = args.network.clone().or(file_config.network.name.clone());
+        let subnet = args.subnet.clone().or(file_config.network.subnet.clone());
+        validate_network(network.as_deref(), subnet.as_deref())?;
+
+        // A DNS record needs a stable address to point at. Both are kept
+        // for the VM's lifetime, so later commands find them without flags.
+        let dns_zone = args.dns_zone.clone().or(vm_state.dns_zone.clone());
+        let static_ip = args.static_ip || vm_state.static_ip || dns_zone.is_some();
+        validate_static_ip(static_ip, dns_zone.as_deref(), network_mode)?;
+
+        // Egress allowlist: command line and config file domains, plus defaults
+        let egress_allow = if args.egress_allow.is_empty() {
+            vm_state.egress_allow.clone()
+        } else {
+            args.egress_allow.clone()
+        };
+        let mut egress_domains = egress_allow.clone();
+        egress_domains.extend(file_config.egress.allow.iter().cloned());
+        let egress_allowlist = egress::allowlist(&egress_domains, file_config.egress.defaults)?;
+
+        let clone_options = file_config.clone.merge(&CloneOptions {
+            depth: args.depth,
+            filter: args.filter.clone(),
+            sparse: args
+                .sparse
+                .as_deref()
+                .map(git::parse_sparse_paths)
+                .unwrap_or_default(),
+        });
+
         Ok(Config {
             agent: args.agent.clone(),
             project_id,
@@ -99,19 +469,111 @@ impl Config {
             owner,
             ssh_username,
             skip_deletion: args.skip_deletion.clone(),
-            cluster_name: args.cluster_name.clone(),
-            cluster_zone: args
-                .cluster_name
-                .as_ref()
-                .map(|_| args.zone.clone())
-                .unwrap_or_else(|| args.zone.clone()),
+            clusters: kube::parse_clusters(
+                &args.clusters,
+                args.cluster_location.as_deref().unwrap_or(&args.zone),
+            )?,
             ssh_key,
             github_token: args.github_token.clone(),
+            git_tokens,
+            git_auth: args.git_auth,
+            github_api_url: args.github_api_url.clone(),
+            git_user_name,
+            git_user_email,
+            signing_key,
+            commit_trailers,
+            push_guard: !args.no_push_guard,
+            protected_branches,
+            allowed_branches,
             permissions,
             additional_ip: args.additional_ip.clone(),
             company: args.company.clone(),
+            clone_options,
+            repo_options: file_config.repos,
+            test_command: args.test_command.clone(),
+            api_key_commands: parse_api_key_commands(&args.api_key_commands)?,
+            service_account_auth: args.service_account_auth,
+            aider_model: args.aider_model.clone(),
+            aider_provider: args.aider_provider,
+            with_settings: args.with_settings,
+            autonomy: args.autonomy,
+            network_mode,
+            network,
+            subnet,
+            static_ip,
+            dns_zone,
+            egress: args.egress.or(vm_state.egress),
+            egress_allow,
+            egress_allowlist,
+            my_ip: args.my_ip,
+            ip_family: args.ip_family,
+            ip_detection: file_config.ip_detection,
+        })
+    }
+
+    /// Copy of this configuration for an ephemeral VM used by run `run_id`
+    pub fn for_ephemeral_run(&self, run_id: &str) -> Config {
+        // The VM's service account ID (`<vm_name>-sa`) is limited to 30 characters
+        let prefix: String = self
+            .ssh_username
+            .chars()
+            .take(22usize.saturating_sub(run_id.len()))
+            .collect();
+        let vm_name = format!("{}-run-{}", prefix.trim_end_matches('-'), run_id);
+        let commit_trailers = self
+            .commit_trailers
+            .iter()
+            .map(|t| t.replace(&self.vm_name, &vm_name))
+            .collect();
+
+        Config {
+            vm_name,
+            commit_trailers,
+            skip_deletion: "no".to_string(),
+            // Short-lived VMs don't need a stable address
+            static_ip: false,
+            dns_zone: None,
+            ..self.clone()
+        }
+    }
+
+    /// Token for github.com, from `--github-token` or `--git-token github.com=...`
+    pub fn github_token(&self) -> Option<&str> {
+        self.git_tokens
+            .get(crate::credentials::DEFAULT_GIT_HOST)
+            .map(String::as_str)
+    }
+
+    /// Per-repository settings for `repo` (a URL or repo name)
+    fn repo_config(&self, repo: &str) -> Option<&RepoConfig> {
+        self.repo_options.get(repo).or_else(|| {
+            let name = utils::extract_repo_name(repo).ok()?;
+            self.repo_options.get(&name)
+        })
+    }
+
+    /// Resolve the clone options for a repository URL
+    pub fn repo_spec(&self, url: &str) -> Result<RepoSpec> {
+        let name = utils::extract_repo_name(url)?;
+        let options = match self.repo_config(url) {
+            Some(repo) => self.clone_options.merge(&repo.clone),
+            None => self.clone_options.clone(),
+        };
+
+        Ok(RepoSpec {
+            url: url.to_string(),
+            name,
+            options,
+            ssh_command: None,
         })
     }
+
+    /// Test command for `repo` (a URL or repo name), if one is configured
+    pub fn test_command(&self, repo: &str) -> Option<String> {
+        self.test_command
+            .clone()
+            .or_else(|| self.repo_config(repo)?.test_command.clone())
+    }
 }
 
 /// Get GCP project ID from gcloud config
@@ -159,6 +621,114 @@ fn derive_vm_name(owner: &str) -> String {
     format!("{}-cloud-agent", owner.replace('_', "-"))
 }
 
+/// Trim list values and drop empty entries
+fn non_empty(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Parse `AGENT=COMMAND` values of `--api-key-command`
+fn parse_api_key_commands(values: &[String]) -> Result<BTreeMap<String, String>> {
+    let mut commands = BTreeMap::new();
+    for value in values {
+        let (agent, command) = value
+            .split_once('=')
+            .map(|(a, c)| (a.trim(), c.trim()))
+            .filter(|(a, c)| !a.is_empty() && !c.is_empty())
+            .ok_or_else(|| {
+                CloudAgentError::ConfigError(format!(
+                    "Invalid --api-key-command '{}', expected AGENT=COMMAND",
+                    value
+                ))
+            })?;
+
+        if !crate::agents::list_agents().contains(&agent) {
+            return Err(CloudAgentError::AgentNotFound(
+                agent.to_string(),
+                crate::agents::list_agents().join(", "),
+            )
+            .into());
+        }
+        commands.insert(agent.to_string(), command.to_string());
+    }
+    Ok(commands)
+}
+
+/// Trailers added to VM commits when none are configured
+const DEFAULT_TRAILERS: [&str; 2] = ["Agent: {agent}", "Cloud-Agent-VM: {vm_name}"];
+
+/// Validate commit trailers and expand `{agent}` / `{vm_name}` placeholders
+fn resolve_trailers(trailers: &[String], agent: &str, vm_name: &str) -> Result<Vec<String>> {
+    let trailers: Vec<String> = if trailers.is_empty() {
+        DEFAULT_TRAILERS.iter().map(|t| t.to_string()).collect()
+    } else {
+        trailers.to_vec()
+    };
+
+    trailers
+        .iter()
+        .map(|t| {
+            let (key, value) = t.split_once(':').ok_or_else(|| {
+                CloudAgentError::ConfigError(format!(
+                    "Invalid commit trailer '{}'. Expected 'Key: value'",
+                    t
+                ))
+            })?;
+            let value = value
+                .trim()
+                .replace("{agent}", agent)
+                .replace("{vm_name}", vm_name);
+            Ok(format!("{}: {}", key.trim(), value))
+        })
+        .collect()
+}
+
+/// Find the private key behind the local SSH commit-signing setup
+fn detect_signing_key() -> Result<PathBuf> {
+    let unsupported = |msg: &str| -> anyhow::Error {
+        CloudAgentError::ConfigError(format!(
+            "{}. Use --signing-key to pick a dedicated SSH signing key.",
+            msg
+        ))
+        .into()
+    };
+
+    if git::local_config("gpg.format").as_deref() != Some("ssh") {
+        return Err(unsupported(
+            "Only SSH commit signing (gpg.format=ssh) can be forwarded",
+        ));
+    }
+
+    let signing_key = git::local_config("user.signingkey")
+        .ok_or_else(|| unsupported("user.signingkey is not set"))?;
+    if signing_key.starts_with("key::") || signing_key.starts_with("ssh-") {
+        return Err(unsupported(
+            "user.signingkey is a literal public key, so its private key can't be located",
+        ));
+    }
+
+    let path = match signing_key.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()
+            .ok_or_else(|| unsupported("Could not determine home directory"))?
+            .join(rest),
+        None => PathBuf::from(&signing_key),
+    };
+    let private = if path.extension().is_some_and(|e| e == "pub") {
+        path.with_extension("")
+    } else {
+        path
+    };
+
+    if !private.exists() {
+        return Err(CloudAgentError::SshKeyNotFound(private.display().to_string()).into());
+    }
+
+    Ok(private)
+}
+
 /// Detect SSH key from common locations
 fn detect_ssh_key() -> Option<PathBuf> {
     let home = dirs::home_dir()?;
@@ -173,3 +743,72 @@ fn detect_ssh_key() -> Option<PathBuf> {
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_trailers() {
+        let trailers = resolve_trailers(&[], "claude", "jdoe-cloud-agent").unwrap();
+        assert_eq!(
+            trailers,
+            vec!["Agent: claude", "Cloud-Agent-VM: jdoe-cloud-agent"]
+        );
+
+        let trailers =
+            resolve_trailers(&["Reviewed-by:  {agent} ".to_string()], "codex", "vm").unwrap();
+        assert_eq!(trailers, vec!["Reviewed-by: codex"]);
+
+        assert!(resolve_trailers(&["no colon".to_string()], "codex", "vm").is_err());
+    }
+
+    #[test]
+    fn test_parse_api_key_commands() {
+        let commands =
+            parse_api_key_commands(&["claude=op read op://dev/anthropic/key".to_string()]).unwrap();
+        assert_eq!(commands["claude"], "op read op://dev/anthropic/key");
+
+        assert!(parse_api_key_commands(&["claude".to_string()]).is_err());
+        assert!(parse_api_key_commands(&["nope=echo".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_repo_config_from_toml() {
+        let config: FileConfig = toml::from_str(
+            r#"
+[repos.api]
+depth = 1
+test_command = "cargo test"
+"#,
+        )
+        .unwrap();
+
+        let api = &config.repos["api"];
+        assert_eq!(api.clone.depth, Some(1));
+        assert_eq!(api.test_command.as_deref(), Some("cargo test"));
+    }
+
+    #[test]
+    fn test_validate_network() {
+        assert!(validate_network(None, None).is_ok());
+        assert!(validate_network(Some("shared-vpc"), None).is_ok());
+        assert!(validate_network(Some("shared-vpc"), Some("agents-1")).is_ok());
+        assert!(validate_network(None, Some("agents-1")).is_err());
+        assert!(validate_network(Some("Shared_VPC"), None).is_err());
+        assert!(validate_network(Some("vpc-"), None).is_err());
+        assert!(validate_network(Some("1vpc"), None).is_err());
+
+        let config: FileConfig = toml::from_str("[network]\nname = \"shared-vpc\"\n").unwrap();
+        assert_eq!(config.network.name.as_deref(), Some("shared-vpc"));
+        assert_eq!(config.network.subnet, None);
+    }
+
+    #[test]
+    fn test_validate_static_ip() {
+        assert!(validate_static_ip(false, None, NetworkMode::Iap).is_ok());
+        assert!(validate_static_ip(true, Some("agents"), NetworkMode::Public).is_ok());
+        assert!(validate_static_ip(true, None, NetworkMode::Iap).is_err());
+        assert!(validate_static_ip(true, Some("agents.example.com"), NetworkMode::Public).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Restricted egress
+//!
+//! With `--egress restricted` the VPC firewall only lets the VM connect out
+//! on ports 22, 80 and 443, and the VM's startup script installs a squid
+//! forward proxy that only forwards to allowlisted domains, on every boot and
+//! before anything else runs. Local iptables rules reject
+//! connections from other users that bypass the proxy, and the SSH user loses
+//! sudo so an agent can't become root to get around them. Later changes to
+//! the allowlist reach the VM through instance metadata, which only the GCP
+//! API can write. Requests the proxy refused are shown by `ca status`, so the
+//! allowlist can be tuned.
+//!
+//! ```toml
+//! [egress]
+//! allow = ["registry.example.com", ".internal.example.com"]
+//! defaults = true
+//! ```
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::CloudAgentError;
+use crate::utils::shell_quote;
+
+/// Proxy and firewall setup script, embedded so deploy can install it on VMs
+/// created before restricted egress was chosen (the startup script installs
+/// it on the others)
+const EGRESS_SCRIPT: &str = include_str!("scripts/egress-proxy.sh");
+
+/// Where the script is installed on the VM
+pub const SCRIPT_PATH: &str = "/usr/local/sbin/cloud-agent-egress";
+
+/// Allowlist read by the proxy, one domain per line
+pub const ALLOWLIST_FILE: &str = "/etc/cloud-agent/egress-allowlist";
+
+/// Proxy variables for systemd services, present while egress is restricted
+pub const PROXY_ENV_FILE: &str = "/etc/cloud-agent/proxy.env";
+
+/// System service that keeps the firewall up and follows the metadata
+pub const SERVICE_NAME: &str = "cloud-agent-egress";
+
+/// Instance metadata keys for the mode and the space-separated allowlist
+pub const MODE_KEY: &str = "cloud-agent-egress";
+pub const ALLOWLIST_KEY: &str = "cloud-agent-egress-allowlist";
+
+/// Domains agents need out of the box: Git hosting, package registries, the
+/// OS and tool repositories used by the startup script, and model provider
+/// APIs. A leading dot also matches subdomains.
+pub const DEFAULT_ALLOWED_DOMAINS: &[&str] = &[
+    // Git hosting
+    ".github.com",
+    ".githubusercontent.com",
+    // Package registries
+    ".npmjs.org",
+    ".yarnpkg.com",
+    ".pypi.org",
+    "files.pythonhosted.org",
+    ".crates.io",
+    "proxy.golang.org",
+    "sum.golang.org",
+    // OS (Ubuntu, including the GCE regional mirrors) and tool repositories
+    ".archive.ubuntu.com",
+    "security.ubuntu.com",
+    "deb.nodesource.com",
+    "packages.cloud.google.com",
+    "dl.k8s.io",
+    // Model providers
+    ".anthropic.com",
+    ".openai.com",
+    ".augmentcode.com",
+    // Google APIs (gcloud, GKE, Gemini)
+    ".googleapis.com",
+    "accounts.google.com",
+];
+
+/// Outbound access from the VM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EgressMode {
+    /// All outbound traffic allowed
+    #[default]
+    Open,
+
+    /// Only allowlisted domains, through a proxy on the VM
+    Restricted,
+}
+
+impl std::fmt::Display for EgressMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            EgressMode::Open => "open",
+            EgressMode::Restricted => "restricted",
+        };
+        f.write_str(s)
+    }
+}
+
+/// `[egress]` settings from the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EgressSettings {
+    /// Domains allowed in addition to the defaults
+    pub allow: Vec<String>,
+
+    /// Include [`DEFAULT_ALLOWED_DOMAINS`]
+    pub defaults: bool,
+}
+
+impl Default for EgressSettings {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            defaults: true,
+        }
+    }
+}
+
+/// Normalise an allowlist entry: lowercase, `*.example.com` becomes
+/// `.example.com` (the domain and its subdomains)
+pub fn normalize_domain(value: &str) -> Result<String> {
+    let domain = value.trim().to_ascii_lowercase();
+    let domain = match domain.strip_prefix("*.") {
+        Some(rest) => format!(".{}", rest),
+        None => domain,
+    };
+
+    let name = domain.strip_prefix('.').unwrap_or(&domain);
+    let valid = name.contains('.')
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        });
+    if !valid {
+        return Err(CloudAgentError::ConfigError(format!(
+            "Invalid egress domain '{}' (expected example.com or .example.com)",
+            value
+        ))
+        .into());
+    }
+    Ok(domain)
+}
+
+/// The allowlist: `extra` domains plus the defaults when `defaults` is set,
+/// sorted, without entries already covered by a `.domain` wildcard (squid
+/// rejects overlapping entries)
+pub fn allowlist(extra: &[String], defaults: bool) -> Result<Vec<String>> {
+    let defaults = DEFAULT_ALLOWED_DOMAINS
+        .iter()
+        .filter(|_| defaults)
+        .map(|d| d.to_string());
+    let mut domains = Vec::new();
+    for value in extra.iter().cloned().chain(defaults) {
+        let domain = normalize_domain(&value)?;
+        if !domains.contains(&domain) {
+            domains.push(domain);
+        }
+    }
+
+    let wildcards: Vec<String> = domains
+        .iter()
+        .filter(|d| d.starts_with('.'))
+        .cloned()
+        .collect();
+    domains.retain(|domain| {
+        !wildcards.iter().any(|wildcard| {
+            domain != wildcard && format!(".{}", domain.trim_start_matches('.')).ends_with(wildcard)
+        })
+    });
+    domains.sort();
+    Ok(domains)
+}
+
+/// Instance metadata for the mode and allowlist, as a `gcloud compute
+/// instances add-metadata --metadata` value. The VM applies changes to it
+/// once restricted egress is enabled.
+pub fn metadata(mode: EgressMode, domains: &[String]) -> String {
+    format!(
+        "{}={},{}={}",
+        MODE_KEY,
+        mode,
+        ALLOWLIST_KEY,
+        domains.join(" ")
+    )
+}
+
+/// Shell command printing `yes` when restricted egress is active on the VM
+pub fn active_command() -> String {
+    format!(
+        "systemctl is-active --quiet {} && echo yes || true",
+        SERVICE_NAME
+    )
+}
+
+/// Shell command that waits (up to fifteen minutes) for the VM's startup
+/// script to finish, so it has had the chance to enable restricted egress
+pub fn startup_wait_command() -> String {
+    "for i in $(seq 180); do \
+         [ \"$(systemctl show -p ActiveState --value google-startup-scripts.service)\" = activating ] || exit 0; \
+         sleep 5; \
+     done; exit 1"
+        .to_string()
+}
+
+/// Shell command that installs the script and enables restricted egress.
+/// It needs sudo, which enabling revokes; later changes go through metadata.
+pub fn install_script() -> String {
+    format!(
+        "printf '%s' {script} | sudo tee {path} > /dev/null && sudo chmod 755 {path} && \
+         sudo {path} restricted",
+        script = shell_quote(EGRESS_SCRIPT),
+        path = SCRIPT_PATH,
+    )
+}
+
+/// Shell command that waits (up to two minutes) until the VM has applied
+/// `mode` and `domains` from metadata
+pub fn wait_script(mode: EgressMode, domains: &[String]) -> String {
+    let check = match mode {
+        EgressMode::Restricted => format!(
+            "[ \"$(cat {} 2>/dev/null)\" = {} ]",
+            ALLOWLIST_FILE,
+            shell_quote(&domains.join("\n"))
+        ),
+        EgressMode::Open => format!("! systemctl is-active --quiet {}", SERVICE_NAME),
+    };
+    format!(
+        "for i in $(seq 60); do {} && exit 0; sleep 2; done; exit 1",
+        check
+    )
+}
+
+/// Shell command listing requests refused by the proxy (empty without one)
+pub fn denied_command() -> String {
+    format!(
+        "if [ -x {path} ]; then {path} denied; fi 2>/dev/null || true",
+        path = SCRIPT_PATH
+    )
+}
+
+/// A destination the proxy refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeniedHost {
+    pub host: String,
+    pub count: usize,
+
+    /// Unix time of the most recent refusal
+    pub last_seen: i64,
+}
+
+/// Summarise [`denied_command`] output (`TIMESTAMP URL` lines) per host,
+/// most frequently denied first
+pub fn summarize_denied(output: &str) -> Vec<DeniedHost> {
+    let mut hosts: BTreeMap<String, DeniedHost> = BTreeMap::new();
+    for line in output.lines() {
+        let Some((time, url)) = line.trim().split_once(' ') else {
+            continue;
+        };
+        let Some(host) = url_host(url) else {
+            continue;
+        };
+        let time = time.parse::<f64>().map(|t| t as i64).unwrap_or(0);
+        let entry = hosts.entry(host.clone()).or_insert(DeniedHost {
+            host,
+            count: 0,
+            last_seen: 0,
+        });
+        entry.count += 1;
+        entry.last_seen = entry.last_seen.max(time);
+    }
+
+    let mut denied: Vec<DeniedHost> = hosts.into_values().collect();
+    denied.sort_by(|a, b| b.count.cmp(&a.count).then(a.host.cmp(&b.host)));
+    denied
+}
+
+/// Host of a squid log URL: `http://host/path` or `host:port` (CONNECT)
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    let authority = rest.split('/').next()?;
+    let host = match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next()?,
+        None => authority.split(':').next()?,
+    };
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(normalize_domain("GitHub.com").unwrap(), "github.com");
+        assert_eq!(normalize_domain("*.example.com").unwrap(), ".example.com");
+        assert_eq!(normalize_domain(".example.com").unwrap(), ".example.com");
+        assert!(normalize_domain("localhost").is_err());
+        assert!(normalize_domain("https://example.com").is_err());
+        assert!(normalize_domain("-bad.com").is_err());
+        assert!(normalize_domain("a..com").is_err());
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let domains = allowlist(
+            &[
+                "api.github.com".to_string(),
+                "example.com".to_string(),
+                ".example.com".to_string(),
+                "registry.example.org".to_string(),
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            domains,
+            vec![".example.com", "api.github.com", "registry.example.org"]
+        );
+
+        let defaults = allowlist(&["api.github.com".to_string()], true).unwrap();
+        assert!(defaults.contains(&".github.com".to_string()));
+        assert!(!defaults.contains(&"api.github.com".to_string()));
+        assert!(allowlist(&["not a domain".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn test_metadata_and_wait_script() {
+        let domains = vec![".github.com".to_string(), "pypi.org".to_string()];
+        assert_eq!(
+            metadata(EgressMode::Restricted, &domains),
+            "cloud-agent-egress=restricted,cloud-agent-egress-allowlist=.github.com pypi.org"
+        );
+
+        let wait = wait_script(EgressMode::Restricted, &domains);
+        assert!(wait.contains(
+            "[ \"$(cat /etc/cloud-agent/egress-allowlist 2>/dev/null)\" = '.github.com\npypi.org' ]"
+        ));
+        let open = wait_script(EgressMode::Open, &[]);
+        assert!(open.contains("! systemctl is-active --quiet cloud-agent-egress && exit 0"));
+    }
+
+    #[test]
+    fn test_startup_wait_command() {
+        let wait = startup_wait_command();
+        assert!(wait.contains("google-startup-scripts.service)\" = activating ] || exit 0"));
+        assert!(wait.ends_with("exit 1"));
+    }
+
+    #[test]
+    fn test_summarize_denied() {
+        let output = "1760000000.123 evil.example.com:443\n\
+                      1760000100.500 http://Evil.example.com/upload\n\
+                      1760000050.000 pastebin.com:443\n\
+                      1760000060.000 [2001:db8::1]:443\n\
+                      garbage\n";
+        let denied = summarize_denied(output);
+        assert_eq!(
+            denied[0],
+            DeniedHost {
+                host: "evil.example.com".to_string(),
+                count: 2,
+                last_seen: 1760000100,
+            }
+        );
+        assert_eq!(denied[1].host, "2001:db8::1");
+        assert_eq!(denied[2].host, "pastebin.com");
+        assert_eq!(denied.len(), 3);
+    }
+}
@@ -21,7 +21,7 @@ pub enum CloudAgentError {
     #[error("Agent '{0}' not found. Available agents: {1}")]
     AgentNotFound(String, String),
 
-    #[error("Failed to detect public IP address")]
+    #[error("Failed to detect public IP address (set it with --my-ip)")]
     IpDetectionFailed,
 
     #[error("GCP project not configured. Run: gcloud config set project PROJECT_ID")]
@@ -42,6 +42,9 @@ pub enum CloudAgentError {
     #[error("Invalid repository URL: {0}")]
     InvalidRepoUrl(String),
 
+    #[error("GitHub API request failed: {0}")]
+    GitHubApi(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
@@ -4,15 +4,42 @@
 
 use anyhow::Result;
 use std::fs;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::process::Command;
-
-use crate::config::Config;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use crate::agents::{self, Autonomy};
+use crate::allowlist;
+use crate::config::{Config, NetworkMode};
+use crate::credentials::{self, GitAuth, GitHost};
+use crate::egress::{self, EgressMode};
 use crate::error::CloudAgentError;
+use crate::git_hooks;
+use crate::github::{self, GitHubClient};
+use crate::kube::{self, GkeCluster};
+use crate::permissions::{self, Grant, ResourceKind};
+use crate::pr;
+use crate::public_ip;
+use crate::queue::{self, Task};
+use crate::run::{self, AgentDone, ComparisonSummary, RunOptions, RunSummary};
+use crate::settings;
 use crate::ssh::SshClient;
+use crate::state::{DeployKeyRecord, VmState};
 use crate::utils;
 
+/// Files copied into the terraform directory of an ephemeral VM
+const TERRAFORM_FILES: [&str; 5] = [
+    "main.tf",
+    "variables.tf",
+    "startup-script.sh",
+    "src/scripts/egress-proxy.sh",
+    ".terraform.lock.hcl",
+];
+
 /// VM manager for GCP operations
 pub struct VmManager {
     config: Config,
@@ -105,6 +132,11 @@ impl VmManager {
             return Ok(());
         }
 
+        self.destroy().await
+    }
+
+    /// Destroy the VM and its resources without asking for confirmation
+    async fn destroy(&self) -> Result<()> {
         let tfstate_path = self.script_dir.join("terraform.tfstate");
         if tfstate_path.exists() {
             utils::log("Running terraform destroy...");
@@ -136,6 +168,90 @@ impl VmManager {
             }
 
             utils::log_success("VM terminated");
+            self.release_static_address();
+        }
+
+        self.remove_deploy_keys().await?;
+
+        // A new VM with this name starts from its own settings
+        let state = VmState::load(&self.config.vm_name)?.forget_vm();
+        if state.is_empty() {
+            VmState::remove(&self.config.vm_name)?;
+        } else {
+            state.save(&self.config.vm_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Show VM status and blocked push attempts
+    pub async fn status(&self) -> Result<()> {
+        utils::log(&format!("Status of VM: {}...", self.config.vm_name));
+
+        let output = Command::new("gcloud")
+            .args([
+                "compute",
+                "instances",
+                "describe",
+                &self.config.vm_name,
+                &format!("--zone={}", self.config.zone),
+                "--format=value(status)",
+            ])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(CloudAgentError::VmNotFound(self.config.vm_name.clone()).into());
+        }
+
+        let status = String::from_utf8(output.stdout)?.trim().to_string();
+        utils::log(&format!("   Status: {}", status));
+        if status != "RUNNING" {
+            return Ok(());
+        }
+
+        let vm_ip = self.get_vm_ip().await?;
+        utils::log(&format!("   External IP: {}", vm_ip));
+        if let Some(dns_name) = self.dns_name() {
+            utils::log(&format!("   DNS name: {}", dns_name));
+        }
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+
+        utils::log("");
+        let blocked = ssh_client.execute(&format!(
+            "tail -n 20 {} 2>/dev/null || true",
+            git_hooks::BLOCKED_PUSH_LOG
+        ))?;
+        if blocked.is_empty() {
+            utils::log_success("No blocked push attempts");
+        } else {
+            utils::log_warning("Blocked push attempts (most recent last):");
+            for line in blocked.lines() {
+                let fields: Vec<&str> = line.split('\t').collect();
+                if let [time, repo, branch, reason] = fields[..] {
+                    println!("  {}  {}  {}  ({})", time, repo, branch, reason);
+                } else {
+                    println!("  {}", line);
+                }
+            }
+        }
+
+        let denied = egress::summarize_denied(&ssh_client.execute(&egress::denied_command())?);
+        if denied.is_empty() {
+            if self.config.egress == Some(EgressMode::Restricted) {
+                utils::log_success("No denied egress requests");
+            }
+        } else {
+            utils::log_warning("Denied egress destinations (allow with --egress-allow):");
+            for host in denied {
+                let last_seen = chrono::DateTime::from_timestamp(host.last_seen, 0)
+                    .map(|t| {
+                        t.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                println!("  {:>5}  {}  (last {})", host.count, host.host, last_seen);
+            }
         }
 
         Ok(())
@@ -144,6 +260,7 @@ impl VmManager {
     /// SSH into the VM
     pub async fn ssh(&self) -> Result<()> {
         let vm_ip = self.get_vm_ip().await?;
+        self.ensure_ssh_reachable(&vm_ip).await?;
         let ssh_client = SshClient::new(self.config.clone(), vm_ip);
         ssh_client.interactive_session()?;
         Ok(())
@@ -166,13 +283,115 @@ impl VmManager {
         Ok(())
     }
 
+    /// Name of the VM's reserved external address (`--static-ip`)
+    fn static_address_name(&self) -> String {
+        format!("{}-ip", self.config.vm_name)
+    }
+
+    /// The reserved external address, if there is one
+    fn static_address(&self) -> Option<String> {
+        let output = Command::new("gcloud")
+            .args([
+                "compute",
+                "addresses",
+                "describe",
+                &self.static_address_name(),
+                &format!("--region={}", self.config.region),
+                "--format=value(address)",
+            ])
+            .output()
+            .ok()?;
+        let ip = String::from_utf8(output.stdout).ok()?.trim().to_string();
+        (output.status.success() && !ip.is_empty()).then_some(ip)
+    }
+
+    /// The VM's DNS name with `--dns-zone` (`<vm>.<zone domain>`)
+    fn dns_name(&self) -> Option<String> {
+        let zone = self.config.dns_zone.as_ref()?;
+        let output = Command::new("gcloud")
+            .args([
+                "dns",
+                "managed-zones",
+                "describe",
+                zone,
+                "--format=value(dnsName)",
+            ])
+            .output()
+            .ok()?;
+        let domain = String::from_utf8(output.stdout).ok()?;
+        let domain = domain.trim().trim_end_matches('.');
+        (output.status.success() && !domain.is_empty())
+            .then(|| format!("{}.{}", self.config.vm_name, domain))
+    }
+
+    /// Release the reserved address and DNS record when the VM was deleted
+    /// without terraform
+    fn release_static_address(&self) {
+        if let Some(dns_name) = self.dns_name() {
+            let deleted = Command::new("gcloud")
+                .args([
+                    "dns",
+                    "record-sets",
+                    "delete",
+                    &format!("{}.", dns_name),
+                    "--type=A",
+                    &format!(
+                        "--zone={}",
+                        self.config.dns_zone.as_deref().unwrap_or_default()
+                    ),
+                    "--quiet",
+                ])
+                .status()
+                .is_ok_and(|s| s.success());
+            if deleted {
+                utils::log_success(&format!("DNS record {} removed", dns_name));
+            } else {
+                utils::log_warning(&format!("Could not remove DNS record {}", dns_name));
+            }
+        }
+
+        if self.static_address().is_some() {
+            let released = Command::new("gcloud")
+                .args([
+                    "compute",
+                    "addresses",
+                    "delete",
+                    &self.static_address_name(),
+                    &format!("--region={}", self.config.region),
+                    "--quiet",
+                ])
+                .status()
+                .is_ok_and(|s| s.success());
+            if released {
+                utils::log_success("Static IP released");
+            } else {
+                utils::log_warning(&format!(
+                    "Could not release static IP {} (billed while reserved)",
+                    self.static_address_name()
+                ));
+            }
+        }
+    }
+
     /// Get VM IP address
     async fn get_vm_ip(&self) -> Result<String> {
+        // IAP VMs only have an internal IP; SshClient tunnels to them by name
+        let (output_name, ip_format) = match self.config.network_mode {
+            NetworkMode::Public => (
+                "cloud_agent_ip",
+                "value(networkInterfaces[0].accessConfigs[0].natIP)",
+            ),
+            NetworkMode::Iap => (
+                "cloud_agent_internal_ip",
+                "value(networkInterfaces[0].networkIP)",
+            ),
+        };
+
         // Try terraform state first
         let tfstate_path = self.script_dir.join("terraform.tfstate");
         if tfstate_path.exists() {
             let output = Command::new("terraform")
-                .args(["output", "-raw", "cloud_agent_ip"])
+                .args(["output", "-raw", output_name])
                 .current_dir(&self.script_dir)
                 .output()?;
 
@@ -184,7 +403,12 @@ impl VmManager {
             }
         }
 
-        // Fallback to gcloud
+        // Fallback to gcloud: the reserved address, then the instance
+        if self.config.static_ip {
+            if let Some(ip) = self.static_address() {
+                return Ok(ip);
+            }
+        }
         let output = Command::new("gcloud")
             .args([
                 "compute",
@@ -192,7 +416,7 @@ impl VmManager {
                 "describe",
                 &self.config.vm_name,
                 &format!("--zone={}", self.config.zone),
-                "--format=value(networkInterfaces[0].accessConfigs[0].natIP)",
+                &format!("--format={}", ip_format),
             ])
             .output()?;
 
@@ -202,7 +426,14 @@ impl VmManager {
 
         let ip = String::from_utf8(output.stdout)?.trim().to_string();
         if ip.is_empty() {
-            return Err(anyhow::anyhow!("Could not determine VM IP address"));
+            return Err(match self.config.network_mode {
+                NetworkMode::Public => anyhow::anyhow!(
+                    "VM {} has no external IP. If it was created with --network-mode iap, \
+                     pass --network-mode iap (or set NETWORK_MODE=iap)",
+                    self.config.vm_name
+                ),
+                NetworkMode::Iap => anyhow::anyhow!("Could not determine VM IP address"),
+            });
         }
 
         Ok(ip)
@@ -252,7 +483,7 @@ impl VmManager {
         }
 
         // Generate terraform.tfvars
-        self.generate_tfvars().await?;
+        let state = self.generate_tfvars().await?;
 
         // Apply terraform
         utils::log("");
@@ -265,6 +496,7 @@ impl VmManager {
         if !status.success() {
             return Err(CloudAgentError::TerraformFailed("apply failed".to_string()).into());
         }
+        state.save(&self.config.vm_name)?;
 
         utils::log("");
         utils::log_success("Terraform apply complete!");
@@ -284,7 +516,7 @@ impl VmManager {
         utils::print_header("🐕 CREATING CLOUD AGENT VM");
 
         // Generate terraform.tfvars
-        self.generate_tfvars().await?;
+        let state = self.generate_tfvars().await?;
 
         // Initialize terraform
         utils::log("");
@@ -312,6 +544,8 @@ impl VmManager {
         if !status.success() {
             return Err(CloudAgentError::TerraformFailed("apply failed".to_string()).into());
         }
+        state.save(&self.config.vm_name)?;
+        self.record_vm_settings()?;
 
         let vm_ip = self.get_vm_ip().await?;
         utils::log("");
@@ -326,25 +560,79 @@ impl VmManager {
         Ok(())
     }
 
-    /// Generate terraform.tfvars file
-    async fn generate_tfvars(&self) -> Result<()> {
+    /// Remember the settings later commands need to reach the VM
+    fn record_vm_settings(&self) -> Result<()> {
+        let mut state = VmState::load(&self.config.vm_name)?;
+        state.network_mode = Some(self.config.network_mode);
+        state.egress = Some(self.config.egress.unwrap_or_default());
+        state.egress_allow = self.config.egress_allow.clone();
+        state.static_ip = self.config.static_ip;
+        state.dns_zone = self.config.dns_zone.clone();
+        state.save(&self.config.vm_name)
+    }
+
+    /// Generate terraform.tfvars file. Returns the VM state with the SSH
+    /// allowlist it uses, to save once Terraform has applied it.
+    async fn generate_tfvars(&self) -> Result<VmState> {
         utils::log("Generating terraform.tfvars...");
 
         // Detect public IP
-        let allowed_ips = self.get_allowed_ips().await?;
+        let (state, allowed_ips) = self.get_allowed_ips().await?;
 
         // Get SSH public key if available
         let (ssh_username, ssh_public_key) = self.get_ssh_config()?;
 
-        // Format permissions as Terraform list
-        let permissions_tf = if self.config.permissions.is_empty() {
-            "[]".to_string()
-        } else {
-            format!("[\"{}\"]", self.config.permissions.join("\", \""))
-        };
+        // Resolve permissions into project roles and resource-scoped grants
+        let mut iam_roles = Vec::new();
+        let mut bucket_iam = Vec::new();
+        let mut secret_iam = Vec::new();
+        for grant in permissions::resolve(&self.config.permissions, &self.config.project_id)? {
+            match grant {
+                Grant::Project { role } => iam_roles.push(format!("\"{}\"", role)),
+                Grant::Resource { kind, name, role } => {
+                    let (list, key) = match kind {
+                        ResourceKind::Bucket => (&mut bucket_iam, "bucket"),
+                        ResourceKind::Secret => (&mut secret_iam, "secret"),
+                    };
+                    list.push(format!("{{ {} = \"{}\", role = \"{}\" }}", key, name, role));
+                }
+            }
+        }
+
+        // Existing networking; without it terraform creates a dedicated VPC
+        let mut network_tf = String::new();
+        if let Some(network) = &self.config.network {
+            network_tf.push_str(&format!("network        = \"{}\"\n", network));
+        }
+        if let Some(subnet) = &self.config.subnet {
+            network_tf.push_str(&format!("subnet         = \"{}\"\n", subnet));
+        }
+        if self.config.static_ip {
+            network_tf.push_str("static_ip      = true\n");
+        }
+        if let Some(zone) = &self.config.dns_zone {
+            network_tf.push_str(&format!("dns_zone       = \"{}\"\n", zone));
+        }
 
         // Format allowed IPs as Terraform list
-        let allowed_ips_tf = format!("[\"{}\"]", allowed_ips.join("\", \""));
+        let allowed_ips_tf = format!(
+            "[{}]",
+            allowed_ips
+                .iter()
+                .map(|ip| format!("\"{}\"", ip))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let egress_allowlist_tf = format!(
+            "[{}]",
+            self.config
+                .egress_allowlist
+                .iter()
+                .map(|domain| format!("\"{}\"", domain))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
 
         // Write terraform.tfvars
         let tfvars_content = format!(
@@ -352,27 +640,34 @@ impl VmManager {
 region         = "{}"
 zone           = "{}"
 machine_type   = "{}"
-cluster_name   = "{}"
-cluster_zone   = "{}"
 vm_name        = "{}"
 owner          = "{}"
 skip_deletion  = "{}"
-permissions    = {}
+iam_roles      = [{}]
+bucket_iam     = [{}]
+secret_iam     = [{}]
+network_mode   = "{}"
+egress_mode    = "{}"
+egress_allowlist = {}
 allowed_ips    = {}
-ssh_username   = "{}"
+{}ssh_username   = "{}"
 ssh_public_key = "{}"
 "#,
             self.config.project_id,
             self.config.region,
             self.config.zone,
             self.config.machine_type,
-            self.config.cluster_name.as_deref().unwrap_or(""),
-            self.config.cluster_zone,
             self.config.vm_name,
             self.config.owner,
             self.config.skip_deletion,
-            permissions_tf,
+            iam_roles.join(", "),
+            bucket_iam.join(", "),
+            secret_iam.join(", "),
+            self.config.network_mode,
+            self.config.egress.unwrap_or_default(),
+            egress_allowlist_tf,
             allowed_ips_tf,
+            network_tf,
             ssh_username,
             ssh_public_key,
         );
@@ -380,31 +675,203 @@ ssh_public_key = "{}"
         let tfvars_path = self.script_dir.join("terraform.tfvars");
         fs::write(&tfvars_path, tfvars_content)?;
 
-        Ok(())
+        Ok(state)
     }
 
-    /// Get allowed IPs for firewall rules
-    async fn get_allowed_ips(&self) -> Result<Vec<String>> {
+    /// Get allowed IPs for firewall rules, with the VM state tracking them
+    async fn get_allowed_ips(&self) -> Result<(VmState, Vec<String>)> {
+        if self.config.network_mode == NetworkMode::Iap {
+            utils::log("✓ Firewall will allow SSH from IAP only (no external IP)");
+            return Ok((VmState::load(&self.config.vm_name)?, Vec::new()));
+        }
+
         utils::log("Detecting your public IP addresses...");
 
-        let mut ips = vec![utils::detect_public_ipv4().await?];
+        let mut ips = vec![public_ip::detect(&self.config, false).await?];
 
         // Add additional IP if specified
         if let Some(additional_ip) = &self.config.additional_ip {
-            let ip_with_cidr = if additional_ip.contains('/') {
-                additional_ip.clone()
-            } else {
-                format!("{}/32", additional_ip)
-            };
+            let ip_with_cidr = allowlist::normalize_cidr(additional_ip)?;
             ips.push(ip_with_cidr.clone());
             utils::log(&format!("✓ Additional whitelisted IP: {}", ip_with_cidr));
         }
 
+        // Keep addresses allowed recently from other networks
+        let (state, ips) = self.allowed_ips_with(&ips, false)?;
+
         utils::log(&format!(
             "✓ Firewall will allow SSH from: {}",
             ips.join(", ")
         ));
-        Ok(ips)
+        Ok((state, ips))
+    }
+
+    /// The VM's state with `cidrs` added to its allowlist (dropping every
+    /// other entry when `replace` is set), and the unexpired entries. The
+    /// caller saves the state once the firewall has been updated.
+    fn allowed_ips_with(&self, cidrs: &[String], replace: bool) -> Result<(VmState, Vec<String>)> {
+        let mut state = VmState::load(&self.config.vm_name)?;
+        if replace {
+            state.allowed_ips.clear();
+        }
+        let now = chrono::Utc::now().timestamp();
+        for cidr in cidrs {
+            state.allow_ip(cidr, now);
+        }
+
+        let ranges = state
+            .active_allowed_ips(now)
+            .into_iter()
+            .map(|e| e.cidr.clone())
+            .collect();
+        Ok((state, ranges))
+    }
+
+    /// Name of the firewall rule allowing SSH to the VM
+    fn ssh_firewall_rule(&self) -> String {
+        format!("{}-allow-ssh", self.config.vm_name)
+    }
+
+    /// Source ranges currently in the SSH firewall rule
+    fn ssh_firewall_ranges(&self) -> Result<Vec<String>> {
+        let output = Command::new("gcloud")
+            .args([
+                "compute",
+                "firewall-rules",
+                "describe",
+                &self.ssh_firewall_rule(),
+                &format!("--project={}", self.config.project_id),
+                "--format=value(sourceRanges)",
+            ])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Firewall rule {} not found",
+                self.ssh_firewall_rule()
+            ));
+        }
+
+        Ok(String::from_utf8(output.stdout)?
+            .split([';', ',', '\n'])
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty())
+            .collect())
+    }
+
+    /// Allow `ip` (default: this machine's public IP) to SSH to the VM,
+    /// updating only the SSH firewall rule (`ca allow-ip`)
+    pub async fn allow_ip(&self, ip: Option<&str>, replace: bool) -> Result<()> {
+        if self.config.network_mode == NetworkMode::Iap {
+            utils::log("IAP VMs only accept SSH through IAP; there is no allowlist to update");
+            return Ok(());
+        }
+
+        let cidr = match ip {
+            Some(ip) => allowlist::normalize_cidr(ip)?,
+            None => public_ip::detect(&self.config, true).await?,
+        };
+        let (state, active) = self.allowed_ips_with(&[cidr], replace)?;
+        let ranges = if replace {
+            active
+        } else {
+            // Keep ranges added to the rule outside ca, such as an office network
+            let tracked: Vec<String> = VmState::load(&self.config.vm_name)?
+                .allowed_ips
+                .into_iter()
+                .map(|e| e.cidr)
+                .collect();
+            allowlist::merge_ranges(&self.ssh_firewall_ranges()?, &tracked, &active)
+        };
+
+        let rule = self.ssh_firewall_rule();
+        utils::log(&format!("Updating firewall rule {}...", rule));
+        let status = Command::new("gcloud")
+            .args([
+                "compute",
+                "firewall-rules",
+                "update",
+                &rule,
+                &format!("--source-ranges={}", ranges.join(",")),
+                &format!("--project={}", self.config.project_id),
+            ])
+            .status()?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to update firewall rule {}", rule));
+        }
+        state.save(&self.config.vm_name)?;
+
+        utils::log_success("SSH allowlist updated:");
+        for entry in state.active_allowed_ips(chrono::Utc::now().timestamp()) {
+            let expires = chrono::DateTime::from_timestamp(entry.expires_at, 0)
+                .map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            utils::log(&format!("  {} (until {})", entry.cidr, expires));
+        }
+        Ok(())
+    }
+
+    /// Before connecting to a public VM, check its SSH port answers and offer
+    /// to allow this machine's IP when it doesn't
+    async fn ensure_ssh_reachable(&self, vm_ip: &str) -> Result<()> {
+        if self.config.network_mode == NetworkMode::Iap
+            || allowlist::ssh_port_open(vm_ip, Duration::from_secs(5))
+        {
+            return Ok(());
+        }
+
+        // The network may have just changed, so don't trust a cached address
+        let Ok(current) = public_ip::detect(&self.config, true).await else {
+            return Ok(());
+        };
+        let Some(ip) = current
+            .split('/')
+            .next()
+            .and_then(|addr| addr.parse::<IpAddr>().ok())
+        else {
+            return Ok(());
+        };
+
+        if allowlist::covers(&self.ssh_firewall_ranges().unwrap_or_default(), ip) {
+            utils::log_warning(&format!(
+                "SSH to {} timed out, although {} is allowed. The VM may still be starting.",
+                vm_ip, ip
+            ));
+            return Ok(());
+        }
+
+        utils::log_warning(&format!(
+            "Your IP {} is not in the SSH allowlist for {}",
+            ip, self.config.vm_name
+        ));
+        if !std::io::stdin().is_terminal() {
+            utils::log("Run: ca allow-ip");
+            return Ok(());
+        }
+
+        print!("Update it? [Y/n] ");
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if input.trim().eq_ignore_ascii_case("n") {
+            return Ok(());
+        }
+
+        self.allow_ip(Some(&current), false).await?;
+
+        // Firewall changes take a few seconds to reach the VM
+        for _ in 0..10 {
+            if allowlist::ssh_port_open(vm_ip, Duration::from_secs(3)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+        Ok(())
     }
 
     /// Get SSH configuration (username and public key)
@@ -439,11 +906,25 @@ ssh_public_key = "{}"
         }
 
         let vm_ip = self.get_vm_ip().await?;
+        self.ensure_ssh_reachable(&vm_ip).await?;
         let ssh_client = SshClient::new(self.config.clone(), vm_ip);
 
         // Transfer credentials if not skipped
         if !skip_creds {
-            self.transfer_credentials(&ssh_client).await?;
+            self.transfer_credentials(&ssh_client, repos).await?;
+        }
+
+        self.configure_git(&ssh_client)?;
+        if self.config.with_settings {
+            self.transfer_agent_settings(&ssh_client)?;
+        }
+        if let Some(autonomy) = self.config.autonomy {
+            self.configure_autonomy(&ssh_client, autonomy)?;
+        }
+        self.install_queue_runner(&ssh_client);
+        self.configure_egress(&ssh_client)?;
+        if !self.config.clusters.is_empty() {
+            self.configure_kube(&ssh_client, Some(&self.config.clusters))?;
         }
 
         // Clone repositories
@@ -478,47 +959,58 @@ ssh_public_key = "{}"
     }
 
     /// Transfer credentials to the VM
-    async fn transfer_credentials(&self, ssh_client: &SshClient) -> Result<()> {
+    async fn transfer_credentials(&self, ssh_client: &SshClient, repos: &[String]) -> Result<()> {
         utils::log("");
         utils::log("Configuring credentials on VM...");
 
         // Create .ssh directory
         ssh_client.execute("mkdir -p ~/.ssh && chmod 700 ~/.ssh")?;
 
-        // Transfer SSH key for GitHub
-        if let Some(ssh_key) = &self.config.ssh_key {
-            utils::log("Transferring GitHub SSH key...");
-
-            ssh_client.copy_to_vm(ssh_key, "~/.ssh/id_ed25519")?;
+        let hosts = credentials::hosts_for_repos(repos)?;
+        let host_names: Vec<&str> = hosts.iter().map(|h| h.alias.as_str()).collect();
 
-            let pub_key = ssh_key.with_extension("pub");
-            if pub_key.exists() {
-                ssh_client.copy_to_vm(&pub_key, "~/.ssh/id_ed25519.pub")?;
+        match self.config.git_auth {
+            GitAuth::CopyKey => self.transfer_ssh_key(ssh_client, &hosts)?,
+            GitAuth::AgentForward => {
+                // The default, so HTTPS-only deploys don't need an ssh-agent
+                if hosts.iter().any(|h| h.ssh) {
+                    credentials::check_ssh_agent()?;
+                }
+                ssh_client.execute(&credentials::ssh_setup_script(&hosts, "~/.ssh/id_ed25519"))?;
+                utils::log_success(&format!(
+                    "Git over SSH will use your forwarded ssh-agent for: {}",
+                    host_names.join(", ")
+                ));
+            }
+            GitAuth::DeployKey => {
+                ssh_client.execute(&credentials::ssh_setup_script(&hosts, "~/.ssh/id_ed25519"))?;
+                self.register_deploy_keys(ssh_client, repos).await?;
             }
+        }
 
-            // Configure SSH on VM
-            ssh_client.execute(
-                "chmod 600 ~/.ssh/id_ed25519 && \
-                 chmod 644 ~/.ssh/id_ed25519.pub 2>/dev/null || true && \
-                 ssh-keyscan github.com >> ~/.ssh/known_hosts 2>/dev/null && \
-                 git config --global user.email 'cloud-agent@localhost' && \
-                 git config --global user.name 'Cloud Agent'",
-            )?;
+        if !self.config.git_tokens.is_empty() {
+            utils::log("Transferring Git credentials (tokens)...");
 
-            utils::log_success("GitHub SSH key transferred");
-        } else if let Some(token) = &self.config.github_token {
-            utils::log("Transferring GitHub credentials (PAT)...");
-
-            ssh_client.execute(&format!(
-                "git config --global credential.helper store && \
-                 echo 'https://oauth2:{}@github.com' > ~/.git-credentials && \
-                 chmod 600 ~/.git-credentials && \
-                 git config --global user.email 'cloud-agent@localhost' && \
-                 git config --global user.name 'Cloud Agent'",
-                token
+            ssh_client.execute(&credentials::token_setup_script(
+                &self.config.git_tokens,
+                &hosts,
             ))?;
 
-            utils::log_success("GitHub PAT transferred");
+            let token_hosts: Vec<&str> =
+                self.config.git_tokens.keys().map(|h| h.as_str()).collect();
+            utils::log_success(&format!(
+                "Git tokens transferred for: {}",
+                token_hosts.join(", ")
+            ));
+
+            for host in &hosts {
+                if !self.config.git_tokens.contains_key(&host.hostname) && !host.ssh {
+                    utils::log_warning(&format!(
+                        "No token for {} (use --git-token {}=TOKEN)",
+                        host.hostname, host.hostname
+                    ));
+                }
+            }
         }
 
         // Transfer agent credentials
@@ -527,6 +1019,243 @@ ssh_public_key = "{}"
         Ok(())
     }
 
+    /// Copy the local SSH key to the VM for Git access
+    fn transfer_ssh_key(&self, ssh_client: &SshClient, hosts: &[GitHost]) -> Result<()> {
+        let Some(ssh_key) = &self.config.ssh_key else {
+            return Ok(());
+        };
+
+        utils::log("Transferring Git SSH key...");
+        if credentials::is_personal_key(ssh_key) {
+            utils::log_warning(&format!(
+                "Copying {} to the VM. Consider --git-auth deploy-key or agent-forward \
+                 instead of sharing your personal key with the agent.",
+                ssh_key.display()
+            ));
+        }
+
+        ssh_client.copy_to_vm(ssh_key, "~/.ssh/id_ed25519")?;
+
+        let pub_key = ssh_key.with_extension("pub");
+        if pub_key.exists() {
+            ssh_client.copy_to_vm(&pub_key, "~/.ssh/id_ed25519.pub")?;
+        }
+
+        // Configure SSH on VM
+        ssh_client.execute(&format!(
+            "chmod 600 ~/.ssh/id_ed25519 && \
+             (chmod 644 ~/.ssh/id_ed25519.pub 2>/dev/null || true) && \
+             {}",
+            credentials::ssh_setup_script(hosts, "~/.ssh/id_ed25519")
+        ))?;
+
+        let host_names: Vec<&str> = hosts.iter().map(|h| h.alias.as_str()).collect();
+        utils::log_success(&format!(
+            "Git SSH key transferred for: {}",
+            host_names.join(", ")
+        ));
+        Ok(())
+    }
+
+    /// Generate a per-VM key for each GitHub repo and register it as a deploy key
+    async fn register_deploy_keys(&self, ssh_client: &SshClient, repos: &[String]) -> Result<()> {
+        let token = self.config.github_token().ok_or_else(|| {
+            CloudAgentError::ConfigError(
+                "--git-auth deploy-key requires a GitHub token (--github-token)".to_string(),
+            )
+        })?;
+        let github = GitHubClient::new(&self.config.github_api_url, token);
+        let mut state = VmState::load(&self.config.vm_name)?;
+
+        for repo_url in repos {
+            let Some((owner, repo)) = github::repo_slug(repo_url) else {
+                utils::log_warning(&format!(
+                    "Deploy keys are only supported for github.com repos, skipping {}",
+                    repo_url
+                ));
+                continue;
+            };
+
+            utils::log(&format!(
+                "  Registering deploy key for {}/{}...",
+                owner, repo
+            ));
+
+            // Replace any key from a previous deploy of this VM
+            let previous: Vec<DeployKeyRecord> = state
+                .deploy_keys
+                .iter()
+                .filter(|k| k.owner == owner && k.repo == repo)
+                .cloned()
+                .collect();
+            for key in &previous {
+                github
+                    .delete_deploy_key(&key.owner, &key.repo, key.id)
+                    .await?;
+            }
+            state.deploy_keys.retain(|k| !previous.contains(k));
+
+            let title = format!("cloud-agent {}", self.config.vm_name);
+            let key = credentials::generate_ed25519_key(&title)?;
+            let deploy_key = github
+                .add_deploy_key(&owner, &repo, &title, &key.public_openssh, false)
+                .await?;
+
+            state.deploy_keys.push(DeployKeyRecord {
+                owner: owner.clone(),
+                repo: repo.clone(),
+                id: deploy_key.id,
+            });
+            state.save(&self.config.vm_name)?;
+
+            let temp_file = tempfile::NamedTempFile::new()?;
+            fs::write(temp_file.path(), &key.private_openssh)?;
+            let remote_path = credentials::deploy_key_path(&owner, &repo);
+            ssh_client.copy_to_vm(temp_file.path(), &remote_path)?;
+            ssh_client.execute(&format!("chmod 600 {}", remote_path))?;
+        }
+
+        utils::log_success("Deploy keys registered");
+        Ok(())
+    }
+
+    /// Remove deploy keys registered for this VM
+    async fn remove_deploy_keys(&self) -> Result<()> {
+        let mut state = VmState::load(&self.config.vm_name)?;
+        if state.deploy_keys.is_empty() {
+            return Ok(());
+        }
+
+        let Some(token) = self.config.github_token() else {
+            utils::log_warning(
+                "Deploy keys were registered for this VM but no GitHub token is configured to remove them",
+            );
+            return Ok(());
+        };
+
+        utils::log("Removing deploy keys...");
+        let github = GitHubClient::new(&self.config.github_api_url, token);
+        for key in std::mem::take(&mut state.deploy_keys) {
+            if let Err(e) = github
+                .delete_deploy_key(&key.owner, &key.repo, key.id)
+                .await
+            {
+                utils::log_warning(&format!(
+                    "Failed to remove deploy key from {}/{}: {}",
+                    key.owner, key.repo, e
+                ));
+                state.deploy_keys.push(key);
+            }
+        }
+        state.save(&self.config.vm_name)?;
+
+        if state.deploy_keys.is_empty() {
+            utils::log_success("Deploy keys removed");
+        }
+        Ok(())
+    }
+
+    /// Configure commit identity, signing and managed git hooks on the VM
+    fn configure_git(&self, ssh_client: &SshClient) -> Result<()> {
+        utils::log("");
+        utils::log(&format!(
+            "Configuring git identity: {} <{}>",
+            self.config.git_user_name, self.config.git_user_email
+        ));
+
+        let mut commands = vec![
+            format!(
+                "git config --global user.name {}",
+                utils::shell_quote(&self.config.git_user_name)
+            ),
+            format!(
+                "git config --global user.email {}",
+                utils::shell_quote(&self.config.git_user_email)
+            ),
+        ];
+
+        if let Some(signing_key) = &self.config.signing_key {
+            utils::log(&format!(
+                "Transferring commit signing key: {}",
+                signing_key.display()
+            ));
+            ssh_client.execute("mkdir -p ~/.ssh && chmod 700 ~/.ssh")?;
+            ssh_client.copy_to_vm(signing_key, "~/.ssh/signing_key")?;
+
+            let pub_key = signing_key.with_extension("pub");
+            if pub_key.exists() {
+                ssh_client.copy_to_vm(&pub_key, "~/.ssh/signing_key.pub")?;
+            } else {
+                commands.push(
+                    "ssh-keygen -y -f ~/.ssh/signing_key > ~/.ssh/signing_key.pub".to_string(),
+                );
+            }
+
+            commands.extend([
+                "chmod 600 ~/.ssh/signing_key".to_string(),
+                "git config --global gpg.format ssh".to_string(),
+                "git config --global user.signingkey ~/.ssh/signing_key.pub".to_string(),
+                "git config --global commit.gpgsign true".to_string(),
+                "git config --global tag.gpgsign true".to_string(),
+            ]);
+        }
+
+        if !self.config.commit_trailers.is_empty() {
+            utils::log(&format!(
+                "Commits will be tagged with: {}",
+                self.config.commit_trailers.join(", ")
+            ));
+        }
+        let mut hooks = vec![(
+            "commit-msg",
+            git_hooks::commit_msg_hook(&self.config.commit_trailers),
+        )];
+        if self.config.push_guard {
+            utils::log(&format!(
+                "Push guard: protected [{}], allowed [{}]",
+                self.config.protected_branches.join(", "),
+                if self.config.allowed_branches.is_empty() {
+                    "any".to_string()
+                } else {
+                    self.config.allowed_branches.join(", ")
+                }
+            ));
+            hooks.push((
+                "pre-push",
+                git_hooks::pre_push_hook(
+                    &self.config.protected_branches,
+                    &self.config.allowed_branches,
+                ),
+            ));
+        } else {
+            commands.push(format!("rm -f {}/pre-push", git_hooks::HOOKS_DIR));
+        }
+        commands.push(git_hooks::install_script(&hooks));
+
+        ssh_client.execute(&commands.join(" && "))?;
+        utils::log_success("Git configured");
+        Ok(())
+    }
+
+    /// Install the task queue runner as a systemd user service.
+    ///
+    /// The queue is optional, so a failure here only warns.
+    fn install_queue_runner(&self, ssh_client: &SshClient) {
+        utils::log("");
+        utils::log("Installing task queue runner...");
+        match ssh_client.execute(&queue::install_script()) {
+            Ok(_) => utils::log_success("Task queue runner installed"),
+            Err(e) => utils::log_warning(&format!("Could not install task queue runner: {}", e)),
+        }
+    }
+
+    /// Restart the queue runner for a new proxy environment
+    fn restart_queue_runner(&self, ssh_client: &SshClient) {
+        if let Err(e) = ssh_client.execute(&queue::restart_command()) {
+            utils::log_warning(&format!("Could not restart the task queue runner: {}", e));
+        }
+    }
+
     /// Transfer AI agent credentials
     async fn transfer_agent_credentials(&self, ssh_client: &SshClient) -> Result<()> {
         utils::log("");
@@ -579,8 +1308,234 @@ ssh_public_key = "{}"
                 )?;
                 utils::log("  ✅ Codex credentials transferred");
             }
+
+            // Aider config (provider keys and defaults)
+            let aider_conf = home.join(".aider.conf.yml");
+            if aider_conf.exists() {
+                utils::log("  Transferring Aider config...");
+                let temp_file = tempfile::NamedTempFile::new()?;
+                fs::copy(&aider_conf, temp_file.path())?;
+
+                ssh_client.copy_to_vm(temp_file.path(), "~/.aider.conf.yml")?;
+                ssh_client.execute("chmod 600 ~/.aider.conf.yml")?;
+                utils::log("  ✅ Aider config transferred");
+            }
+
+            // Gemini CLI credentials (not needed with the VM service account)
+            let gemini_dir = home.join(".gemini");
+            if gemini_dir.join("oauth_creds.json").exists()
+                && !agents::uses_service_account("gemini", &self.config)
+            {
+                utils::log("  Transferring Gemini CLI credentials...");
+                ssh_client.execute("mkdir -p ~/.gemini && chmod 700 ~/.gemini")?;
+                for file in ["oauth_creds.json", "google_accounts.json", "settings.json"] {
+                    let path = gemini_dir.join(file);
+                    if path.exists() {
+                        let temp_file = tempfile::NamedTempFile::new()?;
+                        fs::copy(&path, temp_file.path())?;
+
+                        let remote = format!("~/.gemini/{}", file);
+                        ssh_client.copy_to_vm(temp_file.path(), &remote)?;
+                        ssh_client.execute(&format!("chmod 600 {}", remote))?;
+                    }
+                }
+                utils::log("  ✅ Gemini CLI credentials transferred");
+            }
+        }
+
+        // API key and service account settings for an agent used without a login
+        self.store_agent_env(ssh_client, std::slice::from_ref(&self.config.agent))
+    }
+
+    /// Write the environment of `agents` (API keys, service account and
+    /// model settings) to the VM's agent env file, replacing what was there
+    fn store_agent_env(&self, ssh_client: &SshClient, agents: &[String]) -> Result<()> {
+        let env = agents::collect_agent_env(&self.config, agents)?;
+        for (var, _) in &env {
+            utils::log(&format!("  Storing {} in {}", var, agents::AGENT_ENV_FILE));
+        }
+        ssh_client.execute(&agents::env_file_script(&env)?)?;
+        Ok(())
+    }
+
+    /// Copy every agent's settings bundle to the VM (`--with-settings`)
+    fn transfer_agent_settings(&self, ssh_client: &SshClient) -> Result<()> {
+        utils::log("");
+        utils::log("Transferring agent settings...");
+
+        let Some(home) = dirs::home_dir() else {
+            return Ok(());
+        };
+        let remote_home = format!("/home/{}", self.config.ssh_username);
+        let items = agents::collect_settings_bundle(&self.config)?;
+
+        let staging = tempfile::tempdir()?;
+        let bundle = settings::stage(&items, &home, &remote_home, staging.path())?;
+        for warning in &bundle.warnings {
+            utils::log_warning(&format!("  {}", warning));
+        }
+        if bundle.files.is_empty() {
+            utils::log("  No agent settings found");
+            return Ok(());
         }
 
+        let archive = tempfile::NamedTempFile::new()?;
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(archive.path())
+            .arg("-C")
+            .arg(staging.path())
+            .args(&bundle.files)
+            .status()?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to pack agent settings"));
+        }
+
+        ssh_client.copy_to_vm(archive.path(), "~/cloud-agent-settings.tgz")?;
+        ssh_client.execute(&settings::extract_command(
+            "cloud-agent-settings.tgz",
+            &bundle.files,
+        ))?;
+
+        for file in &bundle.files {
+            utils::log(&format!("  ~/{}", file));
+        }
+        utils::log_success(&format!(
+            "{} agent settings files transferred",
+            bundle.files.len()
+        ));
+        Ok(())
+    }
+
+    /// Set instance metadata entries (`KEY=VALUE,...`) on the VM
+    fn add_metadata(&self, metadata: &str) -> Result<()> {
+        let status = Command::new("gcloud")
+            .args([
+                "compute",
+                "instances",
+                "add-metadata",
+                &self.config.vm_name,
+                &format!("--zone={}", self.config.zone),
+                &format!("--metadata={}", metadata),
+            ])
+            .status()?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to update instance metadata"));
+        }
+        Ok(())
+    }
+
+    /// Install the egress proxy and allowlist for restricted egress, or remove
+    /// them again with open egress, and record the mode for later deploys.
+    /// Without a mode the VM is left as it is.
+    fn configure_egress(&self, ssh_client: &SshClient) -> Result<()> {
+        let Some(mode) = self.config.egress else {
+            return Ok(());
+        };
+
+        let domains = &self.config.egress_allowlist;
+        let active = ssh_client.execute(&egress::active_command())? == "yes";
+        match mode {
+            EgressMode::Restricted => {
+                utils::log("");
+                utils::log(&format!(
+                    "Restricting egress to {} allowlisted domains...",
+                    domains.len()
+                ));
+                self.add_metadata(&egress::metadata(mode, domains))?;
+                let mut running = active;
+                if !running {
+                    // The startup script enables it on new VMs; give it time to finish
+                    ssh_client.execute(&egress::startup_wait_command()).ok();
+                    running = ssh_client.execute(&egress::active_command())? == "yes";
+                }
+                if !running {
+                    // A VM created with open egress: its startup script enforces
+                    // the mode from the next boot, so set it up now over SSH
+                    utils::log_warning("Egress proxy not running yet, setting it up over SSH...");
+                    ssh_client
+                        .execute_streaming(&egress::install_script())
+                        .map_err(|e| anyhow::anyhow!("Could not set up the egress proxy: {}", e))?;
+                }
+                ssh_client
+                    .execute(&egress::wait_script(mode, domains))
+                    .map_err(|_| anyhow::anyhow!("The VM did not apply the egress allowlist"))?;
+                utils::log_success(
+                    "Egress restricted and sudo disabled on the VM (denied requests are shown by ca status)",
+                );
+                if !active {
+                    self.restart_queue_runner(ssh_client);
+                }
+            }
+            EgressMode::Open if active => {
+                utils::log("");
+                utils::log("Removing egress restrictions (--egress open)...");
+                self.add_metadata(&egress::metadata(mode, domains))?;
+                ssh_client
+                    .execute(&egress::wait_script(mode, domains))
+                    .map_err(|_| anyhow::anyhow!("The VM did not remove the egress proxy"))?;
+                utils::log_success("Egress open and sudo restored on the VM");
+                self.restart_queue_runner(ssh_client);
+            }
+            EgressMode::Open => {}
+        }
+
+        let mut state = VmState::load(&self.config.vm_name)?;
+        state.egress = Some(mode);
+        state.egress_allow = self.config.egress_allow.clone();
+        state.save(&self.config.vm_name)
+    }
+
+    /// Fetch GKE credentials on the VM for `clusters`, or for the clusters
+    /// recorded at deploy when `None`
+    fn configure_kube(
+        &self,
+        ssh_client: &SshClient,
+        clusters: Option<&[GkeCluster]>,
+    ) -> Result<()> {
+        utils::log("");
+        utils::log("Configuring kubectl for GKE clusters...");
+
+        let has_gke = permissions::resolve(&self.config.permissions, &self.config.project_id)?
+            .iter()
+            .any(|g| matches!(g, Grant::Project { role } if role.starts_with("roles/container.")));
+        if !has_gke {
+            utils::log_warning(
+                "The VM needs --permissions gke (or admin) to fetch cluster credentials",
+            );
+        }
+
+        match ssh_client
+            .execute_streaming(&kube::credentials_script(&self.config.project_id, clusters))
+        {
+            Ok(()) => utils::log_success("kubectl configured (one context per cluster)"),
+            Err(e) => utils::log_warning(&format!(
+                "Could not fetch credentials for every cluster: {}",
+                e
+            )),
+        }
+        Ok(())
+    }
+
+    /// Re-fetch GKE credentials on the VM (`ca kube refresh`)
+    pub async fn kube_refresh(&self) -> Result<()> {
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+
+        let clusters =
+            (!self.config.clusters.is_empty()).then_some(self.config.clusters.as_slice());
+        self.configure_kube(&ssh_client, clusters)
+    }
+
+    /// Write every agent's permission config for `autonomy` on the VM
+    fn configure_autonomy(&self, ssh_client: &SshClient, autonomy: Autonomy) -> Result<()> {
+        utils::log("");
+        utils::log(&format!("Configuring agent autonomy: {}", autonomy));
+
+        for (agent, script) in agents::autonomy_scripts(&self.config, autonomy)? {
+            ssh_client.execute(&script)?;
+            utils::log(&format!("  ✅ {}", agent));
+        }
         Ok(())
     }
 
@@ -595,28 +1550,549 @@ ssh_public_key = "{}"
             .ok();
 
         for repo in repos {
-            let repo_name = utils::extract_repo_name(repo)?;
-            utils::log(&format!("  Cloning {}...", repo_name));
-
-            let clone_cmd = format!(
-                "cd /workspace && \
-                 if [ -d '{}' ]; then \
-                     echo '  ⚠️  {} already exists, pulling latest...' && \
-                     cd '{}' && git pull; \
-                 else \
-                     git clone '{}' '{}' && \
-                     echo '  ✅ Cloned {}'; \
-                 fi",
-                repo_name, repo_name, repo_name, repo, repo_name, repo_name
-            );
+            let mut spec = self.config.repo_spec(repo)?;
+            utils::log(&format!("  Cloning {}...", spec.name));
 
-            ssh_client.execute(&clone_cmd)?;
+            if self.config.git_auth == GitAuth::DeployKey {
+                spec.ssh_command = github::repo_slug(repo)
+                    .map(|(owner, name)| credentials::deploy_key_ssh_command(&owner, &name));
+            }
+
+            // Pin the managed hooks per repo so repo-level hooksPath settings
+            // can't bypass the push guard
+            let command = format!(
+                "cd /workspace && {} && git config core.hooksPath {}",
+                spec.clone_script(),
+                git_hooks::HOOKS_DIR
+            );
+            self.execute_git(ssh_client, &command)?;
         }
 
         utils::log_success("All repositories cloned");
         Ok(())
     }
 
+    /// Run a git command on the VM that talks to a remote, forwarding the
+    /// SSH agent when `--git-auth agent-forward` is used
+    fn execute_git(&self, ssh_client: &SshClient, command: &str) -> Result<String> {
+        if self.config.git_auth == GitAuth::AgentForward {
+            ssh_client.execute_with_agent(command)
+        } else {
+            ssh_client.execute(command)
+        }
+    }
+
+    /// Open or update pull requests for pushed agent branches
+    pub async fn pr(&self, repo: Option<&str>, draft: bool, labels: &[String]) -> Result<()> {
+        let token = self.config.github_token().ok_or_else(|| {
+            CloudAgentError::ConfigError(
+                "ca pr requires a GitHub token (--github-token)".to_string(),
+            )
+        })?;
+        let github = GitHubClient::new(&self.config.github_api_url, token);
+
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+
+        utils::log("Looking for agent branches in /workspace...");
+        let output = self.execute_git(&ssh_client, &pr::report_command(repo))?;
+        let reports = pr::parse_report(&output);
+
+        if reports.is_empty() {
+            return Err(anyhow::anyhow!(match repo {
+                Some(name) => format!("Repository '{}' not found in /workspace", name),
+                None => "No repositories found in /workspace".to_string(),
+            }));
+        }
+
+        let options = pr::PrOptions {
+            vm_name: &self.config.vm_name,
+            draft,
+            labels,
+        };
+        let mut count = 0;
+
+        for report in &reports {
+            let Some((owner, name)) = github::repo_slug(&report.url) else {
+                utils::log_warning(&format!(
+                    "Skipping {}: only github.com repositories are supported",
+                    report.name
+                ));
+                continue;
+            };
+
+            for branch in &report.branches {
+                let (action, pull) = pr::sync_pull_request(
+                    &github,
+                    &owner,
+                    &name,
+                    &report.default_branch,
+                    branch,
+                    &options,
+                )
+                .await?;
+
+                let verb = match action {
+                    pr::PrAction::Created => "Opened",
+                    pr::PrAction::Updated => "Updated",
+                };
+                utils::log_success(&format!(
+                    "{} PR #{} for {}:{} → {}",
+                    verb, pull.number, report.name, branch.name, pull.html_url
+                ));
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            utils::log("No pushed branches ahead of the default branch");
+        }
+
+        Ok(())
+    }
+
+    /// Resolve `repo` (a URL or directory name) to a repository deployed in
+    /// /workspace on the VM
+    fn deployed_repo(&self, ssh_client: &SshClient, repo: &str) -> Result<String> {
+        let name = if repo.contains(':') || repo.contains('/') {
+            utils::extract_repo_name(repo)?
+        } else {
+            repo.to_string()
+        };
+
+        let exists = ssh_client.execute(&format!(
+            "test -d /workspace/{}/.git && echo yes || true",
+            utils::shell_quote(&name)
+        ))?;
+        if exists.trim() != "yes" {
+            return Err(anyhow::anyhow!(
+                "Repository '{}' not found in /workspace (deploy it first)",
+                name
+            ));
+        }
+
+        Ok(name)
+    }
+
+    /// Add a task for `repo` to the queue on the VM
+    pub async fn queue_add(&self, repo: &str, prompt: &str, command: String) -> Result<()> {
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+        let name = self.deployed_repo(&ssh_client, repo)?;
+
+        let task = Task::new(&name, &self.config.agent, prompt, command);
+        ssh_client.execute(&queue::add_command(&task)?)?;
+        utils::log_success(&format!("Queued task {} for {}", task.id, name));
+        Ok(())
+    }
+
+    /// List tasks in the queue on the VM
+    pub async fn queue_list(&self) -> Result<()> {
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+
+        let tasks = queue::parse_tasks(&ssh_client.execute(&queue::list_command())?);
+        if tasks.is_empty() {
+            utils::log("Queue is empty");
+            return Ok(());
+        }
+
+        for task in &tasks {
+            let exit = task
+                .exit_code
+                .map(|code| format!("exit {}", code))
+                .unwrap_or_default();
+            let prompt: String = task.prompt.chars().take(60).collect();
+            println!(
+                "{}  {:<9} {:<7} {}  [{}]  {}",
+                task.id, task.status, exit, task.repo, task.agent, prompt
+            );
+            if let Some(log) = &task.log {
+                println!("    log: {}", log);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cancel a queued or running task on the VM
+    pub async fn queue_cancel(&self, id: &str) -> Result<()> {
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+
+        let output = ssh_client.execute(&queue::cancel_command(id))?;
+        utils::log_success(output.trim());
+        Ok(())
+    }
+
+    /// Run the agent headless on `repo` in the existing VM and collect the
+    /// results locally
+    pub async fn run(
+        &self,
+        repo: &str,
+        prompt: &str,
+        command: &str,
+        options: &RunOptions,
+    ) -> Result<()> {
+        self.run_with_id(&run::run_id(), repo, prompt, command, options)
+            .await
+    }
+
+    async fn run_with_id(
+        &self,
+        id: &str,
+        repo: &str,
+        prompt: &str,
+        command: &str,
+        options: &RunOptions,
+    ) -> Result<()> {
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+        let name = self.deployed_repo(&ssh_client, repo)?;
+        let branch = run::run_branch(id);
+
+        utils::log("");
+        utils::log(&format!(
+            "Running {} on {} (branch {})...",
+            self.config.agent, name, branch
+        ));
+        ssh_client.execute_streaming(&run::agent_script(id, &name, command))?;
+
+        utils::log("");
+        utils::log("Collecting results...");
+        let output = ssh_client.execute(&run::collect_script(id, &name, prompt))?;
+        let (exit_code, commits) = run::parse_collect_output(&output)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected output collecting results: {}", output))?;
+
+        let pushed = options.push && commits > 0;
+        if pushed {
+            self.execute_git(
+                &ssh_client,
+                &format!(
+                    "cd /workspace/{} && git push -u origin {}",
+                    utils::shell_quote(&name),
+                    utils::shell_quote(&branch)
+                ),
+            )?;
+            utils::log_success(&format!("Pushed {}", branch));
+        }
+
+        let output_dir = options
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("cloud-agent-runs").join(id));
+        fs::create_dir_all(&output_dir)?;
+        let remote_dir = run::remote_dir(id);
+        for file in ["transcript.log", "changes.patch"] {
+            ssh_client.copy_from_vm(&format!("{}/{}", remote_dir, file), &output_dir.join(file))?;
+        }
+
+        let summary = RunSummary {
+            id: id.to_string(),
+            vm_name: self.config.vm_name.clone(),
+            agent: self.config.agent.clone(),
+            repo: name,
+            prompt: prompt.to_string(),
+            branch,
+            pushed,
+            exit_code,
+            commits,
+        };
+        fs::write(
+            output_dir.join("run.json"),
+            serde_json::to_string_pretty(&summary)?,
+        )?;
+
+        utils::log_success(&format!(
+            "{} commit(s) saved to {}",
+            commits,
+            output_dir.join("changes.patch").display()
+        ));
+        utils::log(&format!(
+            "   Transcript: {}",
+            output_dir.join("transcript.log").display()
+        ));
+
+        if exit_code != 0 {
+            return Err(anyhow::anyhow!("Agent exited with code {}", exit_code));
+        }
+
+        Ok(())
+    }
+
+    /// Run several agents on `repo` side by side, each in its own worktree
+    /// and tmux window, then compare and collect their results locally.
+    ///
+    /// `agents` pairs each agent name with its headless command.
+    pub async fn compare(
+        &self,
+        repo: &str,
+        prompt: &str,
+        agents: &[(String, String)],
+        options: &RunOptions,
+    ) -> Result<()> {
+        let vm_ip = self.get_vm_ip().await?;
+        let ssh_client = SshClient::new(self.config.clone(), vm_ip);
+        let name = self.deployed_repo(&ssh_client, repo)?;
+        let id = run::run_id();
+        let names: Vec<String> = agents.iter().map(|(agent, _)| agent.clone()).collect();
+
+        utils::log("");
+        utils::log(&format!(
+            "Starting {} on {} (run {})...",
+            names.join(", "),
+            name,
+            id
+        ));
+        // The compared agents need their keys, and the deployed agent keeps its own
+        let mut env_agents = vec![self.config.agent.clone()];
+        env_agents.extend(names.iter().filter(|n| **n != self.config.agent).cloned());
+        self.store_agent_env(&ssh_client, &env_agents)?;
+
+        ssh_client.execute(&run::worktrees_script(&id, &name, agents))?;
+        utils::log(&format!(
+            "Watch the agents with: ca ssh, then tmux attach -t {}",
+            run::tmux_session(&id)
+        ));
+        utils::log("Agents keep running on the VM if you interrupt this command");
+
+        let deadline = tokio::time::Instant::now()
+            + tokio::time::Duration::from_secs(run::COMPARE_TIMEOUT_SECS);
+        let mut finished: Vec<String> = Vec::new();
+        while finished.len() < names.len() {
+            if tokio::time::Instant::now() >= deadline {
+                let running: Vec<&str> = names
+                    .iter()
+                    .filter(|n| !finished.contains(n))
+                    .map(String::as_str)
+                    .collect();
+                return Err(anyhow::anyhow!(
+                    "Timed out waiting for {} (still running in tmux session {})",
+                    running.join(", "),
+                    run::tmux_session(&id)
+                ));
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
+            let output = ssh_client.execute(&run::finished_command(&id, &names))?;
+            for done in run::parse_finished(&output) {
+                let (AgentDone::Finished(agent) | AgentDone::Gone(agent)) = &done;
+                if finished.contains(agent) {
+                    continue;
+                }
+                match &done {
+                    AgentDone::Finished(_) => utils::log_success(&format!("{} finished", agent)),
+                    AgentDone::Gone(_) => utils::log_warning(&format!(
+                        "{}'s tmux window closed before it finished",
+                        agent
+                    )),
+                }
+                finished.push(agent.clone());
+            }
+        }
+
+        let test_command = self.config.test_command(repo);
+        utils::log("");
+        match &test_command {
+            Some(test) => utils::log(&format!("Comparing results (tests: {})...", test)),
+            None => utils::log("Comparing results (no test command configured)..."),
+        }
+        let output = ssh_client.execute(&run::compare_script(
+            &id,
+            &name,
+            &names,
+            prompt,
+            test_command.as_deref(),
+        ))?;
+        let results = run::parse_compare_output(&output);
+
+        if options.push {
+            for result in results.iter().filter(|r| r.commits > 0) {
+                let branch = run::agent_branch(&id, &result.agent);
+                self.execute_git(
+                    &ssh_client,
+                    &format!(
+                        "cd /workspace/{} && git push -u origin {}",
+                        utils::shell_quote(&name),
+                        utils::shell_quote(&branch)
+                    ),
+                )?;
+                utils::log_success(&format!("Pushed {}", branch));
+            }
+        }
+
+        let output_dir = options
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("cloud-agent-runs").join(&id));
+        let remote_dir = run::remote_dir(&id);
+        for agent in &names {
+            let agent_dir = output_dir.join(agent);
+            fs::create_dir_all(&agent_dir)?;
+            let mut files = vec!["transcript.log", "changes.patch"];
+            if test_command.is_some() {
+                files.push("tests.log");
+            }
+            for file in files {
+                ssh_client.copy_from_vm(
+                    &format!("{}/{}/{}", remote_dir, agent, file),
+                    &agent_dir.join(file),
+                )?;
+            }
+        }
+
+        let report = run::comparison_report(&id, &name, prompt, &results);
+        fs::write(output_dir.join("comparison.md"), &report)?;
+        let summary = ComparisonSummary {
+            id: id.clone(),
+            vm_name: self.config.vm_name.clone(),
+            repo: name,
+            prompt: prompt.to_string(),
+            test_command,
+            results,
+        };
+        fs::write(
+            output_dir.join("run.json"),
+            serde_json::to_string_pretty(&summary)?,
+        )?;
+
+        utils::log("");
+        println!("{}", report);
+        utils::log_success(&format!("Results saved to {}", output_dir.display()));
+        Ok(())
+    }
+
+    /// Create a throwaway VM, run the agent headless on `repo_url`, collect
+    /// the results and destroy the VM again, even on failure or Ctrl-C
+    pub async fn run_ephemeral(
+        config: &Config,
+        repo_url: &str,
+        prompt: &str,
+        command: &str,
+        options: &RunOptions,
+    ) -> Result<()> {
+        crate::git::validate_repo_url(repo_url)?;
+
+        // The handler is registered on the first poll; do that before any
+        // work starts, so Ctrl-C always reaches the cleanup below
+        let mut interrupted = std::pin::pin!(tokio::signal::ctrl_c());
+        std::future::poll_fn(|cx| {
+            let _ = std::future::Future::poll(interrupted.as_mut(), cx);
+            Poll::Ready(())
+        })
+        .await;
+
+        let id = run::run_id();
+        let manager = Arc::new(Self::ephemeral(config, &id)?);
+        utils::print_header("🐕 CLOUD AGENT EPHEMERAL RUN");
+        utils::log(&format!("VM name: {}", manager.config.vm_name));
+
+        // Terraform, ssh and gcloud are waited for synchronously, so the run
+        // goes on a blocking thread and stops at its next await when cancelled
+        let cancel = Arc::new(tokio::sync::Notify::new());
+        let mut work = {
+            let (manager, cancel) = (Arc::clone(&manager), Arc::clone(&cancel));
+            let (id, repo_url, prompt, command, options) = (
+                id.clone(),
+                repo_url.to_string(),
+                prompt.to_string(),
+                command.to_string(),
+                options.clone(),
+            );
+            tokio::task::spawn_blocking(move || {
+                tokio::runtime::Handle::current().block_on(async {
+                    tokio::select! {
+                        result = async {
+                            manager.create_vm(true).await?;
+                            manager.deploy_repos(std::slice::from_ref(&repo_url), false).await?;
+                            manager
+                                .run_with_id(&id, &repo_url, &prompt, &command, &options)
+                                .await
+                        } => result,
+                        _ = cancel.notified() => Err(anyhow::anyhow!("Interrupted")),
+                    }
+                })
+            })
+        };
+
+        let joined = tokio::select! {
+            joined = &mut work => joined,
+            _ = interrupted => {
+                // Child processes got the interrupt too; wait for the current
+                // step to stop so cleanup doesn't race it
+                utils::log_warning("Interrupted, stopping the run...");
+                cancel.notify_one();
+                work.await
+            }
+        };
+        let result = joined.unwrap_or_else(|e| Err(anyhow::anyhow!("The run task failed: {}", e)));
+
+        if let Err(e) = &result {
+            utils::log_warning(&format!("Run failed: {}", e));
+        }
+
+        utils::log("");
+        utils::log(&format!(
+            "Cleaning up ephemeral VM {}...",
+            manager.config.vm_name
+        ));
+        // If the lookup fails the VM may still exist, so try to delete it
+        let cleanup = if manager.script_dir.join("terraform.tfstate").exists()
+            || manager.vm_exists().await.unwrap_or(true)
+        {
+            manager.destroy().await
+        } else {
+            manager.remove_deploy_keys().await
+        };
+
+        match &cleanup {
+            Ok(()) => {
+                fs::remove_dir_all(&manager.script_dir).ok();
+            }
+            Err(e) => {
+                utils::log_error(&format!("Cleanup failed: {}", e));
+                utils::log("Remove the leftover resources with:");
+                utils::log(&format!(
+                    "  terraform -chdir={} destroy",
+                    manager.script_dir.display()
+                ));
+            }
+        }
+
+        result.and(cleanup)
+    }
+
+    /// VM manager for an ephemeral VM, with its own copy of the terraform
+    /// files so it doesn't touch the state of the long-lived VM
+    fn ephemeral(config: &Config, id: &str) -> Result<Self> {
+        let source_dir = std::env::current_dir()?;
+        if !source_dir.join("main.tf").exists() {
+            return Err(anyhow::anyhow!(
+                "main.tf not found in {}",
+                source_dir.display()
+            ));
+        }
+
+        let config = config.for_ephemeral_run(id);
+        let script_dir = dirs::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?
+            .join(".config/cloud-agent/runs")
+            .join(&config.vm_name);
+        fs::create_dir_all(&script_dir)?;
+
+        for file in TERRAFORM_FILES {
+            let path = source_dir.join(file);
+            if path.exists() {
+                let target = script_dir.join(file);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&path, target)?;
+            }
+        }
+
+        Ok(Self { config, script_dir })
+    }
+
     /// Print success message with instructions
     async fn print_success_message(&self, ssh_client: &SshClient) -> Result<()> {
         utils::log("");
@@ -634,9 +2110,10 @@ ssh_public_key = "{}"
         utils::log("  ca ssh");
         utils::log("");
         utils::log("Or manually SSH:");
+        let host = self.dns_name().unwrap_or(vm_ip);
         utils::log(&format!(
             "  ssh -i ~/.ssh/cloud-auggie {}@{}",
-            self.config.ssh_username, vm_ip
+            self.config.ssh_username, host
         ));
         utils::log("");
         utils::log("Start working:");
@@ -644,12 +2121,14 @@ ssh_public_key = "{}"
         utils::log(&format!("  {}", self.config.agent));
         utils::log("");
         utils::log("Agent can commit and push:");
-        utils::log("  git checkout -b feature/my-changes");
+        utils::log("  git checkout -b cloud-agent/my-changes");
         utils::log("  git add . && git commit -m 'Changes from cloud-agent'");
-        utils::log("  git push -u origin feature/my-changes");
+        utils::log("  git push -u origin cloud-agent/my-changes");
         utils::log("");
         utils::log("VM management:");
         utils::log("  ca list       # List VMs");
+        utils::log("  ca status     # VM status and blocked pushes");
+        utils::log("  ca queue add --repo <repo> \"<prompt>\"  # Queue a headless task");
         utils::log("  ca stop       # Stop VM");
         utils::log("  ca start      # Start VM");
         utils::log("  ca terminate  # Delete VM");
@@ -4,9 +4,175 @@
 //! repository and validating repository URLs.
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::process::Command;
 
 use crate::error::CloudAgentError;
+use crate::utils::shell_quote;
+
+/// Options controlling how much of a repository is fetched onto the VM
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloneOptions {
+    /// Create a shallow clone with this many commits (`--depth`)
+    pub depth: Option<u32>,
+
+    /// Partial clone filter, e.g. `blob:none` (`--filter`)
+    pub filter: Option<String>,
+
+    /// Paths to check out with sparse-checkout (cone mode)
+    pub sparse: Vec<String>,
+}
+
+impl CloneOptions {
+    /// Overlay `other` on top of these options (fields set in `other` win)
+    pub fn merge(&self, other: &CloneOptions) -> CloneOptions {
+        CloneOptions {
+            depth: other.depth.or(self.depth),
+            filter: other.filter.clone().or_else(|| self.filter.clone()),
+            sparse: if other.sparse.is_empty() {
+                self.sparse.clone()
+            } else {
+                other.sparse.clone()
+            },
+        }
+    }
+}
+
+/// A repository to deploy along with its clone options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoSpec {
+    /// Repository URL
+    pub url: String,
+
+    /// Directory name under /workspace
+    pub name: String,
+
+    /// Clone options for this repository
+    pub options: CloneOptions,
+
+    /// `core.sshCommand` to use for this repository (e.g. a deploy key)
+    pub ssh_command: Option<String>,
+}
+
+impl RepoSpec {
+    /// Build the shell snippet that clones (or updates) this repo.
+    ///
+    /// Must be run from `/workspace`.
+    pub fn clone_script(&self) -> String {
+        let name = shell_quote(&self.name);
+        let opts = &self.options;
+
+        let mut clone_args = Vec::new();
+        if let Some(depth) = opts.depth {
+            clone_args.push(format!("--depth {}", depth));
+        }
+        if let Some(filter) = &opts.filter {
+            clone_args.push(format!("--filter={}", shell_quote(filter)));
+        }
+        if !opts.sparse.is_empty() {
+            clone_args.push("--sparse".to_string());
+        }
+
+        let sparse_set = if opts.sparse.is_empty() {
+            String::new()
+        } else {
+            let paths: Vec<String> = opts.sparse.iter().map(|p| shell_quote(p)).collect();
+            format!(" && git sparse-checkout set --cone {}", paths.join(" "))
+        };
+
+        // Shallow repos must stay shallow on pull; a plain `git pull` would
+        // try to merge against history that isn't there
+        let pull_depth = opts
+            .depth
+            .map(|d| format!(" --depth {}", d))
+            .unwrap_or_default();
+
+        let mut clone_cmd = vec!["git clone".to_string()];
+        if let Some(ssh_command) = &self.ssh_command {
+            clone_cmd.push(format!(
+                "-c {}",
+                shell_quote(&format!("core.sshCommand={}", ssh_command))
+            ));
+        }
+        clone_cmd.extend(clone_args);
+        clone_cmd.push(shell_quote(&self.url));
+        clone_cmd.push(name.clone());
+
+        format!(
+            "if [ -d {name} ]; then \
+                 echo '  ⚠️  {raw} already exists, pulling latest...' && \
+                 cd {name}{sparse_set} && \
+                 if [ \"$(git rev-parse --is-shallow-repository)\" = true ]; then \
+                     git pull --ff-only{pull_depth}; \
+                 else \
+                     git pull; \
+                 fi; \
+             else \
+                 {clone} && \
+                 cd {name}{sparse_set} && \
+                 echo '  ✅ Cloned {raw}'; \
+             fi && {post}",
+            name = name,
+            raw = self.name,
+            sparse_set = sparse_set,
+            pull_depth = pull_depth,
+            clone = clone_cmd.join(" "),
+            post = POST_CHECKOUT_SCRIPT,
+        )
+    }
+}
+
+/// Shell snippet run inside a freshly cloned or pulled repo.
+///
+/// Initializes submodules (after trusting any SSH hosts they reference) and
+/// fetches Git LFS objects when `.gitattributes` declares an LFS filter.
+const POST_CHECKOUT_SCRIPT: &str = "\
+    if [ -f .gitmodules ]; then \
+        echo '  Updating submodules...' && \
+        for host in $(git config -f .gitmodules --get-regexp '\\.url$' \
+            | sed -n -e 's#^[^ ]* ssh://\\([^@/]*@\\)\\{0,1\\}\\([^:/]*\\).*#\\2#p' \
+                     -e 's#^[^ ]* [^@ ]*@\\([^:/]*\\):.*#\\1#p' | sort -u); do \
+            ssh-keygen -F \"$host\" >/dev/null 2>&1 || \
+                ssh-keyscan \"$host\" >> ~/.ssh/known_hosts 2>/dev/null; \
+        done; \
+        git submodule sync --recursive && \
+        git submodule update --init --recursive --jobs 4; \
+    fi && \
+    if git grep -q 'filter=lfs' -- ':(glob)**/.gitattributes' 2>/dev/null; then \
+        if git lfs version >/dev/null 2>&1; then \
+            echo '  Fetching Git LFS objects...' && \
+            git lfs install --local >/dev/null && \
+            git lfs pull && \
+            git submodule foreach --recursive --quiet 'git lfs pull' >/dev/null; \
+        else \
+            echo '  ⚠️  Repository uses Git LFS but git-lfs is not installed on the VM'; \
+        fi; \
+    fi";
+
+/// Parse a comma-separated list of sparse-checkout paths
+pub fn parse_sparse_paths(paths: &str) -> Vec<String> {
+    paths
+        .split(',')
+        .map(|p| p.trim().trim_matches('/').to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// Read a value from the local git configuration
+pub fn local_config(key: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "--get", key])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
 
 /// Detect the current git repository's origin URL
 pub fn detect_current_repo() -> Result<Vec<String>> {
@@ -51,10 +217,13 @@ pub fn detect_current_repo() -> Result<Vec<String>> {
 }
 
 /// Validate a repository URL
-#[cfg(test)]
 pub fn validate_repo_url(url: &str) -> Result<()> {
     // Check if it's a valid SSH or HTTPS URL
-    if url.starts_with("git@") || url.starts_with("https://") || url.starts_with("http://") {
+    if url.starts_with("git@")
+        || url.starts_with("ssh://")
+        || url.starts_with("https://")
+        || url.starts_with("http://")
+    {
         Ok(())
     } else {
         Err(CloudAgentError::InvalidRepoUrl(url.to_string()).into())
@@ -69,6 +238,70 @@ mod tests {
     fn test_validate_repo_url() {
         assert!(validate_repo_url("git@github.com:org/repo.git").is_ok());
         assert!(validate_repo_url("https://github.com/org/repo.git").is_ok());
+        assert!(validate_repo_url("ssh://git@gitlab.com:2222/org/repo.git").is_ok());
         assert!(validate_repo_url("invalid-url").is_err());
     }
+
+    #[test]
+    fn test_clone_options_merge() {
+        let global = CloneOptions {
+            depth: Some(1),
+            filter: Some("blob:none".to_string()),
+            sparse: vec![],
+        };
+        let repo = CloneOptions {
+            depth: Some(50),
+            filter: None,
+            sparse: vec!["services/api".to_string()],
+        };
+
+        let merged = global.merge(&repo);
+        assert_eq!(merged.depth, Some(50));
+        assert_eq!(merged.filter.as_deref(), Some("blob:none"));
+        assert_eq!(merged.sparse, vec!["services/api"]);
+    }
+
+    #[test]
+    fn test_clone_script() {
+        let plain = RepoSpec {
+            url: "git@github.com:org/repo.git".to_string(),
+            name: "repo".to_string(),
+            options: CloneOptions::default(),
+            ssh_command: None,
+        };
+        let script = plain.clone_script();
+        assert!(script.contains("git clone 'git@github.com:org/repo.git' 'repo'"));
+        assert!(!script.contains("sparse-checkout"));
+
+        let partial = RepoSpec {
+            options: CloneOptions {
+                depth: Some(1),
+                filter: Some("blob:none".to_string()),
+                sparse: vec!["docs".to_string(), "src/core".to_string()],
+            },
+            ..plain
+        };
+        let script = partial.clone_script();
+        assert!(script.contains("git clone --depth 1 --filter='blob:none' --sparse"));
+        assert!(script.contains("git sparse-checkout set --cone 'docs' 'src/core'"));
+        assert!(script.contains("git pull --ff-only --depth 1"));
+        assert!(script.contains("git submodule update --init --recursive"));
+        assert!(script.contains("git lfs pull"));
+
+        let deploy_key = RepoSpec {
+            ssh_command: Some("ssh -i ~/.ssh/deploy_org_repo -o IdentitiesOnly=yes".to_string()),
+            ..partial
+        };
+        assert!(deploy_key.clone_script().contains(
+            "git clone -c 'core.sshCommand=ssh -i ~/.ssh/deploy_org_repo -o IdentitiesOnly=yes'"
+        ));
+    }
+
+    #[test]
+    fn test_parse_sparse_paths() {
+        assert_eq!(
+            parse_sparse_paths("docs/, src/core ,,"),
+            vec!["docs", "src/core"]
+        );
+    }
 }
@@ -0,0 +1,189 @@
+//! Managed git hooks for the VM
+//!
+//! cloud-agent installs its own hooks into a directory on the VM and points
+//! `core.hooksPath` at it. Each managed hook chains to the repository's own
+//! `.git/hooks/<name>` afterwards so existing hooks keep working.
+
+use crate::utils::shell_quote;
+
+/// Directory on the VM holding the managed hooks
+pub const HOOKS_DIR: &str = "~/.cloud-agent/git-hooks";
+
+/// Marker written into every managed hook
+const MANAGED_MARKER: &str = "# Managed by cloud-agent - changes will be overwritten on deploy";
+
+/// File on the VM where blocked pushes are recorded
+pub const BLOCKED_PUSH_LOG: &str = "~/.cloud-agent/blocked-pushes.log";
+
+/// Wrap a hook body with the shebang, marker and chaining to the repo's hook.
+///
+/// Hooks that consume stdin must save it in `$input` so it can be replayed
+/// to the chained hook (`forward_stdin`).
+fn managed_hook(name: &str, body: &str, forward_stdin: bool) -> String {
+    let chain = if forward_stdin {
+        "printf '%s\\n' \"$input\" | \"$repo_hook\" \"$@\"\n    exit $?"
+    } else {
+        "exec \"$repo_hook\" \"$@\""
+    };
+
+    format!(
+        "#!/bin/sh\n{marker}\n\n{body}\n\n\
+         repo_hook=\"$(git rev-parse --git-common-dir)/hooks/{name}\"\n\
+         if [ -x \"$repo_hook\" ]; then\n    {chain}\nfi\nexit 0\n",
+        marker = MANAGED_MARKER,
+        body = body.trim_end(),
+        name = name,
+        chain = chain,
+    )
+}
+
+/// `pre-push` hook that blocks pushes to `protected` branches, force-pushes,
+/// non-branch refs and (if `allowed` is non-empty) branches outside `allowed`.
+///
+/// Blocked attempts are appended to [`BLOCKED_PUSH_LOG`].
+pub fn pre_push_hook(protected: &[String], allowed: &[String]) -> String {
+    let mut body = format!(
+        r#"log_file="$HOME/{log}"
+input=$(cat)
+blocked=0
+
+is_zero() {{
+    case "$1" in *[!0]*) return 1 ;; esac
+    return 0
+}}
+
+block() {{
+    echo "cloud-agent: push to '$1' blocked: $2" >&2
+    mkdir -p "$(dirname "$log_file")"
+    printf '%s\t%s\t%s\t%s\n' "$(date -u +%Y-%m-%dT%H:%M:%SZ)" \
+        "$(basename "$(git rev-parse --show-toplevel)")" "$1" "$2" >> "$log_file"
+    blocked=1
+}}
+
+while read -r local_ref local_sha remote_ref remote_sha; do
+    [ -n "$remote_ref" ] || continue
+    case "$remote_ref" in
+        refs/heads/*) branch="${{remote_ref#refs/heads/}}" ;;
+        *) block "$remote_ref" "only branches may be pushed"; continue ;;
+    esac
+"#,
+        log = BLOCKED_PUSH_LOG.trim_start_matches("~/"),
+    );
+
+    if !protected.is_empty() {
+        body.push_str(&format!(
+            r#"    case "$branch" in
+        {}) block "$branch" "protected branch"; continue ;;
+    esac
+"#,
+            protected.join("|")
+        ));
+    }
+
+    if !allowed.is_empty() {
+        body.push_str(&format!(
+            r#"    case "$branch" in
+        {}) ;;
+        *) block "$branch" "branch must match {}"; continue ;;
+    esac
+"#,
+            allowed.join("|"),
+            allowed.join(", ")
+        ));
+    }
+
+    body.push_str(
+        r#"    if ! is_zero "$remote_sha" && ! is_zero "$local_sha" &&
+        ! git merge-base --is-ancestor "$remote_sha" "$local_sha" 2>/dev/null; then
+        block "$branch" "force-push"
+    fi
+done <<PUSH_REFS
+$input
+PUSH_REFS
+
+[ "$blocked" = 0 ] || exit 1"#,
+    );
+
+    managed_hook("pre-push", &body, true)
+}
+
+/// Check that branch globs only use characters safe inside a shell `case`
+pub fn validate_branch_globs(globs: &[String]) -> bool {
+    globs.iter().all(|g| {
+        !g.is_empty()
+            && g.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._/*-".contains(c))
+    })
+}
+
+/// `commit-msg` hook that appends `trailers` (e.g. `Agent: claude`)
+pub fn commit_msg_hook(trailers: &[String]) -> String {
+    let body = if trailers.is_empty() {
+        ":".to_string()
+    } else {
+        let args: Vec<String> = trailers
+            .iter()
+            .map(|t| format!("--trailer {}", shell_quote(t)))
+            .collect();
+        format!(
+            "git interpret-trailers --in-place --if-exists addIfDifferent {} \"$1\"",
+            args.join(" ")
+        )
+    };
+
+    managed_hook("commit-msg", &body, false)
+}
+
+/// Shell command that writes `hooks` (name, content) into [`HOOKS_DIR`]
+/// and enables them globally
+pub fn install_script(hooks: &[(&str, String)]) -> String {
+    let mut commands = vec![format!("mkdir -p {}", HOOKS_DIR)];
+    for (name, content) in hooks {
+        let path = format!("{}/{}", HOOKS_DIR, name);
+        commands.push(format!(
+            "printf '%s' {} > {} && chmod 755 {}",
+            shell_quote(content),
+            path,
+            path
+        ));
+    }
+    commands.push(format!("git config --global core.hooksPath {}", HOOKS_DIR));
+    commands.join(" && ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_msg_hook() {
+        let hook = commit_msg_hook(&[
+            "Agent: claude".to_string(),
+            "Cloud-Agent-VM: jdoe-cloud-agent".to_string(),
+        ]);
+        assert!(hook.starts_with("#!/bin/sh\n"));
+        assert!(hook.contains(
+            "--trailer 'Agent: claude' --trailer 'Cloud-Agent-VM: jdoe-cloud-agent' \"$1\""
+        ));
+        assert!(hook.contains("/hooks/commit-msg\""));
+    }
+
+    #[test]
+    fn test_pre_push_hook() {
+        let hook = pre_push_hook(
+            &["main".to_string(), "release/*".to_string()],
+            &["cloud-agent/*".to_string()],
+        );
+        assert!(hook.contains("main|release/*) block \"$branch\" \"protected branch\""));
+        assert!(hook.contains("cloud-agent/*) ;;"));
+        assert!(hook.contains("block \"$branch\" \"force-push\""));
+        assert!(hook.contains("| \"$repo_hook\" \"$@\""));
+    }
+
+    #[test]
+    fn test_validate_branch_globs() {
+        assert!(validate_branch_globs(&["release/*".to_string()]));
+        assert!(!validate_branch_globs(&["main;rm -rf".to_string()]));
+        assert!(!validate_branch_globs(&["".to_string()]));
+    }
+}
@@ -0,0 +1,303 @@
+//! GitHub REST API client for cloud-agent
+//!
+//! This module wraps the handful of GitHub endpoints cloud-agent needs.
+//! The API base URL is configurable so tests (and GitHub Enterprise) can
+//! point it somewhere other than api.github.com.
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::credentials::GitHost;
+use crate::error::CloudAgentError;
+
+/// Default GitHub REST API endpoint
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// A repository deploy key as returned by the API
+#[derive(Debug, Deserialize)]
+pub struct DeployKey {
+    pub id: u64,
+}
+
+/// Minimal GitHub REST API client
+pub struct GitHubClient {
+    api_url: String,
+    token: String,
+    http: reqwest::Client,
+}
+
+impl GitHubClient {
+    /// Create a new client for `api_url` authenticated with `token`
+    pub fn new(api_url: &str, token: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.api_url, path))
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header(
+                "User-Agent",
+                concat!("cloud-agent/", env!("CARGO_PKG_VERSION")),
+            )
+    }
+
+    /// Turn a non-success response into a `GitHubApi` error
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        Err(CloudAgentError::GitHubApi(format!("{}: {}", status, body.trim())).into())
+    }
+
+    /// Register a deploy key on `owner/repo`
+    pub async fn add_deploy_key(
+        &self,
+        owner: &str,
+        repo: &str,
+        title: &str,
+        public_key: &str,
+        read_only: bool,
+    ) -> Result<DeployKey> {
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                &format!("/repos/{}/{}/keys", owner, repo),
+            )
+            .json(&json!({
+                "title": title,
+                "key": public_key,
+                "read_only": read_only,
+            }))
+            .send()
+            .await?;
+
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    /// Remove a deploy key from `owner/repo`
+    pub async fn delete_deploy_key(&self, owner: &str, repo: &str, id: u64) -> Result<()> {
+        let response = self
+            .request(
+                reqwest::Method::DELETE,
+                &format!("/repos/{}/{}/keys/{}", owner, repo, id),
+            )
+            .send()
+            .await?;
+
+        // Already gone is as good as deleted
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+
+        Self::check(response).await?;
+        Ok(())
+    }
+
+    /// Find the open pull request for `branch` on `owner/repo`, if any
+    pub async fn find_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+    ) -> Result<Option<PullRequest>> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/repos/{}/{}/pulls", owner, repo),
+            )
+            .query(&[
+                ("head", format!("{}:{}", owner, branch)),
+                ("state", "open".to_string()),
+            ])
+            .send()
+            .await?;
+
+        let pulls: Vec<PullRequest> = Self::check(response).await?.json().await?;
+        Ok(pulls.into_iter().next())
+    }
+
+    /// Open a pull request from `head` into `base`
+    pub async fn create_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        new_pr: &NewPullRequest<'_>,
+    ) -> Result<PullRequest> {
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                &format!("/repos/{}/{}/pulls", owner, repo),
+            )
+            .json(&json!({
+                "title": new_pr.title,
+                "head": new_pr.head,
+                "base": new_pr.base,
+                "body": new_pr.body,
+                "draft": new_pr.draft,
+            }))
+            .send()
+            .await?;
+
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    /// Replace the body of an existing pull request
+    pub async fn update_pull_request_body(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u64,
+        body: &str,
+    ) -> Result<PullRequest> {
+        let response = self
+            .request(
+                reqwest::Method::PATCH,
+                &format!("/repos/{}/{}/pulls/{}", owner, repo, number),
+            )
+            .json(&json!({ "body": body }))
+            .send()
+            .await?;
+
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    /// Add labels to an issue or pull request
+    pub async fn add_labels(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u64,
+        labels: &[String],
+    ) -> Result<()> {
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                &format!("/repos/{}/{}/issues/{}/labels", owner, repo, number),
+            )
+            .json(&json!({ "labels": labels }))
+            .send()
+            .await?;
+
+        Self::check(response).await?;
+        Ok(())
+    }
+}
+
+/// A pull request as returned by the API
+#[derive(Debug, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub html_url: String,
+}
+
+/// Fields for opening a new pull request
+pub struct NewPullRequest<'a> {
+    pub title: &'a str,
+    pub head: &'a str,
+    pub base: &'a str,
+    pub body: &'a str,
+    pub draft: bool,
+}
+
+/// Split a GitHub repository URL into `(owner, repo)`.
+///
+/// Returns `None` for repositories hosted elsewhere.
+pub fn repo_slug(url: &str) -> Option<(String, String)> {
+    let host = GitHost::from_url(url).ok()?.resolve_alias();
+    if host.hostname != "github.com" {
+        return None;
+    }
+
+    let path = if let Some((_, rest)) = url.split_once("://") {
+        rest.split_once('/')?.1
+    } else {
+        url.split_once(':')?.1
+    };
+
+    let mut parts = path
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .split('/');
+    let owner = parts.next().filter(|s| !s.is_empty())?;
+    let repo = parts.next().filter(|s| !s.is_empty())?;
+    Some((owner.to_string(), repo.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repo_slug() {
+        assert_eq!(
+            repo_slug("git@github.com:org/repo.git"),
+            Some(("org".to_string(), "repo".to_string()))
+        );
+        assert_eq!(
+            repo_slug("https://github.com/org/repo"),
+            Some(("org".to_string(), "repo".to_string()))
+        );
+        assert_eq!(repo_slug("git@gitlab.com:org/repo.git"), None);
+    }
+
+    #[tokio::test]
+    async fn test_deploy_key_roundtrip() {
+        let mut server = mockito::Server::new_async().await;
+        let add = server
+            .mock("POST", "/repos/org/repo/keys")
+            .match_header("authorization", "Bearer tok")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "title": "vm-1",
+                "read_only": false,
+            })))
+            .with_status(201)
+            .with_body(r#"{"id": 42, "key": "ssh-ed25519 AAAA"}"#)
+            .create_async()
+            .await;
+        let delete = server
+            .mock("DELETE", "/repos/org/repo/keys/42")
+            .with_status(204)
+            .create_async()
+            .await;
+
+        let client = GitHubClient::new(&server.url(), "tok");
+        let key = client
+            .add_deploy_key("org", "repo", "vm-1", "ssh-ed25519 AAAA", false)
+            .await
+            .unwrap();
+        assert_eq!(key.id, 42);
+        client.delete_deploy_key("org", "repo", 42).await.unwrap();
+
+        add.assert_async().await;
+        delete.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_api_error() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/repos/org/repo/keys")
+            .with_status(422)
+            .with_body(r#"{"message": "key is already in use"}"#)
+            .create_async()
+            .await;
+
+        let client = GitHubClient::new(&server.url(), "tok");
+        let err = client
+            .add_deploy_key("org", "repo", "vm-1", "ssh-ed25519 AAAA", false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already in use"));
+    }
+}
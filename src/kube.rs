@@ -0,0 +1,141 @@
+//! GKE cluster access from the VM
+//!
+//! Clusters given with `--cluster` are recorded in a file on the VM and
+//! their credentials are fetched as the SSH user, one kubeconfig context per
+//! cluster named after it. `ca kube refresh` re-runs the same script, so it
+//! picks up endpoint or certificate changes without redeploying.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CloudAgentError;
+use crate::utils::shell_quote;
+
+/// Clusters configured on the VM, one `name location` pair per line
+pub const CLUSTERS_FILE: &str = "~/.cloud-agent/clusters";
+
+/// A GKE cluster and its location (zone or region)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GkeCluster {
+    pub name: String,
+    pub location: String,
+}
+
+/// Parse `--cluster` values as `NAME` or `NAME@LOCATION`, using
+/// `default_location` for clusters without one
+pub fn parse_clusters(values: &[String], default_location: &str) -> Result<Vec<GkeCluster>> {
+    let mut clusters: Vec<GkeCluster> = Vec::new();
+    for value in values.iter().map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let (name, location) = value.split_once('@').unwrap_or((value, default_location));
+        let valid = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        };
+        if !valid(name) || !valid(location) {
+            return Err(CloudAgentError::ConfigError(format!(
+                "Invalid --cluster '{}' (expected NAME or NAME@LOCATION)",
+                value
+            ))
+            .into());
+        }
+
+        let cluster = GkeCluster {
+            name: name.to_string(),
+            location: location.to_string(),
+        };
+        if !clusters.contains(&cluster) {
+            clusters.push(cluster);
+        }
+    }
+    Ok(clusters)
+}
+
+/// Shell command that fetches credentials for the clusters in
+/// [`CLUSTERS_FILE`], replacing the file with `clusters` first when given.
+///
+/// Each cluster gets a context named after it; the first one becomes the
+/// current context.
+pub fn credentials_script(project_id: &str, clusters: Option<&[GkeCluster]>) -> String {
+    let write = match clusters {
+        Some(clusters) => {
+            let content: String = clusters
+                .iter()
+                .map(|c| format!("{} {}\n", c.name, c.location))
+                .collect();
+            format!(
+                "mkdir -p ~/.cloud-agent && printf '%s' {} > {} && ",
+                shell_quote(&content),
+                CLUSTERS_FILE
+            )
+        }
+        None => String::new(),
+    };
+
+    format!(
+        "{write}[ -s {file} ] || {{ echo 'No clusters configured (use --cluster)' >&2; exit 1; }}; \
+         project={project}; first=; failed=0; \
+         while read -r name location; do \
+             [ -n \"$name\" ] || continue; \
+             echo \"Fetching credentials for $name ($location)\"; \
+             if gcloud container clusters get-credentials \"$name\" --location \"$location\" \
+                    --project \"$project\" --quiet < /dev/null; then \
+                 kubectl config delete-context \"$name\" > /dev/null 2>&1; \
+                 kubectl config rename-context \"gke_${{project}}_${{location}}_${{name}}\" \"$name\" > /dev/null && \
+                 first=${{first:-$name}}; \
+             else failed=1; fi; \
+         done < {file}; \
+         [ -z \"$first\" ] || kubectl config use-context \"$first\" > /dev/null; \
+         kubectl config get-contexts; \
+         exit $failed",
+        write = write,
+        file = CLUSTERS_FILE,
+        project = shell_quote(project_id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_clusters() {
+        let clusters = parse_clusters(
+            &[
+                "prod@us-central1".to_string(),
+                "dev".to_string(),
+                "dev".to_string(),
+            ],
+            "us-east1-b",
+        )
+        .unwrap();
+        assert_eq!(
+            clusters,
+            vec![
+                GkeCluster {
+                    name: "prod".to_string(),
+                    location: "us-central1".to_string()
+                },
+                GkeCluster {
+                    name: "dev".to_string(),
+                    location: "us-east1-b".to_string()
+                },
+            ]
+        );
+
+        assert!(parse_clusters(&["prod@".to_string()], "us-east1-b").is_err());
+        assert!(parse_clusters(&["a;b".to_string()], "us-east1-b").is_err());
+        assert!(parse_clusters(&[], "us-east1-b").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_credentials_script() {
+        let clusters = parse_clusters(&["prod@us-central1".to_string()], "x").unwrap();
+        let script = credentials_script("my-proj", Some(&clusters));
+        assert!(script.starts_with("mkdir -p ~/.cloud-agent && printf '%s' 'prod us-central1\n'"));
+        assert!(script.contains("rename-context \"gke_${project}_${location}_${name}\""));
+
+        let refresh = credentials_script("my-proj", None);
+        assert!(refresh.starts_with("[ -s ~/.cloud-agent/clusters ]"));
+    }
+}
@@ -1,15 +1,31 @@
 //! Cloud Agent - Deploy repos to Cloud Agent VMs for AI coding agents
 //!
 //! This tool helps you create and manage Google Cloud VMs configured for
-//! running AI coding agents like Auggie, Claude Code, and Codex.
+//! running AI coding agents like Aider, Auggie, Claude Code, Codex, and Gemini
+//! CLI.
 
 mod agents;
+mod allowlist;
 mod cli;
 mod config;
+mod credentials;
+mod egress;
 mod error;
 mod gcp;
 mod git;
+mod git_hooks;
+mod github;
+mod kube;
+mod permissions;
+mod pr;
+mod public_ip;
+mod queue;
+mod run;
+mod settings;
+mod skill;
 mod ssh;
+mod state;
+mod update;
 mod utils;
 
 use anyhow::Result;
@@ -0,0 +1,445 @@
+//! VM service account permissions
+//!
+//! `--permissions` entries are resolved here into IAM grants before anything
+//! reaches Terraform. An entry is one of:
+//!
+//! - a shortcut from [`CATALOGUE`] (`storage`, `gke`, ...)
+//! - a scoped shortcut, `SHORTCUT:RESOURCE`, granting a narrower role on a
+//!   single resource (`storage:my-bucket`)
+//! - a predefined role (`roles/pubsub.subscriber`)
+//! - a custom role (`projects/my-project/roles/agentRole` or
+//!   `organizations/123/roles/agentRole`); project roles must belong to the
+//!   VM's project, since they can't be granted in another one
+//!
+//! Unknown shortcuts are rejected with a suggestion instead of being dropped.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CloudAgentError;
+
+/// A permission shortcut and the project roles it grants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permission {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub roles: &'static [&'static str],
+    pub description: &'static str,
+    /// Role granted by `NAME:RESOURCE` on that resource only
+    pub scoped: Option<ScopedGrant>,
+}
+
+/// How a shortcut is granted on a single resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopedGrant {
+    pub kind: ResourceKind,
+    pub role: &'static str,
+}
+
+/// Resources that support scoped grants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResourceKind {
+    Bucket,
+    Secret,
+}
+
+impl ResourceKind {
+    fn label(self) -> &'static str {
+        match self {
+            ResourceKind::Bucket => "BUCKET",
+            ResourceKind::Secret => "SECRET",
+        }
+    }
+
+    fn is_valid_name(self, name: &str) -> bool {
+        match self {
+            // Cloud Storage bucket naming rules (without dotted domain names)
+            ResourceKind::Bucket => {
+                (3..=63).contains(&name.len())
+                    && name.chars().all(|c| {
+                        c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.')
+                    })
+                    && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+                    && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+            }
+            ResourceKind::Secret => {
+                (1..=255).contains(&name.len())
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+            }
+        }
+    }
+}
+
+/// Every permission shortcut `--permissions` accepts
+pub const CATALOGUE: &[Permission] = &[
+    Permission {
+        name: "admin",
+        aliases: &[],
+        roles: &[
+            "roles/compute.admin",
+            "roles/container.admin",
+            "roles/storage.admin",
+            "roles/iam.serviceAccountUser",
+        ],
+        description: "Compute, GKE and Storage admin",
+        scoped: None,
+    },
+    Permission {
+        name: "compute",
+        aliases: &[],
+        roles: &["roles/compute.admin"],
+        description: "Compute Engine",
+        scoped: None,
+    },
+    Permission {
+        name: "gke",
+        aliases: &["container"],
+        roles: &["roles/container.admin"],
+        description: "GKE clusters and kubectl",
+        scoped: None,
+    },
+    Permission {
+        name: "storage",
+        aliases: &["gcs"],
+        roles: &["roles/storage.admin"],
+        description: "Cloud Storage",
+        scoped: Some(ScopedGrant {
+            kind: ResourceKind::Bucket,
+            role: "roles/storage.objectAdmin",
+        }),
+    },
+    Permission {
+        name: "network",
+        aliases: &[],
+        roles: &["roles/compute.networkAdmin"],
+        description: "VPC networks and firewalls",
+        scoped: None,
+    },
+    Permission {
+        name: "bigquery",
+        aliases: &["bq"],
+        roles: &["roles/bigquery.admin"],
+        description: "BigQuery",
+        scoped: None,
+    },
+    Permission {
+        name: "iam",
+        aliases: &[],
+        roles: &["roles/iam.serviceAccountUser"],
+        description: "Act as service accounts",
+        scoped: None,
+    },
+    Permission {
+        name: "logging",
+        aliases: &[],
+        roles: &["roles/logging.admin"],
+        description: "Cloud Logging",
+        scoped: None,
+    },
+    Permission {
+        name: "pubsub",
+        aliases: &[],
+        roles: &["roles/pubsub.admin"],
+        description: "Pub/Sub",
+        scoped: None,
+    },
+    Permission {
+        name: "sql",
+        aliases: &["cloudsql"],
+        roles: &["roles/cloudsql.admin"],
+        description: "Cloud SQL",
+        scoped: None,
+    },
+    Permission {
+        name: "secrets",
+        aliases: &["secretmanager"],
+        roles: &["roles/secretmanager.admin"],
+        description: "Secret Manager",
+        scoped: Some(ScopedGrant {
+            kind: ResourceKind::Secret,
+            role: "roles/secretmanager.secretAccessor",
+        }),
+    },
+    Permission {
+        name: "dns",
+        aliases: &[],
+        roles: &["roles/dns.admin"],
+        description: "Cloud DNS",
+        scoped: None,
+    },
+    Permission {
+        name: "run",
+        aliases: &["cloudrun"],
+        roles: &["roles/run.admin"],
+        description: "Cloud Run",
+        scoped: None,
+    },
+    Permission {
+        name: "functions",
+        aliases: &[],
+        roles: &["roles/cloudfunctions.admin"],
+        description: "Cloud Functions",
+        scoped: None,
+    },
+    Permission {
+        name: "vertex",
+        aliases: &[],
+        roles: &["roles/aiplatform.user"],
+        description: "Vertex AI (--service-account-auth)",
+        scoped: None,
+    },
+];
+
+/// An IAM role granted to the VM's service account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Grant {
+    /// Role on the whole project
+    Project { role: String },
+    /// Role on a single resource
+    Resource {
+        kind: ResourceKind,
+        name: String,
+        role: String,
+    },
+}
+
+/// Look up a shortcut by name or alias
+pub fn find(name: &str) -> Option<&'static Permission> {
+    CATALOGUE
+        .iter()
+        .find(|p| p.name == name || p.aliases.contains(&name))
+}
+
+/// Whether `role` is a predefined (`roles/...`) or custom role ID
+fn is_role_id(role: &str) -> bool {
+    let valid_id = |id: &str| {
+        !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+    };
+    let parts: Vec<&str> = role.split('/').collect();
+    match parts.as_slice() {
+        ["roles", id] => valid_id(id),
+        ["projects" | "organizations", parent, "roles", id] => !parent.is_empty() && valid_id(id),
+        _ => false,
+    }
+}
+
+/// Resolve one `--permissions` entry into grants for a VM in `project_id`
+pub fn parse(entry: &str, project_id: &str) -> Result<Vec<Grant>> {
+    let entry = entry.trim();
+
+    if entry.starts_with("roles/")
+        || entry.starts_with("projects/")
+        || entry.starts_with("organizations/")
+    {
+        if !is_role_id(entry) {
+            return Err(CloudAgentError::ConfigError(format!(
+                "Invalid role '{}' (expected roles/ROLE, projects/PROJECT/roles/ROLE \
+                 or organizations/ORG/roles/ROLE)",
+                entry
+            ))
+            .into());
+        }
+        if let Some(project) = entry
+            .strip_prefix("projects/")
+            .and_then(|rest| rest.split('/').next())
+            .filter(|project| *project != project_id)
+        {
+            return Err(CloudAgentError::ConfigError(format!(
+                "Custom role '{}' belongs to project '{}', not the VM's project '{}'",
+                entry, project, project_id
+            ))
+            .into());
+        }
+        return Ok(vec![Grant::Project {
+            role: entry.to_string(),
+        }]);
+    }
+
+    let (name, resource) = match entry.split_once(':') {
+        Some((name, resource)) => (name, Some(resource)),
+        None => (entry, None),
+    };
+    let permission = find(name).ok_or_else(|| unknown_permission(name))?;
+
+    match (resource, permission.scoped) {
+        (None, _) => Ok(permission
+            .roles
+            .iter()
+            .map(|role| Grant::Project {
+                role: role.to_string(),
+            })
+            .collect()),
+        (Some(resource), Some(scoped)) if scoped.kind.is_valid_name(resource) => {
+            Ok(vec![Grant::Resource {
+                kind: scoped.kind,
+                name: resource.to_string(),
+                role: scoped.role.to_string(),
+            }])
+        }
+        (Some(resource), Some(scoped)) => Err(CloudAgentError::ConfigError(format!(
+            "Invalid {} name '{}' in permission '{}'",
+            scoped.kind.label().to_lowercase(),
+            resource,
+            entry
+        ))
+        .into()),
+        (Some(_), None) => Err(CloudAgentError::ConfigError(format!(
+            "Permission '{}' can't be scoped to a resource (scoped grants: {})",
+            name,
+            CATALOGUE
+                .iter()
+                .filter_map(|p| p.scoped.map(|s| format!("{}:{}", p.name, s.kind.label())))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+        .into()),
+    }
+}
+
+/// Resolve every `--permissions` entry, dropping duplicate grants
+pub fn resolve(entries: &[String], project_id: &str) -> Result<Vec<Grant>> {
+    let mut grants: Vec<Grant> = Vec::new();
+    for entry in entries.iter().filter(|e| !e.trim().is_empty()) {
+        for grant in parse(entry, project_id)? {
+            if !grants.contains(&grant) {
+                grants.push(grant);
+            }
+        }
+    }
+    Ok(grants)
+}
+
+fn unknown_permission(name: &str) -> anyhow::Error {
+    let suggestion = CATALOGUE
+        .iter()
+        .flat_map(|p| std::iter::once(p.name).chain(p.aliases.iter().copied()))
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!(" Did you mean '{}'?", candidate))
+        .unwrap_or_default();
+
+    CloudAgentError::ConfigError(format!(
+        "Unknown permission '{}'.{} Run 'ca permissions list' for the available \
+         permissions, or pass a role like roles/pubsub.subscriber",
+        name, suggestion
+    ))
+    .into()
+}
+
+/// Levenshtein distance between two short ASCII strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Print the catalogue (`ca permissions list`)
+pub fn list() {
+    println!("{:<24} {:<34} ROLES", "PERMISSION", "DESCRIPTION");
+    for permission in CATALOGUE {
+        let mut name = permission.name.to_string();
+        if !permission.aliases.is_empty() {
+            name = format!("{} ({})", name, permission.aliases.join(", "));
+        }
+        println!(
+            "{:<24} {:<34} {}",
+            name,
+            permission.description,
+            permission.roles.join(", ")
+        );
+        if let Some(scoped) = permission.scoped {
+            println!(
+                "{:<24} {:<34} {} on that {} only",
+                format!("{}:{}", permission.name, scoped.kind.label()),
+                "",
+                scoped.role,
+                scoped.kind.label().to_lowercase()
+            );
+        }
+    }
+    println!();
+    println!("Roles can also be given directly: roles/ROLE, projects/PROJECT/roles/ROLE");
+    println!("or organizations/ORG/roles/ROLE.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(role: &str) -> Grant {
+        Grant::Project {
+            role: role.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_shortcuts_and_roles() {
+        assert_eq!(
+            parse("bq", "my-proj").unwrap(),
+            vec![project("roles/bigquery.admin")]
+        );
+        assert_eq!(parse("admin", "my-proj").unwrap().len(), 4);
+        assert_eq!(
+            parse("roles/pubsub.subscriber", "my-proj").unwrap(),
+            vec![project("roles/pubsub.subscriber")]
+        );
+        assert_eq!(
+            parse("projects/my-proj/roles/agentRole", "my-proj").unwrap(),
+            vec![project("projects/my-proj/roles/agentRole")]
+        );
+        assert!(parse("projects/other-proj/roles/agentRole", "my-proj").is_err());
+        assert!(parse("roles/", "my-proj").is_err());
+        assert!(parse("projects/my-proj/agentRole", "my-proj").is_err());
+    }
+
+    #[test]
+    fn test_parse_scoped() {
+        assert_eq!(
+            parse("storage:my-bucket", "my-proj").unwrap(),
+            vec![Grant::Resource {
+                kind: ResourceKind::Bucket,
+                name: "my-bucket".to_string(),
+                role: "roles/storage.objectAdmin".to_string(),
+            }]
+        );
+        assert!(parse("storage:My_Bucket!", "my-proj").is_err());
+        let err = parse("gke:prod", "my-proj").unwrap_err().to_string();
+        assert!(err.contains("storage:BUCKET, secrets:SECRET"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_permission_suggests() {
+        let err = parse("storge", "my-proj").unwrap_err().to_string();
+        assert!(err.contains("Did you mean 'storage'?"), "{}", err);
+        let err = parse("kubernetes", "my-proj").unwrap_err().to_string();
+        assert!(!err.contains("Did you mean"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_dedups() {
+        let grants = resolve(
+            &["admin".to_string(), "compute".to_string(), " ".to_string()],
+            "my-proj",
+        )
+        .unwrap();
+        assert_eq!(grants.len(), 4);
+    }
+}
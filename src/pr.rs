@@ -0,0 +1,312 @@
+//! Pull requests for agent branches
+//!
+//! `ca pr` asks the VM which local branches in `/workspace` are pushed and
+//! ahead of the default branch, then opens (or refreshes) a GitHub pull
+//! request for each one.
+
+use anyhow::Result;
+
+use crate::github::{GitHubClient, NewPullRequest, PullRequest};
+use crate::utils::shell_quote;
+
+/// Shell snippet run inside each repo that reports pushed branches ahead of
+/// the default branch as tab-separated `REPO`, `BRANCH` and `COMMIT` lines
+const BRANCH_REPORT_SCRIPT: &str = r#"url=$(git remote get-url origin 2>/dev/null) || exit 0
+git fetch --quiet --prune origin >/dev/null 2>&1 || echo "fetch of $repo failed" >&2
+default=$(git symbolic-ref --quiet --short refs/remotes/origin/HEAD 2>/dev/null)
+if [ -z "$default" ]; then
+    git remote set-head origin --auto >/dev/null 2>&1
+    default=$(git symbolic-ref --quiet --short refs/remotes/origin/HEAD 2>/dev/null)
+fi
+default=${default#origin/}
+[ -n "$default" ] || exit 0
+printf 'REPO\t%s\t%s\t%s\n' "$repo" "$url" "$default"
+tab=$(printf '\t')
+git for-each-ref --format="%(refname:short)$tab%(upstream:lstrip=3)$tab%(upstream:remotename)" refs/heads |
+while IFS="$tab" read -r local upstream remote; do
+    [ "$remote" = origin ] && [ -n "$upstream" ] && [ "$upstream" != "$default" ] || continue
+    git rev-parse --quiet --verify "refs/remotes/origin/$upstream" >/dev/null || continue
+    [ "$(git rev-list --count "origin/$default..origin/$upstream")" -gt 0 ] || continue
+    printf 'BRANCH\t%s\n' "$upstream"
+    git log --reverse --format='COMMIT%x09%h%x09%s' "origin/$default..origin/$upstream"
+done"#;
+
+/// Build the command that reports branches for one repo, or every repo in
+/// `/workspace` when `repo` is `None`
+pub fn report_command(repo: Option<&str>) -> String {
+    let repos = match repo {
+        Some(name) => shell_quote(name),
+        None => "*/".to_string(),
+    };
+
+    format!(
+        "cd /workspace && for repo in {}; do \
+             repo=${{repo%/}}; \
+             [ -d \"$repo/.git\" ] || continue; \
+             (cd \"$repo\" && {}); \
+         done",
+        repos, BRANCH_REPORT_SCRIPT
+    )
+}
+
+/// A pushed branch that is ahead of the default branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchReport {
+    /// Branch name on origin
+    pub name: String,
+
+    /// Commits ahead of the default branch as `(short sha, subject)`, oldest first
+    pub commits: Vec<(String, String)>,
+}
+
+/// Branch report for one repository in `/workspace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoReport {
+    /// Directory name under `/workspace`
+    pub name: String,
+
+    /// Origin URL
+    pub url: String,
+
+    /// Default branch on origin
+    pub default_branch: String,
+
+    /// Branches ahead of the default branch
+    pub branches: Vec<BranchReport>,
+}
+
+/// Parse the output of [`report_command`]
+pub fn parse_report(output: &str) -> Vec<RepoReport> {
+    let mut repos: Vec<RepoReport> = Vec::new();
+
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields[..] {
+            ["REPO", name, url, default_branch] => repos.push(RepoReport {
+                name: name.to_string(),
+                url: url.to_string(),
+                default_branch: default_branch.to_string(),
+                branches: Vec::new(),
+            }),
+            ["BRANCH", name] => {
+                if let Some(repo) = repos.last_mut() {
+                    repo.branches.push(BranchReport {
+                        name: name.to_string(),
+                        commits: Vec::new(),
+                    });
+                }
+            }
+            ["COMMIT", sha, ref subject @ ..] => {
+                if let Some(branch) = repos.last_mut().and_then(|r| r.branches.last_mut()) {
+                    branch.commits.push((sha.to_string(), subject.join("\t")));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    repos
+}
+
+/// Pull request title: the commit subject for single-commit branches,
+/// otherwise the branch name
+pub fn pr_title(branch: &BranchReport) -> String {
+    match &branch.commits[..] {
+        [(_, subject)] => subject.clone(),
+        _ => branch.name.clone(),
+    }
+}
+
+/// Pull request body generated from the branch's commit log
+pub fn pr_body(branch: &BranchReport, vm_name: &str) -> String {
+    let commits: Vec<String> = branch
+        .commits
+        .iter()
+        .map(|(sha, subject)| format!("- {} {}", sha, subject))
+        .collect();
+
+    format!(
+        "Opened by cloud-agent from `{}`.\n\n## Commits\n\n{}\n",
+        vm_name,
+        commits.join("\n")
+    )
+}
+
+/// Whether [`sync_pull_request`] opened a new PR or refreshed an existing one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrAction {
+    Created,
+    Updated,
+}
+
+/// Options shared by every pull request `ca pr` opens
+pub struct PrOptions<'a> {
+    pub vm_name: &'a str,
+    pub draft: bool,
+    pub labels: &'a [String],
+}
+
+/// Open a pull request for `branch`, or update the body of the open one
+pub async fn sync_pull_request(
+    github: &GitHubClient,
+    owner: &str,
+    repo: &str,
+    base: &str,
+    branch: &BranchReport,
+    options: &PrOptions<'_>,
+) -> Result<(PrAction, PullRequest)> {
+    let body = pr_body(branch, options.vm_name);
+
+    let (action, pr) = match github.find_pull_request(owner, repo, &branch.name).await? {
+        Some(existing) => {
+            let pr = github
+                .update_pull_request_body(owner, repo, existing.number, &body)
+                .await?;
+            (PrAction::Updated, pr)
+        }
+        None => {
+            let title = pr_title(branch);
+            let pr = github
+                .create_pull_request(
+                    owner,
+                    repo,
+                    &NewPullRequest {
+                        title: &title,
+                        head: &branch.name,
+                        base,
+                        body: &body,
+                        draft: options.draft,
+                    },
+                )
+                .await?;
+            (PrAction::Created, pr)
+        }
+    };
+
+    if !options.labels.is_empty() {
+        github
+            .add_labels(owner, repo, pr.number, options.labels)
+            .await?;
+    }
+
+    Ok((action, pr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn branch() -> BranchReport {
+        BranchReport {
+            name: "cloud-agent/fix-lint".to_string(),
+            commits: vec![
+                ("abc1234".to_string(), "Fix clippy warnings".to_string()),
+                ("def5678".to_string(), "Format code".to_string()),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let output = "REPO\tapi\tgit@github.com:org/api.git\tmain\n\
+                      BRANCH\tcloud-agent/fix-lint\n\
+                      COMMIT\tabc1234\tFix clippy warnings\n\
+                      COMMIT\tdef5678\tFormat code\n\
+                      REPO\tweb\tgit@github.com:org/web.git\tdevelop\n";
+
+        let repos = parse_report(output);
+        assert_eq!(repos.len(), 2);
+        assert_eq!(repos[0].default_branch, "main");
+        assert_eq!(repos[0].branches, vec![branch()]);
+        assert!(repos[1].branches.is_empty());
+    }
+
+    #[test]
+    fn test_pr_title_and_body() {
+        let mut single = branch();
+        single.commits.truncate(1);
+        assert_eq!(pr_title(&single), "Fix clippy warnings");
+        assert_eq!(pr_title(&branch()), "cloud-agent/fix-lint");
+
+        let body = pr_body(&branch(), "jdoe-cloud-agent");
+        assert!(body.contains("`jdoe-cloud-agent`"));
+        assert!(body.contains("- abc1234 Fix clippy warnings\n- def5678 Format code"));
+    }
+
+    #[tokio::test]
+    async fn test_sync_creates_pull_request() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/org/api/pulls")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "head".into(),
+                "org:cloud-agent/fix-lint".into(),
+            ))
+            .with_body("[]")
+            .create_async()
+            .await;
+        let create = server
+            .mock("POST", "/repos/org/api/pulls")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "head": "cloud-agent/fix-lint",
+                "base": "main",
+                "draft": true,
+            })))
+            .with_status(201)
+            .with_body(r#"{"number": 7, "html_url": "https://github.com/org/api/pull/7"}"#)
+            .create_async()
+            .await;
+        let labels = server
+            .mock("POST", "/repos/org/api/issues/7/labels")
+            .match_body(mockito::Matcher::Json(json!({"labels": ["cloud-agent"]})))
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let github = GitHubClient::new(&server.url(), "tok");
+        let options = PrOptions {
+            vm_name: "vm",
+            draft: true,
+            labels: &["cloud-agent".to_string()],
+        };
+        let (action, pr) = sync_pull_request(&github, "org", "api", "main", &branch(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(action, PrAction::Created);
+        assert_eq!(pr.number, 7);
+        create.assert_async().await;
+        labels.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_sync_updates_existing_pull_request() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/org/api/pulls")
+            .match_query(mockito::Matcher::Any)
+            .with_body(r#"[{"number": 3, "html_url": "https://github.com/org/api/pull/3"}]"#)
+            .create_async()
+            .await;
+        let update = server
+            .mock("PATCH", "/repos/org/api/pulls/3")
+            .with_body(r#"{"number": 3, "html_url": "https://github.com/org/api/pull/3"}"#)
+            .create_async()
+            .await;
+
+        let github = GitHubClient::new(&server.url(), "tok");
+        let options = PrOptions {
+            vm_name: "vm",
+            draft: false,
+            labels: &[],
+        };
+        let (action, pr) = sync_pull_request(&github, "org", "api", "main", &branch(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(action, PrAction::Updated);
+        assert_eq!(pr.number, 3);
+        update.assert_async().await;
+    }
+}
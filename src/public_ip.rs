@@ -0,0 +1,306 @@
+//! Public IP detection
+//!
+//! The SSH firewall rule needs this machine's public address. It is asked of
+//! a list of plain-text "what is my IP" endpoints over a socket bound to the
+//! requested address family, so an IPv6-only or dual-stack network reports
+//! the address the VM will actually see. Answers are cached briefly on disk,
+//! and `--my-ip` skips detection entirely (offline or behind unusual NAT).
+//!
+//! ```toml
+//! [ip_detection]
+//! endpoints = ["https://ip.example.com"]
+//! timeout_secs = 5
+//! cache_secs = 300
+//! ```
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::CloudAgentError;
+use crate::utils;
+
+/// Endpoints answering with the caller's address as plain text. Each one
+/// serves both IPv4 and IPv6.
+pub const DEFAULT_ENDPOINTS: &[&str] = &[
+    "https://api64.ipify.org",
+    "https://ifconfig.me/ip",
+    "https://icanhazip.com",
+];
+
+/// Address family to detect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpFamily {
+    /// IPv4, falling back to IPv6 on IPv6-only networks
+    #[default]
+    Auto,
+
+    /// IPv4 only
+    V4,
+
+    /// IPv6 only
+    V6,
+}
+
+impl std::fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            IpFamily::Auto => "auto",
+            IpFamily::V4 => "v4",
+            IpFamily::V6 => "v6",
+        };
+        f.write_str(s)
+    }
+}
+
+/// `[ip_detection]` settings from the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpDetectionSettings {
+    /// Endpoints tried in order
+    pub endpoints: Vec<String>,
+
+    /// Timeout for each request
+    pub timeout_secs: u64,
+
+    /// How long a detected address is reused (0 disables the cache)
+    pub cache_secs: i64,
+}
+
+impl Default for IpDetectionSettings {
+    fn default() -> Self {
+        Self {
+            endpoints: DEFAULT_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            timeout_secs: 5,
+            cache_secs: 300,
+        }
+    }
+}
+
+/// The address as a single-host CIDR range (`/32` or `/128`)
+pub fn to_cidr(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("{}/32", ip),
+        IpAddr::V6(ip) => format!("{}/128", ip),
+    }
+}
+
+/// Detects the public address using a list of endpoints
+pub struct IpDetector {
+    settings: IpDetectionSettings,
+    cache_path: Option<PathBuf>,
+}
+
+impl IpDetector {
+    /// Detector caching answers in `cache_path` (no cache when `None`)
+    pub fn new(settings: IpDetectionSettings, cache_path: Option<PathBuf>) -> Self {
+        Self {
+            settings,
+            cache_path,
+        }
+    }
+
+    /// Detect the public address of `family`. A cached answer younger than
+    /// `cache_secs` is reused unless `fresh` is set.
+    pub async fn detect(&self, family: IpFamily, fresh: bool) -> Result<IpAddr> {
+        let now = chrono::Utc::now().timestamp();
+        let mut cache = self.read_cache();
+        if !fresh {
+            if let Some(cached) = cache.get(&family.to_string()) {
+                if now - cached.checked_at < self.settings.cache_secs {
+                    return Ok(cached.ip);
+                }
+            }
+        }
+
+        let families: &[IpFamily] = match family {
+            IpFamily::Auto => &[IpFamily::V4, IpFamily::V6],
+            IpFamily::V4 => &[IpFamily::V4],
+            IpFamily::V6 => &[IpFamily::V6],
+        };
+        for candidate in families {
+            for endpoint in &self.settings.endpoints {
+                match self.query(endpoint, *candidate).await {
+                    Ok(ip) => {
+                        cache.insert(
+                            family.to_string(),
+                            CachedIp {
+                                ip,
+                                checked_at: now,
+                            },
+                        );
+                        self.write_cache(&cache);
+                        return Ok(ip);
+                    }
+                    Err(e) => tracing::debug!("{} ({}): {}", endpoint, candidate, e),
+                }
+            }
+        }
+
+        Err(CloudAgentError::IpDetectionFailed.into())
+    }
+
+    /// Ask one endpoint over a socket of `family` (V4 or V6)
+    async fn query(&self, endpoint: &str, family: IpFamily) -> Result<IpAddr> {
+        let local: IpAddr = match family {
+            IpFamily::V6 => Ipv6Addr::UNSPECIFIED.into(),
+            _ => Ipv4Addr::UNSPECIFIED.into(),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.settings.timeout_secs))
+            .local_address(local)
+            .build()?;
+
+        let body = client
+            .get(endpoint)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let ip: IpAddr = body
+            .trim()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("not an IP address: {:?}", body.trim()))?;
+
+        if ip.is_ipv4() != (family != IpFamily::V6) {
+            return Err(anyhow::anyhow!("{} is not an {} address", ip, family));
+        }
+        Ok(ip)
+    }
+
+    fn read_cache(&self) -> BTreeMap<String, CachedIp> {
+        self.cache_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn write_cache(&self, cache: &BTreeMap<String, CachedIp>) {
+        if let (Some(path), Ok(json)) = (&self.cache_path, serde_json::to_string(cache)) {
+            if let Some(dir) = path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(path, json);
+        }
+    }
+}
+
+/// A previously detected address, keyed by requested family
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedIp {
+    ip: IpAddr,
+    checked_at: i64,
+}
+
+/// This machine's public address as a CIDR range, honouring `--my-ip`,
+/// `--ip-family` and the `[ip_detection]` settings. `fresh` bypasses the cache
+/// (after a connection failure the network may just have changed).
+pub async fn detect(config: &Config, fresh: bool) -> Result<String> {
+    if let Some(ip) = config.my_ip {
+        utils::log(&format!("✓ Your public IP (--my-ip): {}", ip));
+        return Ok(to_cidr(ip));
+    }
+
+    let cache_path = dirs::home_dir().map(|h| h.join(".config/cloud-agent/public-ip.json"));
+    let ip = IpDetector::new(config.ip_detection.clone(), cache_path)
+        .detect(config.ip_family, fresh)
+        .await?;
+    utils::log(&format!("✓ Your public IP: {}", ip));
+    Ok(to_cidr(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(endpoints: Vec<String>, cache: Option<PathBuf>) -> IpDetector {
+        IpDetector::new(
+            IpDetectionSettings {
+                endpoints,
+                timeout_secs: 2,
+                cache_secs: 300,
+            },
+            cache,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_detect_falls_through_bad_endpoints() {
+        let mut server = mockito::Server::new_async().await;
+        let broken = server
+            .mock("GET", "/broken")
+            .with_status(500)
+            .create_async()
+            .await;
+        let garbage = server
+            .mock("GET", "/garbage")
+            .with_body("01.1.1.1\n")
+            .create_async()
+            .await;
+        let good = server
+            .mock("GET", "/ip")
+            .with_body("203.0.113.7\n")
+            .create_async()
+            .await;
+
+        let endpoints = ["/broken", "/garbage", "/ip"]
+            .iter()
+            .map(|p| format!("{}{}", server.url(), p))
+            .collect();
+        let ip = detector(endpoints, None)
+            .detect(IpFamily::V4, false)
+            .await
+            .unwrap();
+
+        assert_eq!(ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+        broken.assert_async().await;
+        garbage.assert_async().await;
+        good.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_detect_checks_family() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/")
+            .with_body("2001:db8::1")
+            .create_async()
+            .await;
+
+        // An IPv6 answer doesn't satisfy an IPv4 request
+        let result = detector(vec![server.url()], None)
+            .detect(IpFamily::V4, false)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_detect_uses_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("public-ip.json");
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/")
+            .with_body("203.0.113.7")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let detector = detector(vec![server.url()], Some(cache));
+        for fresh in [false, false, true] {
+            let ip = detector.detect(IpFamily::Auto, fresh).await.unwrap();
+            assert_eq!(ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+        }
+        // The second lookup came from the cache
+        mock.assert_async().await;
+    }
+}
@@ -0,0 +1,243 @@
+//! Task queue on the VM
+//!
+//! `ca queue` manages a JSON-lines queue file on the VM. A small runner
+//! script, installed as a systemd user service during deploy, works through
+//! queued tasks one at a time using the agent's headless mode and records
+//! the status, exit code and log path of each task.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::agents::AGENT_ENV_FILE;
+use crate::egress::PROXY_ENV_FILE;
+use crate::utils::shell_quote;
+
+/// Runner script, embedded so deploy can install it on the VM
+const RUNNER_SCRIPT: &str = include_str!("scripts/queue-runner.sh");
+
+/// Where the runner script is installed on the VM
+pub const RUNNER_PATH: &str = "~/.cloud-agent/bin/cloud-agent-queue";
+
+/// Queue file on the VM (one JSON task per line)
+pub const QUEUE_FILE: &str = "~/.cloud-agent/queue/tasks.jsonl";
+
+/// Name of the systemd user service running the queue
+pub const SERVICE_NAME: &str = "cloud-agent-queue";
+
+/// Lifecycle of a queued task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl std::fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            TaskStatus::Queued => "queued",
+            TaskStatus::Running => "running",
+            TaskStatus::Done => "done",
+            TaskStatus::Failed => "failed",
+            TaskStatus::Cancelled => "cancelled",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A task in the queue file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Task {
+    /// Unique task ID
+    pub id: String,
+
+    /// Repository directory under /workspace
+    pub repo: String,
+
+    /// Agent name (e.g. "claude")
+    pub agent: String,
+
+    /// Prompt given to the agent
+    pub prompt: String,
+
+    /// Shell command the runner executes inside the repo
+    pub command: String,
+
+    pub status: TaskStatus,
+
+    pub created_at: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+
+    /// Log file on the VM, set once the task starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+}
+
+impl Task {
+    /// Create a queued task running `command` in `/workspace/<repo>`
+    pub fn new(repo: &str, agent: &str, prompt: &str, command: String) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            id: format!(
+                "{}-{:04x}",
+                now.format("%Y%m%d-%H%M%S"),
+                now.timestamp_subsec_micros() & 0xffff
+            ),
+            repo: repo.to_string(),
+            agent: agent.to_string(),
+            prompt: prompt.to_string(),
+            command,
+            status: TaskStatus::Queued,
+            created_at: now.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            started_at: None,
+            finished_at: None,
+            exit_code: None,
+            log: None,
+        }
+    }
+}
+
+/// systemd user unit running the queue
+fn service_unit() -> String {
+    format!(
+        "[Unit]\n\
+         Description=cloud-agent task queue\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         EnvironmentFile=-%h/{env_file}\n\
+         EnvironmentFile=-{proxy_env}\n\
+         ExecStart=%h/{runner} run\n\
+         Restart=on-failure\n\
+         RestartSec=10\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        runner = RUNNER_PATH.trim_start_matches("~/"),
+        env_file = AGENT_ENV_FILE.trim_start_matches("~/"),
+        proxy_env = PROXY_ENV_FILE,
+    )
+}
+
+/// Shell command that installs the runner and (re)starts its service.
+///
+/// The service is only restarted when the runner changed, so redeploying
+/// doesn't kill a task that is in progress.
+pub fn install_script() -> String {
+    let unit_path = format!("~/.config/systemd/user/{}.service", SERVICE_NAME);
+
+    format!(
+        "mkdir -p ~/.cloud-agent/bin ~/.config/systemd/user && \
+         printf '%s' {script} > {runner}.new && chmod 755 {runner}.new && \
+         printf '%s' {unit} > {unit_path}.new && \
+         changed=0 && \
+         for f in {runner} {unit_path}; do \
+             cmp -s \"$f.new\" \"$f\" && rm \"$f.new\" || {{ mv \"$f.new\" \"$f\"; changed=1; }}; \
+         done && \
+         {{ loginctl show-user \"$(id -un)\" -p Linger 2>/dev/null | grep -q yes || \
+             sudo loginctl enable-linger \"$(id -un)\"; }} && \
+         export XDG_RUNTIME_DIR=/run/user/$(id -u) && \
+         systemctl --user daemon-reload && \
+         systemctl --user enable --now {service} && \
+         if [ \"$changed\" = 1 ]; then systemctl --user restart {service}; fi",
+        script = shell_quote(RUNNER_SCRIPT),
+        unit = shell_quote(&service_unit()),
+        runner = RUNNER_PATH,
+        unit_path = unit_path,
+        service = SERVICE_NAME,
+    )
+}
+
+/// Shell command that restarts the runner if it is running, so it picks up
+/// a changed environment (such as the egress proxy)
+pub fn restart_command() -> String {
+    format!(
+        "export XDG_RUNTIME_DIR=/run/user/$(id -u) && systemctl --user try-restart {}",
+        SERVICE_NAME
+    )
+}
+
+/// Shell command that appends `task` to the queue
+pub fn add_command(task: &Task) -> Result<String> {
+    Ok(format!(
+        "{} add {}",
+        RUNNER_PATH,
+        shell_quote(&serde_json::to_string(task)?)
+    ))
+}
+
+/// Shell command that cancels the task with `id`
+pub fn cancel_command(id: &str) -> String {
+    format!("{} cancel {}", RUNNER_PATH, shell_quote(id))
+}
+
+/// Shell command that prints the queue file
+pub fn list_command() -> String {
+    format!("cat {} 2>/dev/null || true", QUEUE_FILE)
+}
+
+/// Parse the queue file, skipping lines that aren't tasks
+pub fn parse_tasks(output: &str) -> Vec<Task> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_roundtrip() {
+        let task = Task::new(
+            "api",
+            "claude",
+            "fix lint",
+            "claude -p 'fix lint'".to_string(),
+        );
+        assert_eq!(task.status, TaskStatus::Queued);
+
+        let json = serde_json::to_string(&task).unwrap();
+        assert!(json.contains("\"status\":\"queued\""));
+        assert!(!json.contains("exit_code"));
+
+        let command = add_command(&task).unwrap();
+        assert!(command.starts_with("~/.cloud-agent/bin/cloud-agent-queue add '{"));
+        assert!(command.contains("'\\''fix lint'\\''"));
+    }
+
+    #[test]
+    fn test_parse_tasks() {
+        let output = r#"{"id":"1","repo":"api","agent":"claude","prompt":"p","command":"c","status":"done","created_at":"t","exit_code":0,"log":"/l/1.log"}
+not json
+{"id":"2","repo":"web","agent":"codex","prompt":"p","command":"c","status":"running","created_at":"t","pid":42}
+"#;
+        let tasks = parse_tasks(output);
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].status, TaskStatus::Done);
+        assert_eq!(tasks[0].exit_code, Some(0));
+        assert_eq!(tasks[1].status, TaskStatus::Running);
+        assert_eq!(tasks[1].log, None);
+    }
+
+    #[test]
+    fn test_install_script() {
+        let script = install_script();
+        assert!(script.contains("ExecStart=%h/.cloud-agent/bin/cloud-agent-queue run"));
+        assert!(script.contains("systemctl --user enable --now cloud-agent-queue"));
+        assert!(script.contains("loginctl enable-linger"));
+        assert!(script.contains("EnvironmentFile=-/etc/cloud-agent/proxy.env"));
+    }
+}
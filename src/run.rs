@@ -0,0 +1,434 @@
+//! One-shot headless agent runs
+//!
+//! `ca run` runs the agent headless against one repo, then collects the
+//! transcript and a patch of everything it changed into a local directory.
+//! With `--ephemeral` the VM is created for the run and destroyed afterwards.
+//!
+//! With `--agents` several agents work on the same prompt side by side, each
+//! in its own git worktree and tmux window, and the results are compared.
+
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+
+use crate::utils::shell_quote;
+
+/// Directory on the VM holding the files of each run
+pub const RUNS_DIR: &str = "~/.cloud-agent/runs";
+
+/// Options for collecting the results of `ca run`
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Local directory for the transcript, patch and summary
+    pub output: Option<PathBuf>,
+
+    /// Push the run branch to origin
+    pub push: bool,
+}
+
+/// Random ID identifying a run (used in branch and VM names), 48 bits so
+/// runs started together don't collide
+pub fn run_id() -> String {
+    // Each RandomState gets fresh random keys
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_i64(chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    format!("{:012x}", hasher.finish() & 0xffff_ffff_ffff)
+}
+
+/// Branch the agent's work is committed to
+pub fn run_branch(id: &str) -> String {
+    format!("cloud-agent/run-{}", id)
+}
+
+/// Remote directory holding the files of run `id`
+pub fn remote_dir(id: &str) -> String {
+    format!("{}/{}", RUNS_DIR, id)
+}
+
+/// Shell command that runs `command` in `/workspace/<repo>` on a new branch.
+///
+/// The agent's output is teed to `transcript.log` and its exit code written
+/// to `exit_code`; the command itself succeeds whatever the agent does.
+pub fn agent_script(id: &str, repo: &str, command: &str) -> String {
+    let dir = remote_dir(id);
+    format!(
+        "mkdir -p {dir} && cd /workspace/{repo} && \
+         git rev-parse HEAD > {dir}/base && \
+         git checkout -q -b {branch} && \
+         {{ ({command}) < /dev/null 2>&1 | tee {dir}/transcript.log; \
+            echo \"${{PIPESTATUS[0]}}\" > {dir}/exit_code; }}",
+        dir = dir,
+        repo = shell_quote(repo),
+        branch = shell_quote(&run_branch(id)),
+        command = command,
+    )
+}
+
+/// Shell command that commits leftover changes, writes `changes.patch` and
+/// prints the exit code and number of commits on the run branch
+pub fn collect_script(id: &str, repo: &str, prompt: &str) -> String {
+    let dir = remote_dir(id);
+    format!(
+        "cd /workspace/{repo} && \
+         if [ -n \"$(git status --porcelain)\" ]; then \
+             git add -A && git commit -q -m {message}; \
+         fi && \
+         base=$(cat {dir}/base) && \
+         git format-patch --stdout \"$base..HEAD\" > {dir}/changes.patch && \
+         echo \"$(cat {dir}/exit_code 2>/dev/null || echo 1) $(git rev-list --count \"$base..HEAD\")\"",
+        repo = shell_quote(repo),
+        message = shell_quote(&format!("cloud-agent run: {}", subject(prompt))),
+        dir = dir,
+    )
+}
+
+/// Parse the `EXIT_CODE COMMITS` line printed by [`collect_script`]
+pub fn parse_collect_output(output: &str) -> Option<(i32, u32)> {
+    let mut fields = output.split_whitespace();
+    let exit_code = fields.next()?.parse().ok()?;
+    let commits = fields.next()?.parse().ok()?;
+    Some((exit_code, commits))
+}
+
+/// `run.json` written next to the transcript and patch
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub id: String,
+    pub vm_name: String,
+    pub agent: String,
+    pub repo: String,
+    pub prompt: String,
+    pub branch: String,
+    pub pushed: bool,
+    pub exit_code: i32,
+    pub commits: u32,
+}
+
+/// Worktree used by `agent` in a multi-agent run
+pub fn worktree_path(repo: &str, agent: &str) -> String {
+    format!("/workspace/{}.worktrees/{}", repo, agent)
+}
+
+/// Branch `agent` works on in a multi-agent run
+pub fn agent_branch(id: &str, agent: &str) -> String {
+    format!("{}-{}", run_branch(id), agent)
+}
+
+/// tmux session holding the agent windows of run `id`
+pub fn tmux_session(id: &str) -> String {
+    format!("run-{}", id)
+}
+
+/// Shell command that creates a worktree per agent and starts each
+/// `(agent, command)` in its own window of a new tmux session.
+///
+/// Each agent's output goes to `<agent>/transcript.log` and its exit code to
+/// `<agent>/exit_code`; the window then drops to a shell in the worktree.
+pub fn worktrees_script(id: &str, repo: &str, agents: &[(String, String)]) -> String {
+    let dir = remote_dir(id);
+    let session = tmux_session(id);
+    let repo_dir = format!("/workspace/{}", shell_quote(repo));
+
+    let mut commands = vec![
+        format!("mkdir -p {}", dir),
+        format!("cd {}", repo_dir),
+        format!("git rev-parse HEAD > {}/base", dir),
+        format!("tmux new-session -d -s {} -c {}", session, repo_dir),
+    ];
+
+    for (agent, command) in agents {
+        let agent_dir = format!("{}/{}", dir, agent);
+        let worktree = shell_quote(&worktree_path(repo, agent));
+        let window = format!(
+            "({}) < /dev/null 2>&1 | tee {dir}/transcript.log; \
+             echo \"${{PIPESTATUS[0]}}\" > {dir}/exit_code; exec bash",
+            command,
+            dir = agent_dir,
+        );
+
+        commands.extend([
+            format!("mkdir -p {}", agent_dir),
+            format!(
+                "{{ [ ! -e {wt} ] || git worktree remove --force {wt} || rm -rf {wt}; }}",
+                wt = worktree
+            ),
+            "git worktree prune".to_string(),
+            format!(
+                "git worktree add -q -b {} {} HEAD",
+                shell_quote(&agent_branch(id, agent)),
+                worktree
+            ),
+            format!(
+                "tmux new-window -t {} -n {} -c {} {}",
+                session,
+                agent,
+                worktree,
+                shell_quote(&format!("bash -c {}", shell_quote(&window)))
+            ),
+        ]);
+    }
+
+    commands.join(" && ")
+}
+
+/// How long `ca run --agents` waits for the agents to finish
+pub const COMPARE_TIMEOUT_SECS: u64 = 12 * 60 * 60;
+
+/// An agent of a multi-agent run that is no longer running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentDone {
+    /// The agent exited and recorded its exit code
+    Finished(String),
+    /// The agent's tmux window closed before it recorded an exit code
+    /// (killed, or the session was closed)
+    Gone(String),
+}
+
+/// Shell command reporting the `agents` of run `id` that are no longer
+/// running, one `<agent> done|gone` line each (see [`parse_finished`])
+pub fn finished_command(id: &str, agents: &[String]) -> String {
+    let names: Vec<String> = agents.iter().map(|a| shell_quote(a)).collect();
+    format!(
+        "cd {dir} && windows=$(tmux list-windows -t {session} -F '#W' 2>/dev/null); \
+         for a in {names}; do \
+         if [ -f \"$a/exit_code\" ]; then echo \"$a done\"; \
+         elif ! printf '%s\\n' \"$windows\" | grep -qxF \"$a\"; then echo \"$a gone\"; fi; \
+         done; true",
+        dir = remote_dir(id),
+        session = tmux_session(id),
+        names = names.join(" "),
+    )
+}
+
+/// Parse [`finished_command`] output
+pub fn parse_finished(output: &str) -> Vec<AgentDone> {
+    output
+        .lines()
+        .filter_map(|line| match line.trim().rsplit_once(' ')? {
+            (agent, "done") => Some(AgentDone::Finished(agent.to_string())),
+            (agent, "gone") => Some(AgentDone::Gone(agent.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Shell command that collects each agent's worktree: commits leftover
+/// changes, writes `<agent>/changes.patch`, runs `test_command` (output in
+/// `<agent>/tests.log`) and prints one tab-separated `AGENT` line per agent
+pub fn compare_script(
+    id: &str,
+    repo: &str,
+    agents: &[String],
+    prompt: &str,
+    test_command: Option<&str>,
+) -> String {
+    let dir = remote_dir(id);
+    let message = shell_quote(&format!("cloud-agent run: {}", subject(prompt)));
+
+    let scripts: Vec<String> = agents
+        .iter()
+        .map(|agent| {
+            let agent_dir = format!("{}/{}", dir, agent);
+            let tests = match test_command {
+                Some(test) => format!(
+                    "(bash -lc {}) < /dev/null > {}/tests.log 2>&1; tests=$?",
+                    shell_quote(test),
+                    agent_dir
+                ),
+                None => "tests=-".to_string(),
+            };
+
+            format!(
+                "(cd {worktree} && \
+                  if [ -n \"$(git status --porcelain)\" ]; then \
+                      git add -A && git commit -q -m {message}; \
+                  fi; \
+                  base=$(cat {dir}/base) && \
+                  git format-patch --stdout \"$base..HEAD\" > {agent_dir}/changes.patch && \
+                  stat=$(git diff --numstat \"$base\" HEAD | \
+                      awk '{{ f++; if ($1 != \"-\") a += $1; if ($2 != \"-\") d += $2 }} \
+                           END {{ printf \"%d\\t%d\\t%d\", f, a, d }}') && \
+                  {tests} && \
+                  printf 'AGENT\\t%s\\t%s\\t%s\\t%s\\t%s\\n' {agent} \
+                      \"$(cat {agent_dir}/exit_code 2>/dev/null || echo 1)\" \
+                      \"$(git rev-list --count \"$base..HEAD\")\" \"$stat\" \"$tests\")",
+                worktree = shell_quote(&worktree_path(repo, agent)),
+                message = message,
+                dir = dir,
+                agent_dir = agent_dir,
+                tests = tests,
+                agent = agent,
+            )
+        })
+        .collect();
+
+    scripts.join("; ")
+}
+
+/// Outcome of one agent in a multi-agent run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AgentResult {
+    pub agent: String,
+    pub exit_code: i32,
+    pub commits: u32,
+    pub files_changed: u32,
+    pub insertions: u32,
+    pub deletions: u32,
+
+    /// Exit code of the test command, if one is configured
+    pub tests: Option<i32>,
+}
+
+/// Parse the `AGENT` lines printed by [`compare_script`]
+pub fn parse_compare_output(output: &str) -> Vec<AgentResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let ["AGENT", agent, exit_code, commits, files, insertions, deletions, tests] =
+                fields[..]
+            else {
+                return None;
+            };
+
+            Some(AgentResult {
+                agent: agent.to_string(),
+                exit_code: exit_code.parse().ok()?,
+                commits: commits.parse().ok()?,
+                files_changed: files.parse().ok()?,
+                insertions: insertions.parse().ok()?,
+                deletions: deletions.parse().ok()?,
+                tests: tests.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// Markdown comparison of the agents in a multi-agent run
+pub fn comparison_report(id: &str, repo: &str, prompt: &str, results: &[AgentResult]) -> String {
+    let mut report = format!(
+        "# Agent comparison (run {})\n\n\
+         Repository: `{}`\n\n\
+         Prompt: {}\n\n\
+         | Agent | Exit | Commits | Files | Lines | Tests |\n\
+         |-------|------|---------|-------|-------|-------|\n",
+        id, repo, prompt
+    );
+
+    for r in results {
+        let tests = match r.tests {
+            Some(0) => "passed".to_string(),
+            Some(code) => format!("failed ({})", code),
+            None => "-".to_string(),
+        };
+        report.push_str(&format!(
+            "| {} | {} | {} | {} | +{} -{} | {} |\n",
+            r.agent, r.exit_code, r.commits, r.files_changed, r.insertions, r.deletions, tests
+        ));
+    }
+
+    report
+}
+
+/// `run.json` written for a multi-agent run
+#[derive(Debug, Serialize)]
+pub struct ComparisonSummary {
+    pub id: String,
+    pub vm_name: String,
+    pub repo: String,
+    pub prompt: String,
+    pub test_command: Option<String>,
+    pub results: Vec<AgentResult>,
+}
+
+/// First line of `prompt`, shortened for use as a commit subject
+fn subject(prompt: &str) -> String {
+    prompt
+        .lines()
+        .next()
+        .unwrap_or("")
+        .chars()
+        .take(72)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_id() {
+        let id = run_id();
+        assert_eq!(id.len(), 12);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, run_id());
+    }
+
+    #[test]
+    fn test_agent_script() {
+        let script = agent_script("abc123", "api", "claude -p 'fix it'");
+        assert!(script.contains(
+            "cd /workspace/'api' && git rev-parse HEAD > ~/.cloud-agent/runs/abc123/base"
+        ));
+        assert!(script.contains("git checkout -q -b 'cloud-agent/run-abc123'"));
+        assert!(script.contains(
+            "(claude -p 'fix it') < /dev/null 2>&1 | tee ~/.cloud-agent/runs/abc123/transcript.log"
+        ));
+        assert!(script.contains("${PIPESTATUS[0]}"));
+    }
+
+    #[test]
+    fn test_collect_script() {
+        let script = collect_script("abc123", "api", "Fix the lint\nand more");
+        assert!(script.contains("git commit -q -m 'cloud-agent run: Fix the lint'"));
+        assert!(script.contains("> ~/.cloud-agent/runs/abc123/changes.patch"));
+
+        assert_eq!(parse_collect_output("0 2\n"), Some((0, 2)));
+        assert_eq!(parse_collect_output("garbage"), None);
+    }
+
+    #[test]
+    fn test_worktrees_script() {
+        let agents = vec![
+            ("claude".to_string(), "claude -p 'go'".to_string()),
+            ("codex".to_string(), "codex exec 'go'".to_string()),
+        ];
+        let script = worktrees_script("abc123", "api", &agents);
+        assert!(script.contains("tmux new-session -d -s run-abc123 -c /workspace/'api'"));
+        assert!(script.contains(
+            "git worktree add -q -b 'cloud-agent/run-abc123-codex' '/workspace/api.worktrees/codex' HEAD"
+        ));
+        assert!(script.contains("tmux new-window -t run-abc123 -n claude"));
+    }
+
+    #[test]
+    fn test_finished() {
+        let command = finished_command("abc123", &["claude".to_string(), "codex".to_string()]);
+        assert!(command.contains("tmux list-windows -t run-abc123"));
+        assert!(command.contains("for a in 'claude' 'codex'; do"));
+
+        assert_eq!(
+            parse_finished("claude done\ncodex gone\ngarbage\n"),
+            vec![
+                AgentDone::Finished("claude".to_string()),
+                AgentDone::Gone("codex".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compare_results() {
+        let output = "AGENT\tclaude\t0\t2\t3\t10\t4\t0\n\
+                      AGENT\tcodex\t1\t0\t0\t0\t0\t-\n";
+        let results = parse_compare_output(output);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].insertions, 10);
+        assert_eq!(results[0].tests, Some(0));
+        assert_eq!(results[1].tests, None);
+
+        let report = comparison_report("abc123", "api", "Fix lint", &results);
+        assert!(report.contains("| claude | 0 | 2 | 3 | +10 -4 | passed |"));
+        assert!(report.contains("| codex | 1 | 0 | 0 | +0 -0 | - |"));
+    }
+}
@@ -0,0 +1,248 @@
+#!/bin/bash
+# Managed by cloud-agent - changes will be overwritten on deploy
+#
+# Restricted egress for the cloud-agent VM. Outbound connections from
+# non-root users are rejected unless they go through a local squid proxy,
+# which only forwards to domains listed in $ALLOWLIST.
+#
+# The VM's startup script enables it on every boot. Enabling revokes sudo
+# from the SSH user (so an agent can't become root and bypass the proxy).
+# From then on the allowlist and mode are read from instance metadata, which
+# only `ca` (through the GCP API) can change.
+#
+#   cloud-agent-egress restricted USER   enable the proxy, firewall and sync (as root)
+#   sudo cloud-agent-egress restricted   the same for the calling user
+#   cloud-agent-egress run               apply the firewall and follow metadata (systemd)
+#   cloud-agent-egress denied            requests refused by the proxy, oldest first
+
+set -eu
+
+CONF_DIR=/etc/cloud-agent
+ALLOWLIST="$CONF_DIR/egress-allowlist"
+USER_FILE="$CONF_DIR/egress-user"
+PROXY="http://127.0.0.1:3128"
+NO_PROXY="localhost,127.0.0.1,::1,169.254.169.254,metadata.google.internal"
+CHAIN=CLOUD_AGENT_EGRESS
+ACCESS_LOG=/var/log/squid/access.log
+SERVICE=/etc/systemd/system/cloud-agent-egress.service
+
+METADATA_URL="http://metadata.google.internal/computeMetadata/v1/instance/attributes"
+MODE_KEY=cloud-agent-egress
+ALLOWLIST_KEY=cloud-agent-egress-allowlist
+
+PROFILE_FILE=/etc/profile.d/cloud-agent-proxy.sh
+ENV_FILE="$CONF_DIR/proxy.env"
+SSHD_FILE=/etc/ssh/sshd_config.d/cloud-agent-proxy.conf
+SSH_FILE=/etc/ssh/ssh_config.d/cloud-agent-proxy.conf
+APT_FILE=/etc/apt/apt.conf.d/80cloud-agent-proxy
+SUDOERS_FILE=/etc/sudoers.d/zz-cloud-agent-egress
+
+install_packages() {
+    command -v squid > /dev/null && command -v iptables > /dev/null && command -v nc > /dev/null && return
+    # The startup script may still be holding the dpkg lock
+    apt-get -o DPkg::Lock::Timeout=600 update -qq
+    DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=600 install -y -qq \
+        squid iptables netcat-openbsd > /dev/null
+}
+
+metadata() {
+    curl -sf -H "Metadata-Flavor: Google" "$METADATA_URL/$1" || true
+}
+
+write_squid_config() {
+    [ -f /etc/squid/squid.conf.orig ] || cp /etc/squid/squid.conf /etc/squid/squid.conf.orig
+    cat > /etc/squid/squid.conf << EOF
+# Managed by cloud-agent
+http_port 127.0.0.1:3128
+acl allowed_domains dstdomain "$ALLOWLIST"
+acl safe_ports port 80 443 22
+acl connect_ports port 443 22
+acl CONNECT method CONNECT
+http_access deny !safe_ports
+http_access deny CONNECT !connect_ports
+http_access allow localhost allowed_domains
+http_access deny all
+access_log daemon:$ACCESS_LOG squid
+cache deny all
+coredump_dir /var/spool/squid
+EOF
+}
+
+# Proxy settings for login shells, SSH sessions, systemd services (the
+# queue runner loads $ENV_FILE), apt (which downloads as _apt) and outbound
+# SSH (git over SSH)
+write_proxy_settings() {
+    local var
+    : > "$PROFILE_FILE"
+    : > "$ENV_FILE"
+    for var in http_proxy https_proxy HTTP_PROXY HTTPS_PROXY; do
+        echo "export $var=$PROXY" >> "$PROFILE_FILE"
+        echo "$var=$PROXY" >> "$ENV_FILE"
+    done
+    echo "export no_proxy=$NO_PROXY NO_PROXY=$NO_PROXY" >> "$PROFILE_FILE"
+    printf 'no_proxy=%s\nNO_PROXY=%s\n' "$NO_PROXY" "$NO_PROXY" >> "$ENV_FILE"
+
+    cat > "$SSHD_FILE" << EOF
+# Managed by cloud-agent
+SetEnv http_proxy=$PROXY https_proxy=$PROXY HTTP_PROXY=$PROXY HTTPS_PROXY=$PROXY no_proxy=$NO_PROXY NO_PROXY=$NO_PROXY
+EOF
+
+    cat > "$SSH_FILE" << EOF
+# Managed by cloud-agent
+Host * !localhost !127.0.0.1
+    ProxyCommand nc -X connect -x 127.0.0.1:3128 %h %p
+EOF
+
+    cat > "$APT_FILE" << EOF
+Acquire::http::Proxy "$PROXY";
+Acquire::https::Proxy "$PROXY";
+EOF
+}
+
+# Reject outbound traffic except loopback, replies, the metadata server
+# (DNS and credentials), root and the proxy itself
+enable_firewall() {
+    local ipt
+    for ipt in iptables ip6tables; do
+        $ipt -N "$CHAIN" 2> /dev/null || $ipt -F "$CHAIN"
+        $ipt -C OUTPUT -j "$CHAIN" 2> /dev/null || $ipt -I OUTPUT -j "$CHAIN"
+        $ipt -A "$CHAIN" -o lo -j RETURN
+        $ipt -A "$CHAIN" -m conntrack --ctstate ESTABLISHED,RELATED -j RETURN
+        $ipt -A "$CHAIN" -m owner --uid-owner 0 -j RETURN
+        $ipt -A "$CHAIN" -m owner --uid-owner proxy -j RETURN
+    done
+    iptables -A "$CHAIN" -d 169.254.169.254 -j RETURN
+    iptables -A "$CHAIN" -j REJECT
+    ip6tables -A "$CHAIN" -j REJECT
+}
+
+disable_firewall() {
+    local ipt
+    for ipt in iptables ip6tables; do
+        command -v $ipt > /dev/null || continue
+        while $ipt -D OUTPUT -j "$CHAIN" 2> /dev/null; do :; done
+        $ipt -F "$CHAIN" 2> /dev/null || true
+        $ipt -X "$CHAIN" 2> /dev/null || true
+    done
+}
+
+# Deny the user every sudo command (the last matching sudoers rule wins,
+# so this overrides the google-sudoers group)
+revoke_sudo() {
+    local user=$1
+    echo "$user" > "$USER_FILE"
+    echo "$user ALL=(ALL:ALL) !ALL" > "$SUDOERS_FILE.new"
+    chmod 440 "$SUDOERS_FILE.new"
+    visudo -cqf "$SUDOERS_FILE.new"
+    mv "$SUDOERS_FILE.new" "$SUDOERS_FILE"
+}
+
+restore_sudo() {
+    rm -f "$SUDOERS_FILE"
+    if [ -f "$USER_FILE" ]; then
+        gpasswd -d "$(cat "$USER_FILE")" proxy > /dev/null 2>&1 || true
+        rm -f "$USER_FILE"
+    fi
+}
+
+# Update the allowlist from instance metadata (domains separated by spaces)
+apply_allowlist() {
+    local domains
+    domains=$(metadata "$ALLOWLIST_KEY")
+    [ -n "$domains" ] || return 0
+    # shellcheck disable=SC2086 # one domain per line
+    printf '%s\n' $domains > "$ALLOWLIST.new"
+    if cmp -s "$ALLOWLIST.new" "$ALLOWLIST"; then
+        rm "$ALLOWLIST.new"
+    else
+        mv "$ALLOWLIST.new" "$ALLOWLIST"
+        systemctl reload squid 2> /dev/null || true
+    fi
+}
+
+enable_restricted() {
+    local user=${1:-${SUDO_USER:-}}
+    if [ -z "$user" ] || [ "$user" = root ]; then
+        echo "run as root with the VM's SSH user, or with sudo as that user" >&2
+        exit 2
+    fi
+
+    # Firewall and sudo first, so a failure below leaves egress closed
+    mkdir -p "$CONF_DIR"
+    enable_firewall
+    revoke_sudo "$user"
+
+    touch "$ALLOWLIST"
+    apply_allowlist
+    install_packages
+    write_squid_config
+    systemctl enable --quiet squid
+    systemctl restart squid
+    write_proxy_settings
+    systemctl reload ssh 2> /dev/null || systemctl reload sshd
+
+    cat > "$SERVICE" << EOF
+[Unit]
+Description=cloud-agent restricted egress
+After=network-online.target squid.service
+
+[Service]
+ExecStart=/usr/local/sbin/cloud-agent-egress run
+Restart=on-failure
+RestartSec=10
+
+[Install]
+WantedBy=multi-user.target
+EOF
+    systemctl daemon-reload
+    systemctl enable --quiet cloud-agent-egress
+    systemctl restart cloud-agent-egress
+    # Let the user read the proxy log (`ca status`)
+    usermod -aG proxy "$user"
+}
+
+# Undo everything; only reachable through instance metadata once enabled
+disable_restricted() {
+    disable_firewall
+    rm -f "$PROFILE_FILE" "$ENV_FILE" "$SSHD_FILE" "$SSH_FILE" "$APT_FILE"
+    systemctl reload ssh 2> /dev/null || systemctl reload sshd 2> /dev/null || true
+    systemctl disable --now --quiet squid 2> /dev/null || true
+    restore_sudo
+    systemctl disable --quiet cloud-agent-egress 2> /dev/null || true
+    rm -f "$SERVICE"
+    systemctl daemon-reload
+}
+
+# Apply the firewall, then follow the metadata set by `ca deploy`
+run() {
+    local state last=""
+    enable_firewall
+    while true; do
+        state="$(metadata "$MODE_KEY") $(metadata "$ALLOWLIST_KEY")"
+        if [ "$state" != "$last" ]; then
+            if [ "$(metadata "$MODE_KEY")" = open ]; then
+                disable_restricted
+                exit 0
+            fi
+            apply_allowlist
+            last=$state
+        fi
+        # Returns as soon as any attribute changes (or after the timeout)
+        curl -sf -o /dev/null -H "Metadata-Flavor: Google" \
+            "$METADATA_URL/?recursive=true&wait_for_change=true&timeout_sec=300" || sleep 10
+    done
+}
+
+case "${1:-}" in
+    restricted) enable_restricted "${2:-}" ;;
+    run) run ;;
+    denied)
+        if [ -r "$ACCESS_LOG" ]; then
+            awk '$4 ~ /^TCP_DENIED/ {print $1, $7}' "$ACCESS_LOG" | tail -n 1000
+        fi
+        ;;
+    *)
+        echo "usage: cloud-agent-egress restricted|run|denied" >&2
+        exit 2
+        ;;
+esac
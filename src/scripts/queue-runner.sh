@@ -0,0 +1,155 @@
+#!/bin/bash
+# Managed by cloud-agent - changes will be overwritten on deploy
+#
+# Task queue for the cloud-agent VM. Tasks are JSON lines in $QUEUE_FILE;
+# every change to the file happens under an flock so `ca queue` and the
+# runner service can't clobber each other.
+#
+#   cloud-agent-queue run          process queued tasks one at a time (systemd)
+#   cloud-agent-queue add JSON     append a task
+#   cloud-agent-queue cancel ID    cancel a queued or running task
+
+set -u
+
+QUEUE_DIR="$HOME/.cloud-agent/queue"
+QUEUE_FILE="$QUEUE_DIR/tasks.jsonl"
+LOCK_FILE="$QUEUE_DIR/tasks.lock"
+LOG_DIR="$QUEUE_DIR/logs"
+POLL_SECONDS=5
+
+mkdir -p "$LOG_DIR"
+touch "$QUEUE_FILE"
+
+now() {
+    date -u +%Y-%m-%dT%H:%M:%SZ
+}
+
+# Rewrite the task with id $1 using the jq filter $2 (extra jq args follow)
+update_task() {
+    local id=$1 filter=$2
+    shift 2
+    jq -c --arg id "$id" "$@" "if .id == \$id then $filter else . end" \
+        "$QUEUE_FILE" > "$QUEUE_FILE.tmp" && mv "$QUEUE_FILE.tmp" "$QUEUE_FILE"
+}
+
+# Claim the oldest queued task and print it, or print nothing
+claim_next() {
+    (
+        flock 9
+        task=$(jq -c 'select(.status == "queued")' "$QUEUE_FILE" | head -n 1)
+        [ -n "$task" ] || exit 0
+        id=$(jq -r .id <<<"$task")
+        update_task "$id" '.status = "running" | .started_at = $now | .log = $log' \
+            --arg now "$(now)" --arg log "$LOG_DIR/$id.log"
+        jq -c 'select(.id == $id)' --arg id "$id" "$QUEUE_FILE"
+    ) 9>"$LOCK_FILE"
+}
+
+run_task() {
+    local task=$1 id repo command log pid rc
+    id=$(jq -r .id <<<"$task")
+    repo=$(jq -r .repo <<<"$task")
+    command=$(jq -r .command <<<"$task")
+    log=$(jq -r .log <<<"$task")
+
+    {
+        echo "# task $id started $(now)"
+        echo "# repo: $repo"
+        echo "# command: $command"
+    } > "$log"
+
+    if [ -d "/workspace/$repo" ]; then
+        # Own process group so cancel can stop the agent and its children
+        (cd "/workspace/$repo" && exec setsid bash -lc "$command") < /dev/null >> "$log" 2>&1 &
+        pid=$!
+        (
+            flock 9
+            update_task "$id" '.pid = $pid' --argjson pid "$pid"
+        ) 9>"$LOCK_FILE"
+        wait "$pid"
+        rc=$?
+    else
+        echo "Repository /workspace/$repo not found" >> "$log"
+        rc=1
+    fi
+
+    echo "# task $id finished $(now) with exit code $rc" >> "$log"
+    (
+        flock 9
+        update_task "$id" \
+            'del(.pid) | .exit_code = $rc | .finished_at = $now
+             | .status = (if .status == "cancelled" then "cancelled"
+                          elif $rc == 0 then "done" else "failed" end)' \
+            --argjson rc "$rc" --arg now "$(now)"
+    ) 9>"$LOCK_FILE"
+}
+
+cmd_run() {
+    # Tasks left running by a previous runner (VM restart) can't be resumed
+    (
+        flock 9
+        jq -c --arg now "$(now)" \
+            'if .status == "running" then .status = "failed" | .finished_at = $now | del(.pid) else . end' \
+            "$QUEUE_FILE" > "$QUEUE_FILE.tmp" && mv "$QUEUE_FILE.tmp" "$QUEUE_FILE"
+    ) 9>"$LOCK_FILE"
+
+    while true; do
+        task=$(claim_next)
+        if [ -n "$task" ]; then
+            run_task "$task"
+        else
+            sleep "$POLL_SECONDS"
+        fi
+    done
+}
+
+cmd_add() {
+    local task=$1
+    jq -e 'has("id") and has("repo") and has("command")' <<<"$task" > /dev/null || {
+        echo "invalid task: $task" >&2
+        exit 1
+    }
+    (
+        flock 9
+        jq -c . <<<"$task" >> "$QUEUE_FILE"
+    ) 9>"$LOCK_FILE"
+}
+
+cmd_cancel() {
+    local id=$1 task status pid
+    (
+        flock 9
+        task=$(jq -c 'select(.id == $id)' --arg id "$id" "$QUEUE_FILE")
+        if [ -z "$task" ]; then
+            echo "no task with id $id" >&2
+            exit 1
+        fi
+
+        status=$(jq -r .status <<<"$task")
+        case "$status" in
+            queued)
+                update_task "$id" '.status = "cancelled" | .finished_at = $now' --arg now "$(now)"
+                ;;
+            running)
+                pid=$(jq -r '.pid // empty' <<<"$task")
+                update_task "$id" '.status = "cancelled"'
+                [ -z "$pid" ] || kill -TERM -- "-$pid" 2>/dev/null || kill -TERM "$pid" 2>/dev/null || true
+                ;;
+            *)
+                echo "task $id already $status" >&2
+                exit 1
+                ;;
+        esac
+        echo "cancelled $id ($status)"
+    ) 9>"$LOCK_FILE"
+}
+
+case "${1:-}" in
+    run) cmd_run ;;
+    add) cmd_add "${2:?task JSON required}" ;;
+    cancel) cmd_cancel "${2:?task id required}" ;;
+    *)
+        echo "usage: $0 run | add JSON | cancel ID" >&2
+        exit 2
+        ;;
+esac
@@ -0,0 +1,382 @@
+//! Agent settings bundles
+//!
+//! `--with-settings` copies each agent's local customisation (settings,
+//! memory files, custom commands, MCP servers) to the VM. Paths under the
+//! local home directory are rewritten to the VM home, and MCP servers whose
+//! command won't exist on the VM are dropped with a warning.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::utils::shell_quote;
+
+/// How a settings file is processed before it is copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsFormat {
+    /// JSON with optional `mcpServers` objects
+    Json,
+    /// TOML with an optional `[mcp_servers]` table
+    Toml,
+    /// Plain text (memory files, prompts)
+    Text,
+    /// Directory of text files (custom commands)
+    Dir,
+}
+
+/// A file or directory in an agent's settings bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SettingsItem {
+    /// Path relative to the home directory
+    pub path: &'static str,
+    pub format: SettingsFormat,
+}
+
+impl SettingsItem {
+    pub const fn new(path: &'static str, format: SettingsFormat) -> Self {
+        Self { path, format }
+    }
+}
+
+/// Commands stdio MCP servers may use on the VM
+const VM_COMMANDS: &[&str] = &[
+    "npx", "node", "npm", "python3", "python", "pipx", "bash", "sh",
+];
+
+/// Directories whose binaries are assumed to match the VM's
+const SYSTEM_DIRS: &[&str] = &["/usr/bin", "/bin", "/usr/local/bin"];
+
+/// Whether an MCP server command can run on the VM
+fn vm_has_command(command: &str) -> bool {
+    let name = match command.rsplit_once('/') {
+        Some((dir, name)) if SYSTEM_DIRS.contains(&dir) => name,
+        Some(_) => return false,
+        None => command,
+    };
+    VM_COMMANDS.contains(&name)
+}
+
+/// Replace the local home directory with the VM's in `content`. Only whole
+/// paths are replaced: `/Users/me` is followed by `/`, a quote or the end,
+/// so `/Users/meg` is left alone.
+pub fn rewrite_paths(content: &str, local_home: &str, remote_home: &str) -> String {
+    let local_home = local_home.trim_end_matches('/');
+    if local_home.is_empty() || local_home == remote_home {
+        return content.to_string();
+    }
+
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(index) = rest.find(local_home) {
+        let after = &rest[index + local_home.len()..];
+        output.push_str(&rest[..index]);
+        match after.chars().next() {
+            None | Some('/' | '"' | '\'') => output.push_str(remote_home),
+            Some(_) => output.push_str(local_home),
+        }
+        rest = after;
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Drop MCP servers the VM can't run from every `mcpServers` object in
+/// `value`, returning `name (command)` for each one removed
+pub fn filter_json_mcp_servers(value: &mut serde_json::Value) -> Vec<String> {
+    let mut skipped = Vec::new();
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if key == "mcpServers" {
+                    if let Some(servers) = child.as_object_mut() {
+                        servers.retain(|name, server| {
+                            match server.get("command").and_then(|c| c.as_str()) {
+                                Some(command) if !vm_has_command(command) => {
+                                    skipped.push(format!("{} ({})", name, command));
+                                    false
+                                }
+                                _ => true,
+                            }
+                        });
+                    }
+                } else {
+                    skipped.extend(filter_json_mcp_servers(child));
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                skipped.extend(filter_json_mcp_servers(item));
+            }
+        }
+        _ => {}
+    }
+    skipped
+}
+
+/// Drop MCP servers the VM can't run from the `[mcp_servers]` table
+pub fn filter_toml_mcp_servers(table: &mut toml::Table) -> Vec<String> {
+    let mut skipped = Vec::new();
+    if let Some(servers) = table.get_mut("mcp_servers").and_then(|s| s.as_table_mut()) {
+        servers.retain(
+            |name, server| match server.get("command").and_then(|c| c.as_str()) {
+                Some(command) if !vm_has_command(command) => {
+                    skipped.push(format!("{} ({})", name, command));
+                    false
+                }
+                _ => true,
+            },
+        );
+    }
+    skipped
+}
+
+/// Prepare a settings file for the VM, returning the new content and the
+/// MCP servers that were skipped. Files that aren't UTF-8 are left alone.
+pub fn prepare(
+    format: SettingsFormat,
+    content: &[u8],
+    local_home: &str,
+    remote_home: &str,
+) -> Result<(Vec<u8>, Vec<String>)> {
+    let Ok(text) = std::str::from_utf8(content) else {
+        return Ok((content.to_vec(), Vec::new()));
+    };
+    let text = rewrite_paths(text, local_home, remote_home);
+
+    match format {
+        SettingsFormat::Json => {
+            let mut value: serde_json::Value = serde_json::from_str(&text)?;
+            let skipped = filter_json_mcp_servers(&mut value);
+            if skipped.is_empty() {
+                Ok((text.into_bytes(), skipped))
+            } else {
+                Ok((serde_json::to_vec_pretty(&value)?, skipped))
+            }
+        }
+        SettingsFormat::Toml => {
+            let mut table: toml::Table = toml::from_str(&text)?;
+            let skipped = filter_toml_mcp_servers(&mut table);
+            if skipped.is_empty() {
+                Ok((text.into_bytes(), skipped))
+            } else {
+                Ok((toml::to_string(&table)?.into_bytes(), skipped))
+            }
+        }
+        SettingsFormat::Text | SettingsFormat::Dir => Ok((text.into_bytes(), Vec::new())),
+    }
+}
+
+/// Result of staging a settings bundle
+#[derive(Debug, Default)]
+pub struct StagedBundle {
+    /// Files written, relative to the home directory
+    pub files: Vec<String>,
+
+    /// Warnings for skipped MCP servers and unreadable files
+    pub warnings: Vec<String>,
+}
+
+/// Copy the bundle items found under `local_home` into `staging`, keeping
+/// their home-relative layout
+pub fn stage(
+    items: &[SettingsItem],
+    local_home: &Path,
+    remote_home: &str,
+    staging: &Path,
+) -> Result<StagedBundle> {
+    let home = local_home.to_string_lossy();
+    let mut bundle = StagedBundle::default();
+
+    for item in items {
+        let source = local_home.join(item.path);
+        let files: Vec<_> = match item.format {
+            SettingsFormat::Dir if source.is_dir() => walkdir::WalkDir::new(&source)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path())
+                .collect(),
+            SettingsFormat::Dir => continue,
+            _ if source.is_file() => vec![source],
+            _ => continue,
+        };
+
+        for file in files {
+            let relative = file.strip_prefix(local_home)?.to_path_buf();
+            let content = fs::read(&file).with_context(|| format!("reading {}", file.display()))?;
+
+            let (content, skipped) = match prepare(item.format, &content, &home, remote_home) {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    bundle
+                        .warnings
+                        .push(format!("Skipping ~/{}: {}", relative.display(), e));
+                    continue;
+                }
+            };
+            for server in skipped {
+                bundle.warnings.push(format!(
+                    "Skipping MCP server {} from ~/{}: command not available on the VM",
+                    server,
+                    relative.display()
+                ));
+            }
+
+            let target = staging.join(&relative);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, content)?;
+            // Settings can hold tokens (MCP server env, API keys)
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&target, fs::Permissions::from_mode(0o600))?;
+            }
+            bundle.files.push(relative.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(bundle)
+}
+
+/// Shell command that unpacks a staged bundle `archive` into the home
+/// directory on the VM. Extracting replaces existing files, so `files` are
+/// made private again afterwards.
+pub fn extract_command(archive: &str, files: &[String]) -> String {
+    let files: Vec<String> = files.iter().map(|f| shell_quote(f)).collect();
+    format!(
+        "cd ~ && tar xzf {archive} && chmod 600 {} && rm {archive}",
+        files.join(" "),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vm_has_command() {
+        assert!(vm_has_command("npx"));
+        assert!(vm_has_command("/usr/bin/python3"));
+        assert!(!vm_has_command("/opt/homebrew/bin/npx"));
+        assert!(!vm_has_command("/Users/me/bin/my-mcp"));
+        assert!(!vm_has_command("uvx"));
+    }
+
+    #[test]
+    fn test_rewrite_paths() {
+        let content = "\"/Users/me\" '/Users/me' /Users/me/src /Users/meg/src /Users/me";
+        assert_eq!(
+            rewrite_paths(content, "/Users/me/", "/home/me"),
+            "\"/home/me\" '/home/me' /home/me/src /Users/meg/src /home/me"
+        );
+    }
+
+    #[test]
+    fn test_prepare_json() {
+        let content = r#"{
+  "mcpServers": {
+    "github": {"command": "npx", "args": ["-y", "@modelcontextprotocol/server-github"]},
+    "local": {"command": "/Users/me/bin/my-mcp"},
+    "remote": {"type": "http", "url": "https://mcp.example.com"}
+  },
+  "projects": {"/Users/me/src/api": {"mcpServers": {"db": {"command": "pg-mcp"}}}}
+}"#;
+        let (output, skipped) = prepare(
+            SettingsFormat::Json,
+            content.as_bytes(),
+            "/Users/me",
+            "/home/me",
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped.contains(&"local (/home/me/bin/my-mcp)".to_string()));
+        assert!(skipped.contains(&"db (pg-mcp)".to_string()));
+        assert!(output.contains("\"github\""));
+        assert!(output.contains("\"remote\""));
+        assert!(output.contains("/home/me/src/api"));
+        assert!(!output.contains("/Users/me"));
+    }
+
+    #[test]
+    fn test_prepare_toml() {
+        let content = "model = \"o3\"\n\n[profiles.fast]\nmodel = \"gpt-4.1\"\n\n\
+                       [mcp_servers.docs]\ncommand = \"npx\"\nargs = [\"docs-mcp\"]\n\n\
+                       [mcp_servers.local]\ncommand = \"/opt/tools/mcp\"\n";
+        let (output, skipped) = prepare(
+            SettingsFormat::Toml,
+            content.as_bytes(),
+            "/Users/me",
+            "/home/me",
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(skipped, vec!["local (/opt/tools/mcp)"]);
+        assert!(output.contains("[profiles.fast]"));
+        assert!(output.contains("[mcp_servers.docs]"));
+        assert!(!output.contains("mcp_servers.local"));
+
+        // Untouched files keep their formatting
+        let content = "# my profile\nmodel = \"o3\"\n";
+        let (output, _) = prepare(
+            SettingsFormat::Toml,
+            content.as_bytes(),
+            "/Users/me",
+            "/home/me",
+        )
+        .unwrap();
+        assert_eq!(output, content.as_bytes());
+    }
+
+    #[test]
+    fn test_stage() {
+        let home = tempfile::tempdir().unwrap();
+        let staging = tempfile::tempdir().unwrap();
+        let local = home.path().to_string_lossy().to_string();
+
+        fs::create_dir_all(home.path().join(".claude/commands/review")).unwrap();
+        fs::write(
+            home.path().join(".claude/commands/review/pr.md"),
+            format!("Read {}/notes.md first", local),
+        )
+        .unwrap();
+        fs::write(home.path().join(".claude/settings.json"), "not json").unwrap();
+
+        let items = [
+            SettingsItem::new(".claude/settings.json", SettingsFormat::Json),
+            SettingsItem::new(".claude/CLAUDE.md", SettingsFormat::Text),
+            SettingsItem::new(".claude/commands", SettingsFormat::Dir),
+        ];
+        let bundle = stage(&items, home.path(), "/home/me", staging.path()).unwrap();
+
+        assert_eq!(bundle.files, vec![".claude/commands/review/pr.md"]);
+        assert_eq!(bundle.warnings.len(), 1);
+        assert_eq!(
+            fs::read_to_string(staging.path().join(".claude/commands/review/pr.md")).unwrap(),
+            "Read /home/me/notes.md first"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(staging.path().join(".claude/commands/review/pr.md"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_extract_command() {
+        let files = vec![".claude.json".to_string(), ".codex/config.toml".to_string()];
+        assert_eq!(
+            extract_command("cloud-agent-settings.tgz", &files),
+            "cd ~ && tar xzf cloud-agent-settings.tgz && chmod 600 '.claude.json' '.codex/config.toml' && rm cloud-agent-settings.tgz"
+        );
+    }
+}
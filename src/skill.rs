@@ -0,0 +1,139 @@
+//! Agent skill installation
+//!
+//! The cloud-agent skill (`skills/cloud-agent/SKILL.md`) is embedded in the
+//! binary. `ca skill install` writes it to each agent's skill or command
+//! directory with a version marker, and every `ca` invocation refreshes
+//! installed copies whose marker names a different version. Copies without
+//! a marker (installed by hand or edited) are left alone.
+
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+/// Skill content as shipped in the repository
+const SKILL: &str = include_str!("../skills/cloud-agent/SKILL.md");
+
+/// Name of the skill (and of its directory for agents with skill folders)
+pub const SKILL_NAME: &str = "cloud-agent";
+
+/// Prefix of the version marker written after the frontmatter
+const VERSION_MARKER: &str = "<!-- cloud-agent skill version: ";
+
+/// Outcome of installing the skill to one location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkillStatus {
+    Installed,
+    UpToDate,
+    Updated { from: String },
+}
+
+/// Skill content for this version of `ca`, with the version marker after
+/// the frontmatter (agents require the frontmatter to come first)
+pub fn render() -> String {
+    let marker = format!("{}{} -->\n", VERSION_MARKER, env!("CARGO_PKG_VERSION"));
+    match SKILL
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.find("\n---\n").map(|end| end + 4 + 5))
+    {
+        Some(split) => format!("{}{}{}", &SKILL[..split], marker, &SKILL[split..]),
+        None => format!("{}{}", marker, SKILL),
+    }
+}
+
+/// Version recorded in an installed skill, or "unknown" for copies
+/// installed without a marker
+pub fn installed_version(content: &str) -> &str {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(VERSION_MARKER))
+        .and_then(|rest| rest.strip_suffix(" -->"))
+        .unwrap_or("unknown")
+}
+
+/// Write the skill to `path`, reporting whether anything changed
+pub fn install(path: &Path) -> Result<SkillStatus> {
+    let content = render();
+    let status = match fs::read_to_string(path) {
+        Ok(existing) if existing == content => return Ok(SkillStatus::UpToDate),
+        Ok(existing) => SkillStatus::Updated {
+            from: installed_version(&existing).to_string(),
+        },
+        Err(_) => SkillStatus::Installed,
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(status)
+}
+
+/// Remove the skill at `path` (and its skill directory once empty),
+/// returning whether it was installed
+pub fn uninstall(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(path)?;
+
+    if let Some(dir) = path.parent().filter(|d| d.ends_with(SKILL_NAME)) {
+        // Only removes the directory when nothing else is in it
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(true)
+}
+
+/// Update the skill at `path` if it was installed by another version of
+/// `ca`, returning the version it was updated from
+pub fn refresh(path: &Path) -> Result<Option<String>> {
+    let Ok(existing) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+    let version = installed_version(&existing);
+    if version == "unknown" || version == env!("CARGO_PKG_VERSION") {
+        return Ok(None);
+    }
+    let from = version.to_string();
+    install(path)?;
+    Ok(Some(from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_keeps_frontmatter_first() {
+        let content = render();
+        assert!(content.starts_with("---\nname: cloud-agent\n"));
+        assert_eq!(installed_version(&content), env!("CARGO_PKG_VERSION"));
+        assert_eq!(installed_version(SKILL), "unknown");
+    }
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let home = tempfile::tempdir().unwrap();
+        let path = home.path().join(".claude/skills/cloud-agent/SKILL.md");
+
+        assert_eq!(refresh(&path).unwrap(), None);
+        assert_eq!(install(&path).unwrap(), SkillStatus::Installed);
+        assert_eq!(install(&path).unwrap(), SkillStatus::UpToDate);
+
+        // Copies without a marker, or edited but current, are left alone
+        fs::write(&path, SKILL).unwrap();
+        assert_eq!(refresh(&path).unwrap(), None);
+        let edited = format!("{}\nMy notes\n", render());
+        fs::write(&path, &edited).unwrap();
+        assert_eq!(refresh(&path).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), edited);
+
+        let old = render().replace(env!("CARGO_PKG_VERSION"), "0.0.1");
+        fs::write(&path, old).unwrap();
+        assert_eq!(refresh(&path).unwrap(), Some("0.0.1".to_string()));
+        assert_eq!(install(&path).unwrap(), SkillStatus::UpToDate);
+
+        assert!(uninstall(&path).unwrap());
+        assert!(!path.parent().unwrap().exists());
+        assert!(!uninstall(&path).unwrap());
+    }
+}
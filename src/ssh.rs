@@ -1,12 +1,14 @@
 //! SSH operations for cloud-agent
 //!
 //! This module handles SSH connections, file transfers, and remote command execution.
+//! VMs created with `--network-mode iap` have no external IP, so connections
+//! go through `gcloud compute start-iap-tunnel` as the SSH proxy command.
 
 use anyhow::Result;
 use std::path::Path;
 use std::process::Command;
 
-use crate::config::Config;
+use crate::config::{Config, NetworkMode};
 use crate::error::CloudAgentError;
 use crate::utils;
 
@@ -22,24 +24,63 @@ impl SshClient {
         Self { config, vm_ip }
     }
 
-    /// Execute a command on the VM via SSH
-    pub fn execute(&self, command: &str) -> Result<String> {
+    /// Key and host options shared by every ssh/scp invocation
+    fn connection_args(&self) -> Result<Vec<String>> {
         let ssh_key =
             self.config.ssh_key.as_ref().ok_or_else(|| {
                 CloudAgentError::SshKeyNotFound("No SSH key configured".to_string())
             })?;
 
+        let mut args = vec![
+            "-i".to_string(),
+            ssh_key.to_string_lossy().to_string(),
+            "-o".to_string(),
+            "StrictHostKeyChecking=accept-new".to_string(),
+        ];
+        if self.config.network_mode == NetworkMode::Iap {
+            args.push("-o".to_string());
+            args.push(format!(
+                "ProxyCommand=gcloud compute start-iap-tunnel {} %p --listen-on-stdin \
+                 --project={} --zone={} --verbosity=warning",
+                self.config.vm_name, self.config.project_id, self.config.zone
+            ));
+        }
+        Ok(args)
+    }
+
+    /// Timeout for establishing a connection (IAP tunnels take a few seconds to open)
+    fn connect_timeout(&self) -> &str {
+        match self.config.network_mode {
+            NetworkMode::Public => "ConnectTimeout=10",
+            NetworkMode::Iap => "ConnectTimeout=30",
+        }
+    }
+
+    /// `user@host` for ssh and scp; IAP connections are addressed by VM name
+    /// so known_hosts doesn't depend on the internal IP
+    fn destination(&self) -> String {
+        let host = match self.config.network_mode {
+            NetworkMode::Public => &self.vm_ip,
+            NetworkMode::Iap => &self.config.vm_name,
+        };
+        format!("{}@{}", self.config.ssh_username, host)
+    }
+
+    /// Execute a command on the VM via SSH
+    pub fn execute(&self, command: &str) -> Result<String> {
+        self.run(command, false)
+    }
+
+    /// Execute a command on the VM via SSH with the local SSH agent forwarded
+    pub fn execute_with_agent(&self, command: &str) -> Result<String> {
+        self.run(command, true)
+    }
+
+    fn run(&self, command: &str, forward_agent: bool) -> Result<String> {
         let output = Command::new("ssh")
-            .args([
-                "-i",
-                ssh_key.to_str().unwrap(),
-                "-o",
-                "StrictHostKeyChecking=accept-new",
-                "-o",
-                "ConnectTimeout=10",
-                &format!("{}@{}", self.config.ssh_username, self.vm_ip),
-                command,
-            ])
+            .args(if forward_agent { &["-A"][..] } else { &[] })
+            .args(self.connection_args()?)
+            .args(["-o", self.connect_timeout(), &self.destination(), command])
             .output()?;
 
         if !output.status.success() {
@@ -51,24 +92,10 @@ impl SshClient {
     }
 
     /// Execute a command on the VM via SSH (streaming output)
-    #[allow(dead_code)]
     pub fn execute_streaming(&self, command: &str) -> Result<()> {
-        let ssh_key =
-            self.config.ssh_key.as_ref().ok_or_else(|| {
-                CloudAgentError::SshKeyNotFound("No SSH key configured".to_string())
-            })?;
-
         let status = Command::new("ssh")
-            .args([
-                "-i",
-                ssh_key.to_str().unwrap(),
-                "-o",
-                "StrictHostKeyChecking=accept-new",
-                "-o",
-                "ConnectTimeout=10",
-                &format!("{}@{}", self.config.ssh_username, self.vm_ip),
-                command,
-            ])
+            .args(self.connection_args()?)
+            .args(["-o", self.connect_timeout(), &self.destination(), command])
             .status()?;
 
         if !status.success() {
@@ -84,24 +111,13 @@ impl SshClient {
 
     /// Copy a file to the VM
     pub fn copy_to_vm(&self, local_path: &Path, remote_path: &str) -> Result<()> {
-        let ssh_key =
-            self.config.ssh_key.as_ref().ok_or_else(|| {
-                CloudAgentError::SshKeyNotFound("No SSH key configured".to_string())
-            })?;
-
         let status = Command::new("scp")
+            .args(self.connection_args()?)
             .args([
-                "-i",
-                ssh_key.to_str().unwrap(),
-                "-o",
-                "StrictHostKeyChecking=accept-new",
                 "-o",
-                "ConnectTimeout=10",
+                self.connect_timeout(),
                 local_path.to_str().unwrap(),
-                &format!(
-                    "{}@{}:{}",
-                    self.config.ssh_username, self.vm_ip, remote_path
-                ),
+                &format!("{}:{}", self.destination(), remote_path),
             ])
             .status()?;
 
@@ -113,25 +129,13 @@ impl SshClient {
     }
 
     /// Copy a file from the VM
-    #[allow(dead_code)]
     pub fn copy_from_vm(&self, remote_path: &str, local_path: &Path) -> Result<()> {
-        let ssh_key =
-            self.config.ssh_key.as_ref().ok_or_else(|| {
-                CloudAgentError::SshKeyNotFound("No SSH key configured".to_string())
-            })?;
-
         let status = Command::new("scp")
+            .args(self.connection_args()?)
             .args([
-                "-i",
-                ssh_key.to_str().unwrap(),
-                "-o",
-                "StrictHostKeyChecking=accept-new",
                 "-o",
-                "ConnectTimeout=10",
-                &format!(
-                    "{}@{}:{}",
-                    self.config.ssh_username, self.vm_ip, remote_path
-                ),
+                self.connect_timeout(),
+                &format!("{}:{}", self.destination(), remote_path),
                 local_path.to_str().unwrap(),
             ])
             .status()?;
@@ -157,12 +161,9 @@ impl SshClient {
         utils::log(&format!("Using SSH key: {}", ssh_key.display()));
 
         let status = Command::new("ssh")
+            .args(self.connection_args()?)
             .args([
-                "-i",
-                ssh_key.to_str().unwrap(),
-                "-o",
-                "StrictHostKeyChecking=accept-new",
-                &format!("{}@{}", self.config.ssh_username, self.vm_ip),
+                &self.destination(),
                 "-t",
                 "tmux attach-session 2>/dev/null || tmux new-session",
             ])
@@ -177,27 +178,15 @@ impl SshClient {
 
     /// Copy files with 'vm:' prefix support
     pub fn scp_with_prefix(&self, src: &str, dst: &str) -> Result<()> {
-        let ssh_key =
-            self.config.ssh_key.as_ref().ok_or_else(|| {
-                CloudAgentError::SshKeyNotFound("No SSH key configured".to_string())
-            })?;
-
-        // Replace 'vm:' prefix with user@ip:
-        let remote_prefix = format!("{}@{}:", self.config.ssh_username, self.vm_ip);
+        // Replace 'vm:' prefix with user@host:
+        let remote_prefix = format!("{}:", self.destination());
         let src_resolved = src.replace("vm:", &remote_prefix);
         let dst_resolved = dst.replace("vm:", &remote_prefix);
 
         utils::log("Copying files...");
         let status = Command::new("scp")
-            .args([
-                "-i",
-                ssh_key.to_str().unwrap(),
-                "-o",
-                "StrictHostKeyChecking=accept-new",
-                "-r",
-                &src_resolved,
-                &dst_resolved,
-            ])
+            .args(self.connection_args()?)
+            .args(["-r", &src_resolved, &dst_resolved])
             .status()?;
 
         if !status.success() {
@@ -0,0 +1,209 @@
+//! Local state for cloud-agent VMs
+//!
+//! Some resources created during deploy live outside terraform (for example
+//! GitHub deploy keys). We record them per VM under
+//! `~/.config/cloud-agent/vms/<vm_name>.json` so `ca terminate` can clean
+//! them up. The same file tracks the addresses in the VM's SSH allowlist and
+//! the settings the VM was created with.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::NetworkMode;
+use crate::egress::EgressMode;
+
+/// A deploy key registered on a GitHub repository
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeployKeyRecord {
+    /// Repository owner
+    pub owner: String,
+
+    /// Repository name
+    pub repo: String,
+
+    /// Deploy key ID returned by the GitHub API
+    pub id: u64,
+}
+
+/// How long an address stays in the SSH allowlist
+pub const ALLOWED_IP_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// An address allowed to SSH to the VM
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowedIpRecord {
+    /// Address in CIDR notation
+    pub cidr: String,
+
+    /// Unix timestamp when the address was last allowed
+    pub added_at: i64,
+
+    /// Unix timestamp after which the address is dropped
+    pub expires_at: i64,
+}
+
+/// Persisted state for a single VM
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VmState {
+    /// Deploy keys registered for this VM
+    pub deploy_keys: Vec<DeployKeyRecord>,
+
+    /// Addresses in the SSH firewall rule
+    pub allowed_ips: Vec<AllowedIpRecord>,
+
+    /// Network mode the VM was created with, so later commands reach it
+    /// without `--network-mode`
+    pub network_mode: Option<NetworkMode>,
+
+    /// Egress mode last applied to the VM
+    pub egress: Option<EgressMode>,
+
+    /// Extra egress domains last given with `--egress-allow`
+    pub egress_allow: Vec<String>,
+
+    /// The VM has a reserved external address (`--static-ip`)
+    pub static_ip: bool,
+
+    /// Cloud DNS zone holding the VM's record (`--dns-zone`)
+    pub dns_zone: Option<String>,
+}
+
+impl VmState {
+    /// Load the state for `vm_name` (empty if none has been saved)
+    pub fn load(vm_name: &str) -> Result<Self> {
+        let Some(path) = state_path(vm_name) else {
+            return Ok(Self::default());
+        };
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Allow `cidr` (refreshing its expiry if already allowed) and drop
+    /// expired entries
+    pub fn allow_ip(&mut self, cidr: &str, now: i64) {
+        self.allowed_ips
+            .retain(|e| e.expires_at > now && e.cidr != cidr);
+        self.allowed_ips.push(AllowedIpRecord {
+            cidr: cidr.to_string(),
+            added_at: now,
+            expires_at: now + ALLOWED_IP_TTL_SECS,
+        });
+    }
+
+    /// Unexpired allowlist entries
+    pub fn active_allowed_ips(&self, now: i64) -> Vec<&AllowedIpRecord> {
+        self.allowed_ips
+            .iter()
+            .filter(|e| e.expires_at > now)
+            .collect()
+    }
+
+    /// State once the VM is deleted: only deploy keys that could not be
+    /// removed yet are kept, so a new VM with the same name starts afresh
+    pub fn forget_vm(self) -> Self {
+        Self {
+            deploy_keys: self.deploy_keys,
+            ..Self::default()
+        }
+    }
+
+    /// Whether there is nothing worth saving
+    pub fn is_empty(&self) -> bool {
+        self.deploy_keys.is_empty()
+            && self.allowed_ips.is_empty()
+            && self.network_mode.is_none()
+            && self.egress.is_none()
+            && self.egress_allow.is_empty()
+            && !self.static_ip
+            && self.dns_zone.is_none()
+    }
+
+    /// Delete the state file for `vm_name`
+    pub fn remove(vm_name: &str) -> Result<()> {
+        if let Some(path) = state_path(vm_name).filter(|p| p.exists()) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Save the state for `vm_name`
+    pub fn save(&self, vm_name: &str) -> Result<()> {
+        let path = state_path(vm_name)
+            .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Path of the state file for `vm_name`
+fn state_path(vm_name: &str) -> Option<PathBuf> {
+    dirs::home_dir().map(|h| {
+        h.join(".config/cloud-agent/vms")
+            .join(format!("{}.json", vm_name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_ip_expiry() {
+        let mut state = VmState::default();
+        state.allow_ip("1.1.1.1/32", 0);
+        state.allow_ip("2.2.2.2/32", 100);
+        state.allow_ip("1.1.1.1/32", 200);
+
+        let active = state.active_allowed_ips(300);
+        assert_eq!(active.len(), 2);
+        assert_eq!(active[1].cidr, "1.1.1.1/32");
+        assert_eq!(active[1].expires_at, 200 + ALLOWED_IP_TTL_SECS);
+
+        // 2.2.2.2 expires first and is dropped on the next update
+        let cidrs: Vec<_> = state
+            .active_allowed_ips(150 + ALLOWED_IP_TTL_SECS)
+            .iter()
+            .map(|e| e.cidr.as_str())
+            .collect();
+        assert_eq!(cidrs, vec!["1.1.1.1/32"]);
+        state.allow_ip("3.3.3.3/32", 150 + ALLOWED_IP_TTL_SECS);
+        assert_eq!(state.allowed_ips.len(), 2);
+    }
+
+    #[test]
+    fn test_forget_vm() {
+        let mut state = VmState {
+            network_mode: Some(NetworkMode::Iap),
+            egress: Some(EgressMode::Restricted),
+            egress_allow: vec!["example.com".to_string()],
+            static_ip: true,
+            dns_zone: Some("agents".to_string()),
+            ..VmState::default()
+        };
+        state.allow_ip("1.1.1.1/32", 0);
+        assert!(state.clone().forget_vm().is_empty());
+
+        state.deploy_keys.push(DeployKeyRecord {
+            owner: "org".to_string(),
+            repo: "api".to_string(),
+            id: 7,
+        });
+        let forgotten = state.forget_vm();
+        assert_eq!(forgotten.deploy_keys.len(), 1);
+        assert!(forgotten.allowed_ips.is_empty());
+        assert_eq!(forgotten.network_mode, None);
+        assert_eq!(forgotten.egress, None);
+        assert_eq!(forgotten.dns_zone, None);
+    }
+}
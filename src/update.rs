@@ -0,0 +1,509 @@
+//! Self-update from GitHub releases
+//!
+//! `ca self-update` downloads the release tarball for this platform (built
+//! by the release-please workflow), checks it against the published SHA-256
+//! and atomically replaces the running binary. Other commands run from a
+//! terminal print a notice when a newer release exists, checking GitHub at
+//! most once a day. Releases cover Linux x86_64 and macOS; elsewhere (such
+//! as Linux aarch64) `ca` is updated by building from source.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::error::CloudAgentError;
+use crate::utils;
+
+/// Repository publishing `ca` releases
+pub const RELEASES_REPO: &str = "jeremyplichta/cloud-agent";
+
+/// Env var overriding the releases API URL (mirrors and tests)
+pub const RELEASES_API_ENV: &str = "CLOUD_AGENT_RELEASES_API";
+
+/// Env var disabling the new-version notice
+pub const NO_UPDATE_CHECK_ENV: &str = "CLOUD_AGENT_NO_UPDATE_CHECK";
+
+/// How long a cached latest-version lookup is trusted
+const CHECK_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// Timeout for the background version check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Version of the running binary
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A GitHub release
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<ReleaseAsset>,
+}
+
+/// A file attached to a release
+#[derive(Debug, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// Release version without the leading `v`
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    fn asset(&self, name: &str) -> Option<&ReleaseAsset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+}
+
+/// Release tarball name for `os`/`arch` (as in `std::env::consts`).
+/// Linux uses the static musl build so it runs on any distribution.
+pub fn asset_name(os: &str, arch: &str) -> Option<&'static str> {
+    match (os, arch) {
+        ("linux", "x86_64") => Some("ca-linux-x86_64-musl.tar.gz"),
+        ("macos", "x86_64") => Some("ca-macos-x86_64.tar.gz"),
+        ("macos", "aarch64") => Some("ca-macos-aarch64.tar.gz"),
+        _ => None,
+    }
+}
+
+/// Release tarball name for the running platform, or an error explaining
+/// that self-update isn't supported on it
+fn platform_asset() -> Result<&'static str> {
+    let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+    asset_name(os, arch).ok_or_else(|| {
+        CloudAgentError::ConfigError(format!(
+            "Self-update is not supported on {}-{} (no release binary is published); \
+             update with cargo install cloud-agent",
+            os, arch
+        ))
+        .into()
+    })
+}
+
+/// Whether version `candidate` is newer than `current` (dotted numbers,
+/// pre-release suffixes ignored)
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    }
+    parts(candidate) > parts(current)
+}
+
+/// Check `data` against a `sha256sum`-style checksum file
+pub fn verify_checksum(data: &[u8], checksum_file: &str) -> Result<()> {
+    let expected = checksum_file
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let actual = format!("{:x}", Sha256::digest(data));
+
+    if expected != actual {
+        return Err(CloudAgentError::ConfigError(format!(
+            "Checksum mismatch for downloaded release (expected {}, got {})",
+            expected, actual
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Minimal client for the GitHub releases API
+pub struct ReleaseClient {
+    api_url: String,
+    http: reqwest::Client,
+}
+
+impl ReleaseClient {
+    /// Client for `api_url`, usually [`default_api_url`]
+    pub fn new(api_url: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    async fn get(&self, url: &str, timeout: Option<Duration>) -> Result<reqwest::Response> {
+        let mut request = self
+            .http
+            .get(url)
+            .header("Accept", "application/vnd.github+json")
+            .header(
+                "User-Agent",
+                concat!("cloud-agent/", env!("CARGO_PKG_VERSION")),
+            );
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CloudAgentError::GitHubApi(format!("{}: {}", status, url)).into());
+        }
+        Ok(response)
+    }
+
+    /// Fetch the latest release, or the release for `version`
+    pub async fn release(&self, version: Option<&str>) -> Result<Release> {
+        let url = match version {
+            Some(v) => format!(
+                "{}/repos/{}/releases/tags/v{}",
+                self.api_url,
+                RELEASES_REPO,
+                v.trim_start_matches('v')
+            ),
+            None => format!("{}/repos/{}/releases/latest", self.api_url, RELEASES_REPO),
+        };
+        Ok(self.get(&url, None).await?.json().await?)
+    }
+
+    /// Latest released version, failing fast when GitHub is slow
+    async fn latest_version(&self) -> Result<String> {
+        let url = format!("{}/repos/{}/releases/latest", self.api_url, RELEASES_REPO);
+        let release: Release = self.get(&url, Some(CHECK_TIMEOUT)).await?.json().await?;
+        Ok(release.version().to_string())
+    }
+
+    async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        Ok(self.get(url, None).await?.bytes().await?.to_vec())
+    }
+}
+
+/// Releases API URL, honouring [`RELEASES_API_ENV`]
+pub fn default_api_url() -> String {
+    std::env::var(RELEASES_API_ENV).unwrap_or_else(|_| crate::github::DEFAULT_API_URL.to_string())
+}
+
+/// Download and verify the binary for this platform from `release` into
+/// `dir`, returning the path of the extracted `ca`
+pub async fn download_binary(
+    client: &ReleaseClient,
+    release: &Release,
+    dir: &Path,
+) -> Result<PathBuf> {
+    let name = platform_asset()?;
+    let missing = |file: String| {
+        CloudAgentError::ConfigError(format!("Release {} has no {}", release.tag_name, file))
+    };
+
+    let asset = release
+        .asset(name)
+        .ok_or_else(|| missing(name.to_string()))?;
+    let checksum_name = format!("{}.sha256", name);
+    let checksum = release
+        .asset(&checksum_name)
+        .ok_or_else(|| missing(checksum_name.clone()))?;
+
+    let archive = client.download(&asset.browser_download_url).await?;
+    let checksum = client.download(&checksum.browser_download_url).await?;
+    verify_checksum(&archive, &String::from_utf8_lossy(&checksum))?;
+
+    let archive_path = dir.join(name);
+    fs::write(&archive_path, &archive)?;
+    let status = Command::new("tar")
+        .arg("xzf")
+        .arg(&archive_path)
+        .arg("-C")
+        .arg(dir)
+        .status()?;
+    let binary = dir.join("ca");
+    if !status.success() || !binary.is_file() {
+        return Err(anyhow::anyhow!("Failed to extract {}", name));
+    }
+    Ok(binary)
+}
+
+/// Atomically replace `target` with `new_binary`.
+///
+/// The new file is written next to `target` and renamed over it, so the
+/// running process keeps its old inode and `target` is never half-written.
+pub fn replace_binary(new_binary: &Path, target: &Path) -> Result<()> {
+    let dir = target
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid binary path {}", target.display()))?;
+    let staged = dir.join(format!(".ca-update-{}", std::process::id()));
+
+    fs::copy(new_binary, &staged)
+        .with_context(|| format!("Can't write to {} (try with sudo)", dir.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    if let Err(e) = fs::rename(&staged, target) {
+        let _ = fs::remove_file(&staged);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// `ca self-update [--version X]`
+pub async fn self_update(version: Option<&str>) -> Result<()> {
+    platform_asset()?;
+    let client = ReleaseClient::new(&default_api_url());
+    let release = client.release(version).await?;
+
+    if version.is_none() && !is_newer(release.version(), CURRENT_VERSION) {
+        utils::log_success(&format!("ca {} is up to date", CURRENT_VERSION));
+        return Ok(());
+    }
+    if release.version() == CURRENT_VERSION {
+        utils::log_success(&format!("ca {} is already installed", CURRENT_VERSION));
+        return Ok(());
+    }
+
+    let target = std::env::current_exe()?.canonicalize()?;
+    utils::log(&format!(
+        "Updating {} from {} to {}...",
+        target.display(),
+        CURRENT_VERSION,
+        release.version()
+    ));
+
+    let dir = tempfile::tempdir()?;
+    let binary = download_binary(&client, &release, dir.path()).await?;
+    replace_binary(&binary, &target)?;
+
+    // Don't announce a version we just installed
+    write_cache(&UpdateCache {
+        checked_at: chrono::Utc::now().timestamp(),
+        latest: release.version().to_string(),
+    });
+    utils::log_success(&format!("Updated ca to {}", release.version()));
+    Ok(())
+}
+
+/// Cached result of the last version check
+#[derive(Debug, Serialize, Deserialize)]
+struct UpdateCache {
+    checked_at: i64,
+    latest: String,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|h| h.join(".config/cloud-agent/update-check.json"))
+}
+
+fn read_cache() -> Option<UpdateCache> {
+    serde_json::from_str(&fs::read_to_string(cache_path()?).ok()?).ok()
+}
+
+fn write_cache(cache: &UpdateCache) {
+    if let (Some(path), Ok(json)) = (cache_path(), serde_json::to_string(cache)) {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(path, json);
+    }
+}
+
+/// Print a notice when a newer release exists. Only runs when stdout is a
+/// terminal, checks at most once a day (failed checks included) and never
+/// fails the calling command.
+pub async fn notify_if_outdated() {
+    if std::env::var_os(NO_UPDATE_CHECK_ENV).is_some() || !std::io::stdout().is_terminal() {
+        return;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let latest = match read_cache() {
+        Some(cache) if now - cache.checked_at < CHECK_INTERVAL_SECS => cache.latest,
+        cached => {
+            let client = ReleaseClient::new(&default_api_url());
+            match client.latest_version().await {
+                Ok(latest) => {
+                    write_cache(&UpdateCache {
+                        checked_at: now,
+                        latest: latest.clone(),
+                    });
+                    latest
+                }
+                // Keep reporting what we knew, and don't retry until the
+                // interval has passed (an empty version means none is known)
+                Err(_) => {
+                    let latest = cached.map(|cache| cache.latest).unwrap_or_default();
+                    write_cache(&UpdateCache {
+                        checked_at: now,
+                        latest: latest.clone(),
+                    });
+                    if latest.is_empty() {
+                        return;
+                    }
+                    latest
+                }
+            }
+        }
+    };
+
+    if is_newer(&latest, CURRENT_VERSION) {
+        let upgrade = match platform_asset() {
+            Ok(_) => "ca self-update",
+            Err(_) => "cargo install cloud-agent",
+        };
+        utils::log_warning(&format!(
+            "ca {} is available (you have {}). Run '{}' to upgrade.",
+            latest, CURRENT_VERSION, upgrade
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release_json(server: &mockito::ServerGuard, tag: &str) -> String {
+        let name = asset_name(std::env::consts::OS, std::env::consts::ARCH)
+            .unwrap_or("ca-linux-x86_64-musl.tar.gz");
+        serde_json::json!({
+            "tag_name": tag,
+            "assets": [
+                {"name": name, "browser_download_url": format!("{}/dl/ca.tar.gz", server.url())},
+                {"name": format!("{}.sha256", name), "browser_download_url": format!("{}/dl/ca.tar.gz.sha256", server.url())},
+            ]
+        })
+        .to_string()
+    }
+
+    /// A release tarball containing a `ca` script
+    fn tarball() -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ca"), "#!/bin/sh\necho new\n").unwrap();
+        let archive = dir.path().join("ca.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(dir.path())
+            .arg("ca")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::read(archive).unwrap()
+    }
+
+    #[test]
+    fn test_asset_name() {
+        assert_eq!(
+            asset_name("linux", "x86_64"),
+            Some("ca-linux-x86_64-musl.tar.gz")
+        );
+        assert_eq!(
+            asset_name("macos", "aarch64"),
+            Some("ca-macos-aarch64.tar.gz")
+        );
+        assert_eq!(asset_name("windows", "x86_64"), None);
+        assert_eq!(asset_name("linux", "aarch64"), None);
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.9"));
+        assert!(is_newer("v0.10.0", "0.9.0"));
+        assert!(!is_newer("0.1.1", "0.1.1"));
+        assert!(!is_newer("0.1.0", "0.1.1"));
+        assert!(is_newer("1.0.0", "0.1.1-beta"));
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let digest = format!("{:x}", Sha256::digest(b"hello"));
+        assert!(verify_checksum(b"hello", &format!("{}  ca.tar.gz\n", digest)).is_ok());
+        assert!(verify_checksum(b"hello!", &digest).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_release_lookup() {
+        let mut server = mockito::Server::new_async().await;
+        let latest = server
+            .mock("GET", "/repos/jeremyplichta/cloud-agent/releases/latest")
+            .with_body(release_json(&server, "v0.3.0"))
+            .create_async()
+            .await;
+        let tagged = server
+            .mock(
+                "GET",
+                "/repos/jeremyplichta/cloud-agent/releases/tags/v0.2.0",
+            )
+            .with_body(release_json(&server, "v0.2.0"))
+            .create_async()
+            .await;
+
+        let client = ReleaseClient::new(&server.url());
+        assert_eq!(client.release(None).await.unwrap().version(), "0.3.0");
+        assert_eq!(client.latest_version().await.unwrap(), "0.3.0");
+        assert_eq!(
+            client.release(Some("v0.2.0")).await.unwrap().version(),
+            "0.2.0"
+        );
+        latest.expect(2).assert_async().await;
+        tagged.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_binary() {
+        let mut server = mockito::Server::new_async().await;
+        let archive = tarball();
+        let digest = format!("{:x}", Sha256::digest(&archive));
+        server
+            .mock("GET", "/dl/ca.tar.gz")
+            .with_body(archive)
+            .create_async()
+            .await;
+        let checksum = server
+            .mock("GET", "/dl/ca.tar.gz.sha256")
+            .with_body(format!("{}  ca.tar.gz\n", digest))
+            .create_async()
+            .await;
+
+        let client = ReleaseClient::new(&server.url());
+        let release: Release = serde_json::from_str(&release_json(&server, "v0.3.0")).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let binary = download_binary(&client, &release, dir.path())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&binary).unwrap(),
+            "#!/bin/sh\necho new\n"
+        );
+
+        // A tampered checksum is rejected
+        checksum.remove_async().await;
+        server
+            .mock("GET", "/dl/ca.tar.gz.sha256")
+            .with_body(format!("{:x}  ca.tar.gz\n", Sha256::digest(b"other")))
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        assert!(download_binary(&client, &release, dir.path())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_replace_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("ca");
+        let new = dir.path().join("new");
+        fs::write(&target, "old").unwrap();
+        fs::write(&new, "new").unwrap();
+
+        replace_binary(&new, &target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
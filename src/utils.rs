@@ -32,35 +32,6 @@ pub fn log_warning(message: &str) {
     println!("[{}] ⚠️  {}", timestamp, message);
 }
 
-/// Detect public IPv4 address
-pub async fn detect_public_ipv4() -> Result<String> {
-    // Try multiple services for reliability
-    let services = [
-        "https://api.ipify.org",
-        "https://ifconfig.me/ip",
-        "https://icanhazip.com",
-    ];
-
-    for service in &services {
-        if let Ok(response) = reqwest::get(*service).await {
-            if let Ok(ip) = response.text().await {
-                let ip = ip.trim();
-                if !ip.is_empty() && is_valid_ipv4(ip) {
-                    log(&format!("✓ Your IPv4 address: {}", ip));
-                    return Ok(format!("{}/32", ip));
-                }
-            }
-        }
-    }
-
-    Err(CloudAgentError::IpDetectionFailed.into())
-}
-
-/// Check if a string is a valid IPv4 address
-fn is_valid_ipv4(ip: &str) -> bool {
-    ip.split('.').filter_map(|s| s.parse::<u8>().ok()).count() == 4
-}
-
 /// Check if a command exists in PATH
 pub fn command_exists(cmd: &str) -> bool {
     which::which(cmd).is_ok()
@@ -110,6 +81,11 @@ pub fn extract_repo_name(url: &str) -> Result<String> {
     Ok(name.to_string())
 }
 
+/// Quote a string for safe use as a single POSIX shell word
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
 /// Print a fancy header
 pub fn print_header(title: &str) {
     println!("╔══════════════════════════════════════════════════════════════╗");
@@ -122,11 +98,9 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_is_valid_ipv4() {
-        assert!(is_valid_ipv4("192.168.1.1"));
-        assert!(is_valid_ipv4("8.8.8.8"));
-        assert!(!is_valid_ipv4("256.1.1.1"));
-        assert!(!is_valid_ipv4("not.an.ip.address"));
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("repo"), "'repo'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
     }
 
     #[test]
@@ -12,6 +12,22 @@ log "╔════════════════════════
 log "║  🐕 CLOUD AGENT STARTUP - INITIALIZING                      ║"
 log "╚══════════════════════════════════════════════════════════════╝"
 
+# Restricted egress (ca --egress restricted) is enforced before anything
+# else runs, on every boot. Later changes arrive through instance metadata.
+cat > /usr/local/sbin/cloud-agent-egress << 'CLOUD_AGENT_EGRESS_SCRIPT'
+${egress_script}
+CLOUD_AGENT_EGRESS_SCRIPT
+chmod 755 /usr/local/sbin/cloud-agent-egress
+EGRESS_MODE=$(curl -sf -H "Metadata-Flavor: Google" \
+    "http://metadata.google.internal/computeMetadata/v1/instance/attributes/cloud-agent-egress" || true)
+if [ "$EGRESS_MODE" = "restricted" ]; then
+    log "Restricting egress to the allowlisted domains..."
+    if [ -n "${ssh_username}" ] && ! id "${ssh_username}" &>/dev/null; then
+        useradd -m -s /bin/bash "${ssh_username}"
+    fi
+    /usr/local/sbin/cloud-agent-egress restricted "${ssh_username}"
+fi
+
 # Update system
 log "Updating system packages..."
 apt-get update -qq
@@ -19,15 +35,20 @@ apt-get install -y -qq \
     curl \
     wget \
     git \
+    git-lfs \
     tmux \
     vim \
     jq \
     python3 \
     python3-pip \
+    pipx \
     unzip \
     tar \
     gzip
 
+# Enable Git LFS smudge/clean filters for all users
+git lfs install --system
+
 # Install gcloud SDK (if not already installed)
 log "Installing Google Cloud SDK..."
 if ! command -v gcloud &> /dev/null; then
@@ -56,15 +77,21 @@ npm install -g @anthropic-ai/claude-code
 log "Installing Codex CLI (OpenAI)..."
 npm install -g @openai/codex
 
-# Configure kubectl for the cluster (if cluster_name is provided)
-if [ -n "${cluster_name}" ] && [ "${cluster_name}" != "" ]; then
-    log "Configuring kubectl for cluster ${cluster_name}..."
-    gcloud container clusters get-credentials ${cluster_name} \
-        --zone=${cluster_zone} \
-        --project=${project_id}
-else
-    log "No GKE cluster specified, skipping kubectl configuration"
-fi
+log "Installing Gemini CLI (Google)..."
+npm install -g @google/gemini-cli
+
+# Aider is a Python tool; install it system-wide with pipx
+log "Installing Aider..."
+PIPX_HOME=/opt/pipx PIPX_BIN_DIR=/usr/local/bin pipx install aider-chat
+
+# GKE auth plugin for kubectl; cluster credentials are fetched as the SSH
+# user during deploy (ca --cluster) and by ca kube refresh
+log "Installing GKE auth plugin..."
+curl -fsSL https://packages.cloud.google.com/apt/doc/apt-key.gpg | gpg --dearmor --yes -o /usr/share/keyrings/cloud.google.gpg
+echo "deb [signed-by=/usr/share/keyrings/cloud.google.gpg] https://packages.cloud.google.com/apt cloud-sdk main" \
+    > /etc/apt/sources.list.d/google-cloud-sdk.list
+apt-get update -qq
+apt-get install -y -qq google-cloud-cli-gke-gcloud-auth-plugin || log "GKE auth plugin not installed"
 
 # Create workspace directory (world-writable so any user can clone repos)
 log "Creating workspace directory..."
@@ -116,6 +143,10 @@ if [ -n "${ssh_username}" ] && [ "${ssh_username}" != "" ]; then
 
     # Give the user access to workspace
     chown -R "${ssh_username}:${ssh_username}" /workspace
+
+    # Keep the user's services (the task queue) running without a login,
+    # which needs root once restricted egress has revoked sudo
+    loginctl enable-linger "${ssh_username}"
 fi
 
 # Restart SSH service to apply changes
@@ -133,9 +164,11 @@ This VM is ready to run AI coding agents.
 
 ## Available Agents
 
+- **Aider**: \`aider\`
 - **Auggie** (Augment Code): \`auggie\`
 - **Claude Code** (Anthropic): \`claude\`
 - **Codex** (OpenAI): \`codex\`
+- **Gemini CLI** (Google): \`gemini\`
 
 ## Quick Start
 
@@ -154,7 +187,7 @@ This VM is ready to run AI coding agents.
 ## VM Info
 
 - **Project:** ${project_id}
-- **Zone:** ${cluster_zone}
+- **Zone:** ${zone}
 
 ## Files
 
@@ -7,9 +7,16 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
 
+/// `ca` without the new-version check, which would query GitHub
+fn ca() -> Command {
+    let mut cmd = Command::cargo_bin("ca").unwrap();
+    cmd.env("CLOUD_AGENT_NO_UPDATE_CHECK", "1");
+    cmd
+}
+
 #[test]
 fn test_help_command() {
-    let mut cmd = Command::cargo_bin("ca").unwrap();
+    let mut cmd = ca();
     cmd.arg("--help");
 
     cmd.assert()
@@ -20,7 +27,7 @@ fn test_help_command() {
 
 #[test]
 fn test_version_command() {
-    let mut cmd = Command::cargo_bin("ca").unwrap();
+    let mut cmd = ca();
     cmd.arg("--version");
 
     cmd.assert()
@@ -32,7 +39,7 @@ fn test_version_command() {
 fn test_list_command_structure() {
     // This test just verifies the command structure is valid
     // It may fail if gcloud is not configured, which is expected
-    let mut cmd = Command::cargo_bin("ca").unwrap();
+    let mut cmd = ca();
     cmd.arg("list");
 
     // We don't assert success because it requires gcloud to be configured
@@ -42,7 +49,7 @@ fn test_list_command_structure() {
 
 #[test]
 fn test_invalid_agent() {
-    let mut cmd = Command::cargo_bin("ca").unwrap();
+    let mut cmd = ca();
     cmd.args([
         "--agent",
         "invalid-agent",
@@ -53,3 +60,62 @@ fn test_invalid_agent() {
     // But we need gcloud configured to get that far
     let _ = cmd.output();
 }
+
+#[test]
+fn test_permissions_list() {
+    let mut cmd = ca();
+    cmd.args(["permissions", "list"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("roles/container.admin"))
+        .stdout(predicate::str::contains("storage:BUCKET"));
+}
+
+#[test]
+fn test_self_update_up_to_date() {
+    let mut server = mockito::Server::new();
+    let release = server
+        .mock("GET", "/repos/jeremyplichta/cloud-agent/releases/latest")
+        .with_body(format!(
+            r#"{{"tag_name": "v{}", "assets": []}}"#,
+            env!("CARGO_PKG_VERSION")
+        ))
+        .create();
+
+    let mut cmd = ca();
+    cmd.arg("self-update")
+        .env("CLOUD_AGENT_RELEASES_API", server.url());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("is up to date"));
+    release.assert();
+}
+
+#[test]
+fn test_run_duplicate_agents() {
+    let mut cmd = ca();
+    cmd.args([
+        "run",
+        "--repo",
+        "api",
+        "--agents",
+        "claude,codex,claude",
+        "Fix it",
+    ]);
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "--agents lists 'claude' more than once",
+    ));
+}
+
+#[test]
+fn test_zero_clone_depth() {
+    let mut cmd = ca();
+    cmd.args(["--depth", "0", "https://github.com/test/repo.git"]);
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "invalid value '0' for '--depth <DEPTH>'",
+    ));
+}
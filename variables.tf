@@ -37,32 +37,86 @@ variable "skip_deletion" {
   default     = "yes"
 }
 
-variable "cluster_name" {
-  description = "Name of the GKE cluster to configure kubectl for (optional)"
+variable "iam_roles" {
+  description = "IAM roles to grant the VM's service account on the project, resolved from --permissions by ca. The service account is only created when a role or grant is given."
+  type        = list(string)
+  default     = []
+}
+
+variable "bucket_iam" {
+  description = "Roles to grant the VM's service account on individual Cloud Storage buckets"
+  type = list(object({
+    bucket = string
+    role   = string
+  }))
+  default = []
+}
+
+variable "secret_iam" {
+  description = "Roles to grant the VM's service account on individual Secret Manager secrets"
+  type = list(object({
+    secret = string
+    role   = string
+  }))
+  default = []
+}
+
+variable "network" {
+  description = "Existing VPC network to attach the VM to. Empty creates a dedicated network and subnet for this VM (default)."
   type        = string
   default     = ""
 }
 
-variable "cluster_zone" {
-  description = "Zone of the GKE cluster (only used if cluster_name is set)"
+variable "subnet" {
+  description = "Subnet of the existing network in the VM's region (only used with network; may be empty for auto-mode networks)"
   type        = string
-  default     = "us-central1-a"
+  default     = ""
+}
+
+variable "network_mode" {
+  description = "How the VM is reached: 'public' (ephemeral external IP, SSH from allowed_ips) or 'iap' (no external IP, SSH through IAP TCP forwarding, egress through Cloud NAT)"
+  type        = string
+  default     = "public"
+
+  validation {
+    condition     = contains(["public", "iap"], var.network_mode)
+    error_message = "network_mode must be 'public' or 'iap'."
+  }
 }
 
-variable "permissions" {
-  description = "List of permission shortcuts to grant to the VM's service account. Empty means no service account (default). Options: admin, compute, gke, storage, network, bigquery/bq, iam, logging, pubsub, sql, secrets, dns, run, functions"
+variable "static_ip" {
+  description = "Reserve a static external IP for the VM so its address survives stop/start (public network_mode only)"
+  type        = bool
+  default     = false
+}
+
+variable "dns_zone" {
+  description = "Cloud DNS managed zone to add an A record '<vm_name>.<zone domain>' for the static IP to. Implies static_ip. Leave empty for no record."
+  type        = string
+  default     = ""
+}
+
+variable "egress_mode" {
+  description = "Outbound access from the VM: 'open' (all traffic) or 'restricted' (ports 22, 80 and 443 only; the startup script installs a proxy on the VM that enforces egress_allowlist)"
+  type        = string
+  default     = "open"
+
+  validation {
+    condition     = contains(["open", "restricted"], var.egress_mode)
+    error_message = "egress_mode must be 'open' or 'restricted'."
+  }
+}
+
+variable "egress_allowlist" {
+  description = "Domains the egress proxy forwards to with restricted egress ('.example.com' includes subdomains)"
   type        = list(string)
   default     = []
 }
 
 variable "allowed_ips" {
-  description = "List of IP addresses allowed to connect to the VM via SSH. Each IP should be in CIDR notation (e.g., '1.2.3.4/32'). Terraform will replace any existing firewall rules with these. Required - cannot be empty."
+  description = "List of IP addresses allowed to connect to the VM via SSH. Each IP should be in CIDR notation (e.g., '1.2.3.4/32'). Terraform will replace any existing firewall rules with these. Required unless network_mode is 'iap'."
   type        = list(string)
-
-  validation {
-    condition     = length(var.allowed_ips) > 0
-    error_message = "allowed_ips cannot be empty. At least one IP address must be specified for SSH access."
-  }
+  default     = []
 }
 
 variable "ssh_username" {